@@ -0,0 +1,29 @@
+//! Calculates the CRC-32 of the embedded kernel image, so the bootloader can check the image it
+//! finds in memory against the one it was built with.
+
+use std::{env, fs, path::Path};
+
+/// Reversed representation of the CRC-32 polynomial, as in `stellaros::common::checksum`.
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=stellaros");
+    let kernel = fs::read("stellaros").expect("Kernel image `stellaros` not found");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("kernel_crc32.rs");
+    fs::write(out, format!("{:#010x}", crc32(&kernel))).unwrap();
+}
@@ -1,12 +1,46 @@
 use core::fmt;
 use core::fmt::Write;
+use stellaros::{
+    common::volatile::{Mmio, MmioBlock},
+    cpu::{self, smp::SingleCoreCell},
+};
 
-pub const UART0: *mut u8 = 0x09000000 as *mut u8;
+/// Physical address of the PL011, which bigbang accesses identity mapped.
+pub const UART0_BASE: usize = 0x09000000;
+
+const UART0: MmioBlock = unsafe { MmioBlock::new(UART0_BASE) };
+
+/// PL011 data register.
+fn uartdr() -> Mmio<u8> {
+    UART0.reg(0x0)
+}
+
+/// PL011 flag register.
+fn uartfr() -> Mmio<u32> {
+    UART0.reg(0x18)
+}
 
 #[inline(always)]
 pub unsafe fn raw_print(s: &str) {
+    let uartdr = uartdr();
     for byte in s.as_bytes() {
-        core::ptr::write_volatile(UART0, *byte);
+        uartdr.write(*byte);
+    }
+}
+
+/// UARTFR: the UART is busy transmitting data.
+const UARTFR_BUSY: u32 = 1 << 3;
+/// UARTFR: the transmit FIFO is empty.
+const UARTFR_TXFE: u32 = 1 << 7;
+
+/// Spin until every byte written so far has left the UART.
+pub fn flush() {
+    loop {
+        let flags = uartfr().read();
+        if flags & UARTFR_TXFE != 0 && flags & UARTFR_BUSY == 0 {
+            break;
+        }
+        cpu::spin_hint();
     }
 }
 
@@ -21,10 +55,10 @@ impl Write for Console {
     }
 }
 
-static mut CONSOLE: Console = Console;
+static CONSOLE: SingleCoreCell<Console> = SingleCoreCell::new(Console);
 #[inline(always)]
 pub fn console() -> &'static mut dyn Write {
-    unsafe { &mut CONSOLE }
+    unsafe { CONSOLE.get_mut() }
 }
 
 
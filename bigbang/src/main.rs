@@ -8,24 +8,26 @@
 #![feature(const_fn_fn_ptr_basics)]
 #![feature(format_args_nl)]
 
-use core::{cell::UnsafeCell, mem::ManuallyDrop};
+use core::cell::UnsafeCell;
 
 use cortex_a::regs::*;
-use debug::UART0;
-use elfloader::{ElfBinary, Flags, LoadableHeaders, Rela, VAddr, P64};
+use debug::UART0_BASE;
+use elfloader::{ElfBinary, Entry, Flags, LoadableHeaders, Rela, VAddr, P64};
 use stellaros::{
     arch::{
-        mmu::{MemoryManagementUnit, MmuReigon},
+        mmu::{MemoryManagementUnit, MmuReigon, TranslationConfig},
         reg::cpacr_el1::CPACR_EL1,
     },
-    common::align_up,
+    common::{align_up, checksum, insertion_sort_by_key},
+    cpu::{self, cache, smp::SingleCoreCell},
     memory::{
-        AccessPermissions, Address, AttributeFields, MemAttributes, Page, PageAllocator, Physical,
+        AccessPermissions, Address, AttributeFields, MemAttributes, PageAllocator, Physical,
+        StackMetadataCell, StackPageAllocatorMetadata, StaticTable, Virtual,
     },
 };
 use stellaros::{
-    bsp::config::MmuGranule,
-    memory::{AddressRange, IdentMapper},
+    bsp::config::{self, MmuGranule},
+    memory::{self, AddrMapper, AddressRange, IdentMapper, OffsetMapper},
 };
 
 #[macro_use]
@@ -40,41 +42,113 @@ extern "Rust" {
     static __load_end: UnsafeCell<u8>;
 }
 
-unsafe fn kernel_elf() -> &'static [u8] {
-    include_bytes!("../stellaros")
+static KERNEL_ELF: &[u8] = include_bytes!("../stellaros");
+
+/// CRC-32 of the kernel image, calculated by the build script from the same file.
+const KERNEL_CRC32: u32 = include!(concat!(env!("OUT_DIR"), "/kernel_crc32.rs"));
+
+/// The embedded kernel image, read through a volatile load so the compiler can not see its
+/// contents and fold the checksum away.
+fn kernel_elf() -> &'static [u8] {
+    unsafe { core::ptr::read_volatile(&KERNEL_ELF) }
+}
+
+/// Halt if the embedded kernel image no longer matches the one the bootloader was built with.
+fn verify_kernel_elf() {
+    let crc = checksum::crc32(kernel_elf());
+    if crc != KERNEL_CRC32 {
+        panic!(
+            "Kernel image is corrupted: CRC-32 is {:#010x}, expected {:#010x}",
+            crc, KERNEL_CRC32
+        );
+    }
 }
 
 struct KernelLoader {
     mmu: MemoryManagementUnit<StackPageAllocator>,
 }
 
-struct StackPageAllocator;
+/// The level 0 tables of TTBR0 and TTBR1, part of the image so the first mappings do not depend on
+/// the page pool.
+static ROOT_TABLES: SingleCoreCell<StaticTable<2>> = SingleCoreCell::new(StaticTable::new());
 
-struct StackPageAllocatorMetadata {
-    start: Address<Physical>,
-    end: Address<Physical>,
-    top: Address<Physical>,
+static METADATA: SingleCoreCell<StackPageAllocatorMetadata> =
+    SingleCoreCell::new(StackPageAllocatorMetadata::new());
+
+/// The metadata of the bootloader's page pool.
+struct PoolMetadata;
+
+impl StackMetadataCell for PoolMetadata {
+    fn metadata() -> &'static SingleCoreCell<StackPageAllocatorMetadata> {
+        &METADATA
+    }
 }
 
-static mut METADATA: StackPageAllocatorMetadata = StackPageAllocatorMetadata::new();
+/// Hands out the page pool after the bootloader image, which the kernel later finds reserved in
+/// `BootInfo::used_pages`.
+type StackPageAllocator = memory::StackPageAllocator<PoolMetadata>;
 
 fn flags_to_attributes(flags: &Flags) -> AttributeFields {
+    // Segments are loaded through the identity map of the page pool, so they need no write
+    // permission in the kernel's mapping.
     let ap = if flags.is_write() {
         AccessPermissions::ReadWrite
     } else {
-        // TODO: change back to read
-        AccessPermissions::ReadWrite
+        AccessPermissions::ReadOnly
     };
     let nx = !flags.is_execute();
     AttributeFields {
         mem_attributes: MemAttributes::CacheableDRAM,
         acc_perms: ap,
         execute_never: nx,
+        allow_wx: false,
+        non_global: false,
+        accessed: true,
+    }
+}
+
+/// Maximum number of loadable segments the kernel image may have.
+const MAX_SEGMENTS: usize = 16;
+
+/// Page aligned virtual range covering one or more loadable segments.
+#[derive(Copy, Clone)]
+struct SegmentPages {
+    vrange: AddressRange<Virtual>,
+    attributes: AttributeFields,
+    /// Alignment of the start of `vrange` and of the physical pages backing it, at least a page.
+    alignment: usize,
+}
+
+impl SegmentPages {
+    /// Absorb `other`, granting the union of both permissions to the shared pages.
+    ///
+    /// Mapping fails if that makes the pages writable and executable.
+    fn merge(&mut self, other: &Self) {
+        let last = match (self.vrange.last(), other.vrange.last()) {
+            (Some(last), Some(other_last)) if other_last > last => Some(other_last),
+            (None, other_last) => other_last,
+            (last, _) => last,
+        };
+        self.alignment = self.alignment.max(other.alignment);
+        let start = self.vrange.addr().align_down(self.alignment);
+        self.vrange = match last {
+            Some(last) => AddressRange::new_inclusive(start, last),
+            None => AddressRange::new(start, 0),
+        };
+
+        if let AccessPermissions::ReadWrite = other.attributes.acc_perms {
+            self.attributes.acc_perms = AccessPermissions::ReadWrite;
+        }
+        self.attributes.execute_never &= other.attributes.execute_never;
     }
 }
 
 impl elfloader::ElfLoader for KernelLoader {
+    /// Map pages for all segments. Segments whose page rounded ranges overlap share their pages,
+    /// as linkers pack adjacent sections without page alignment.
     fn allocate(&mut self, load_headers: LoadableHeaders) -> Result<(), &'static str> {
+        let mut segments = [None; MAX_SEGMENTS];
+        let mut count = 0;
         for header in load_headers {
             println!(
                 "allocate base = {:#x} size = {:#x} flags = {}",
@@ -82,15 +156,61 @@ impl elfloader::ElfLoader for KernelLoader {
                 header.mem_size(),
                 header.flags()
             );
-            let aligned_size = align_up(header.mem_size() as usize, MmuGranule::SIZE);
-            let pages_num = aligned_size >> MmuGranule::SHIFT;
-
-            let pages = ManuallyDrop::new(StackPageAllocator::alloc_pages(pages_num)?);
-            self.mmu.ttbl1::<IdentMapper>().map_range_with(
-                pages.range(),
-                AddressRange::new_raw(header.virtual_addr() as usize, aligned_size),
-                flags_to_attributes(&header.flags()),
-            )?;
+            if count == MAX_SEGMENTS {
+                return Err("Too many loadable segments");
+            }
+            // Honor alignments beyond a page, e.g. for segments meant to be block mapped.
+            let alignment = match header.align() as usize {
+                align if align.is_power_of_two() && align > MmuGranule::SIZE => align,
+                _ => MmuGranule::SIZE,
+            };
+            let vrange = AddressRange::<Virtual>::new_raw(
+                header.virtual_addr() as usize,
+                header.mem_size() as usize,
+            )
+            .align_outward(MmuGranule::SIZE);
+            let start = vrange.addr().align_down(alignment);
+            segments[count] = Some(SegmentPages {
+                vrange: AddressRange::new(
+                    start,
+                    vrange.addr().into_usize() - start.into_usize() + vrange.size(),
+                ),
+                attributes: flags_to_attributes(&header.flags()),
+                alignment,
+            });
+            count += 1;
+        }
+
+        let segments = &mut segments[..count];
+        insertion_sort_by_key(segments, |segment| segment.unwrap().vrange);
+
+        // Coalesce overlapping ranges into the first segment of each run.
+        let mut merged = 0;
+        for i in 1..segments.len() {
+            let current = segments[i].unwrap();
+            let last = segments[merged].as_mut().unwrap();
+            if last.vrange.overlaps(&current.vrange) {
+                last.merge(&current);
+            } else {
+                merged += 1;
+                segments[merged] = Some(current);
+            }
+        }
+
+        for segment in segments.iter().take(merged + 1).flatten() {
+            let pages_num = segment.vrange.page_count();
+            // The kernel image stays in place for good.
+            let (base, _) =
+                StackPageAllocator::alloc_aligned_pages(pages_num, segment.alignment)?.leak();
+            let mapped = self.mmu.ttbl1::<IdentMapper>().map_range_with(
+                AddressRange::from_page_count(base, pages_num),
+                segment.vrange,
+                segment.attributes,
+            );
+            if let Err(err) = mapped {
+                println!("Failed to map segment {}: {}", segment.vrange, err);
+                return Err("Failed to map a loadable segment");
+            }
         }
         Ok(())
     }
@@ -99,128 +219,223 @@ impl elfloader::ElfLoader for KernelLoader {
         Err("Relocation not supported")
     }
 
-    fn load(&mut self, _flags: Flags, base: VAddr, region: &[u8]) -> Result<(), &'static str> {
+    fn load(&mut self, flags: Flags, base: VAddr, region: &[u8]) -> Result<(), &'static str> {
         let start = base;
         let end = base + region.len() as u64;
         println!("load region into = {:#x} -- {:#x}", start, end);
+        // The kernel's mapping may be read-only, write through the identity map of the page pool
+        // instead. The pages of a segment are physically contiguous.
+        let paddr = self
+            .mmu
+            .ttbl1::<IdentMapper>()
+            .translate(Address::new(base as usize))
+            .ok_or("Segment not mapped")?;
         unsafe {
-            core::ptr::copy(region.as_ptr(), base as *mut _, region.len());
-        }
-        Ok(())
-    }
-}
-
-impl PageAllocator for StackPageAllocator {
-    /// TODO: Consider SMP data race
-    fn alloc_pages(num: usize) -> Result<Page<Self>, &'static str> {
-        let size = num * MmuGranule::SIZE;
-        // Only one thread is running at the moment.
-        unsafe {
-            assert_ne!(METADATA.start.into_usize(), 0);
-            // println!(
-            //     "Trying to alloc {:#x} size, top {:#x}, end {:#x}",
-            //     size,
-            //     METADATA.top.into_usize(),
-            //     METADATA.end.into_usize()
-            // );
-            if METADATA.top + size > METADATA.end {
-                return Err("Page stack overflow");
-            }
-            let page = Page::from_raw(METADATA.top, num);
-            METADATA.top = METADATA.top + size;
-            Ok(page)
+            core::ptr::copy(region.as_ptr(), paddr.into_usize() as *mut u8, region.len());
         }
-    }
-    unsafe fn free_pages(_pages: &mut Page<Self>) -> Result<(), &'static str> {
-        Err("Page stack free not supported")
-    }
-}
-
-impl StackPageAllocatorMetadata {
-    const fn new() -> Self {
-        Self {
-            start: Address::new(0),
-            end: Address::new(0),
-            top: Address::new(0),
+        if flags.is_execute() {
+            // Make sure the copied code is fetched instead of stale instructions.
+            cache::clean_dcache_range(AddressRange::new_raw(paddr.into_usize(), region.len()));
+            cache::invalidate_icache_all();
         }
-    }
-
-    fn init(&mut self, start: Address<Physical>, num: usize) {
-        self.start = start;
-        self.top = start;
-        self.end = start + num * MmuGranule::SIZE;
-    }
-
-    const fn range(&self) -> AddressRange<Physical> {
-        AddressRange::new_range(self.start, self.end)
+        Ok(())
     }
 }
 
 fn setup_kernel_mmu() -> MemoryManagementUnit<StackPageAllocator> {
-    let mut mmu: MemoryManagementUnit<StackPageAllocator> = unsafe { MemoryManagementUnit::new() };
+    let mut mmu: MemoryManagementUnit<StackPageAllocator> =
+        unsafe { MemoryManagementUnit::try_new() }.expect("MMU configuration not supported");
 
     let range =
         unsafe { AddressRange::new_range(__load_start.get().into(), __load_end.get().into()) };
-    let attributes = AttributeFields {
+    // The bootloader image is not split into page aligned sections, its code and data share
+    // pages.
+    let image_attributes = AttributeFields {
         mem_attributes: MemAttributes::CacheableDRAM,
         acc_perms: AccessPermissions::ReadWrite,
         execute_never: false,
+        allow_wx: true,
+        non_global: false,
+        accessed: true,
     };
+    let pool_attributes = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+        allow_wx: false,
+        non_global: false,
+        accessed: true,
+    };
+    let device_attributes = AttributeFields {
+        mem_attributes: MemAttributes::Device,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+        allow_wx: false,
+        non_global: false,
+        accessed: true,
+    };
+    // The bootloader runs identity mapped, the tables' addresses are physical ones.
+    ROOT_TABLES.with(|tables| unsafe {
+        mmu.ttbl0::<IdentMapper>()
+            .install_root(Address::new(tables.vaddr(0).into_usize()));
+        mmu.ttbl1::<IdentMapper>()
+            .install_root(Address::new(tables.vaddr(1).into_usize()));
+    });
+
     let ttbl0 = mmu.ttbl0::<IdentMapper>();
     ttbl0
-        .map_range(range, attributes)
-        .expect("Failed to map image");
+        .map_range(range, image_attributes)
+        .unwrap_or_else(|err| panic!("Failed to map image: {}", err));
 
     ttbl0
-        .map_range(unsafe { METADATA.range() }, attributes)
-        .expect("Failed to map page pool");
+        .map_range(METADATA.with(|metadata| metadata.range()), pool_attributes)
+        .unwrap_or_else(|err| panic!("Failed to map page pool: {}", err));
 
     ttbl0
         .map_page(
-            UART0.into(),
-            UART0.into(),
-            AttributeFields {
-                mem_attributes: MemAttributes::Device,
-                acc_perms: AccessPermissions::ReadWrite,
-                execute_never: true,
-            },
+            Address::new(UART0_BASE),
+            Address::new(UART0_BASE),
+            device_attributes,
         )
-        .expect("Failed to map UART0");
+        .unwrap_or_else(|err| panic!("Failed to map UART0: {}", err));
+
+    // The kernel runs from the high half only and accesses the devices there, next to the RAM in
+    // the physical memory map.
+    for (name, range) in memory::mmio_regions() {
+        let range = range.align_outward(MmuGranule::SIZE);
+        if let Err(err) = mmu.ttbl1::<IdentMapper>().map_range_with(
+            range,
+            PhysMapMapper::map_to_vrange(range),
+            device_attributes,
+        ) {
+            panic!("Failed to map {} at {}: {}", name, range, err);
+        }
+    }
 
     mmu.enable();
 
     mmu
 }
 
+/// Linear map of all RAM in the TTBR1 half, used by the kernel to access physical memory.
+type PhysMapMapper = OffsetMapper<{ config::PHYS_MAP_OFFSET }>;
+
+/// Map all RAM at `config::PHYS_MAP_OFFSET` for the kernel.
+fn setup_phys_map(mmu: &mut MemoryManagementUnit<StackPageAllocator>) {
+    let ram = AddressRange::<Physical>::new_raw(config::RAM_BASE, config::RAM_SIZE);
+    mmu.ttbl1::<IdentMapper>()
+        .map_range_with(
+            ram,
+            PhysMapMapper::map_to_vrange(ram),
+            AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+                allow_wx: false,
+                non_global: false,
+                accessed: true,
+            },
+        )
+        .unwrap_or_else(|err| panic!("Failed to map physical memory: {}", err));
+}
+
+/// Map the kernel stack, leaving the page below it unmapped as a guard against overflows.
 fn setup_kernel_stack(mmu: &mut MemoryManagementUnit<StackPageAllocator>) -> usize {
-    const STACK_PAGES: usize = 512;
-    let stack_pages = ManuallyDrop::new(
-        StackPageAllocator::alloc_pages(STACK_PAGES).expect("No enough stack size"),
-    );
-    let stack_vrange = AddressRange::new(
-        Address::new(0xFFFF_1000_0000_0000),
-        STACK_PAGES * MmuGranule::SIZE,
+    // The kernel keeps running on this stack, it is never freed.
+    let (stack_base, _) = StackPageAllocator::alloc_pages(config::KERNEL_STACK_PAGES)
+        .expect("No enough stack size")
+        .leak();
+    let stack_vrange = AddressRange::from_page_count(
+        Address::new(config::KERNEL_STACK_VBASE),
+        config::KERNEL_STACK_PAGES,
     );
     mmu.ttbl1::<IdentMapper>()
         .map_range_with(
-            stack_pages.range(),
+            AddressRange::from_page_count(stack_base, config::KERNEL_STACK_PAGES),
             stack_vrange,
             AttributeFields {
                 mem_attributes: MemAttributes::CacheableDRAM,
                 acc_perms: AccessPermissions::ReadWrite,
                 execute_never: true,
+                allow_wx: false,
+                non_global: false,
+                accessed: true,
             },
         )
-        .expect("Failed to map stack");
-    stack_vrange.end().into_usize()
+        .unwrap_or_else(|err| panic!("Failed to map stack: {}", err));
+
+    let guard_page = stack_vrange.addr() - MmuGranule::SIZE;
+    assert!(
+        mmu.ttbl1::<IdentMapper>().translate(guard_page).is_none(),
+        "Stack guard page {} is mapped",
+        guard_page
+    );
+    stack_vrange
+        .end()
+        .expect("Kernel stack reaches the top of the address space")
+        .into_usize()
+}
+
+/// Address and size of the kernel symbol `name`.
+fn find_symbol(binary: &ElfBinary, name: &str) -> Option<(usize, usize)> {
+    let mut symbol = None;
+    binary
+        .for_each_symbol(|entry| {
+            if binary.symbol_name(entry) == name {
+                symbol = Some((entry.value() as usize, entry.size() as usize));
+            }
+        })
+        .expect("Failed to read the kernel symbol table");
+    symbol
+}
+
+/// Halt unless the loaded kernel exports the `BootInfo` version the bootloader writes.
+///
+/// The kernel would only find out after the jump, with a `BootInfo` it can not trust.
+fn check_boot_info_abi(binary: &ElfBinary, mmu: &mut MemoryManagementUnit<StackPageAllocator>) {
+    let symbol = find_symbol(binary, stellaros::boot::BOOT_INFO_ABI_SYMBOL);
+    let (vaddr, size) = symbol.unwrap_or_else(|| {
+        panic!(
+            "Kernel does not export {}, refusing to boot it",
+            stellaros::boot::BOOT_INFO_ABI_SYMBOL
+        )
+    });
+    assert_eq!(
+        size,
+        core::mem::size_of::<u32>(),
+        "Kernel {} has the wrong size",
+        stellaros::boot::BOOT_INFO_ABI_SYMBOL
+    );
+
+    // Loaded through the identity map of the page pool, so the physical address can be read.
+    let paddr = mmu
+        .ttbl1::<IdentMapper>()
+        .translate(Address::new(vaddr))
+        .expect("Kernel boot info version not loaded");
+    let version = unsafe { (paddr.into_usize() as *const u32).read_volatile() };
+    if version != stellaros::boot::BOOT_INFO_VERSION {
+        panic!(
+            "Kernel expects boot info version {}, the bootloader writes version {}",
+            version,
+            stellaros::boot::BOOT_INFO_VERSION
+        );
+    }
 }
 
 fn jump_to_entry(entry_point: usize, stack_end: usize) -> ! {
     println!("Jump to kernel entry");
     unsafe {
-        let boot_info = &mut *(stack_end as *mut stellaros::boot::BootInfo).offset(-1);
-        boot_info.used_pages = AddressRange::new_range(METADATA.start, METADATA.top);
-        let stack_end = boot_info as *const _ as usize;
+        let boot_info = (stack_end as *mut stellaros::boot::BootInfo).offset(-1);
+        // Written as a whole, so no field can be left uninitialized.
+        boot_info.write(stellaros::boot::BootInfo {
+            magic: stellaros::boot::BOOT_INFO_MAGIC,
+            version: stellaros::boot::BOOT_INFO_VERSION,
+            used_pages: METADATA.with(|metadata| metadata.used()),
+            translation: TranslationConfig::current(),
+            ram: AddressRange::new_raw(config::RAM_BASE, config::RAM_SIZE),
+            phys_offset: config::PHYS_MAP_OFFSET,
+        });
+        let stack_end = boot_info as usize;
         asm!(
             "mov SP, x0",
             "br {}",
@@ -233,21 +448,57 @@ fn jump_to_entry(entry_point: usize, stack_end: usize) -> ! {
 
 #[no_mangle]
 unsafe fn main() {
+    // The setup below only touches EL1 registers.
+    cpu::assert_el(1);
     DAIF.write(DAIF::D::Masked + DAIF::A::Masked + DAIF::F::Masked + DAIF::I::Masked);
     CPACR_EL1.write(CPACR_EL1::FPEN::NONE);
 
     stellaros::arch::exception::handling_init();
-    METADATA.init(
-        Address::new(align_up(__load_end.get() as usize, MmuGranule::SIZE)),
-        1024,
-    );
+    METADATA.with(|metadata| {
+        metadata.init(
+            Address::new(align_up(__load_end.get() as usize, MmuGranule::SIZE)),
+            2048,
+        );
+        metadata
+            .reserve(AddressRange::new_range(
+                __load_start.get().into(),
+                __load_end.get().into(),
+            ))
+            .expect("Failed to reserve the bootloader image");
+    });
     let mut mmu = setup_kernel_mmu();
+    setup_phys_map(&mut mmu);
 
     let stack_end = setup_kernel_stack(&mut mmu);
-
+    mmu.debug_summary();
+    #[cfg(feature = "mmu_selftest")]
+    mmu.self_test::<IdentMapper>()
+        .unwrap_or_else(|err| panic!("MMU self test failed: {}", err));
+    #[cfg(all(feature = "mmu_selftest", feature = "alloc_fault_injection"))]
+    mmu.rollback_test::<IdentMapper>()
+        .unwrap_or_else(|err| panic!("MMU rollback test failed: {}", err));
+
+    verify_kernel_elf();
     let binary = ElfBinary::new("test", kernel_elf()).expect("Got proper ELF section");
     let mut loader = KernelLoader { mmu };
     binary.load(&mut loader).expect("Can't load the binary?");
 
-    jump_to_entry(binary.entry_point() as usize, stack_end)
+    check_boot_info_abi(&binary, &mut loader.mmu);
+
+    // The ELF entry is the kernel's own stub for loaders that enter with the MMU off.
+    let (entry_point, _) = find_symbol(&binary, stellaros::boot::KERNEL_ENTRY_SYMBOL)
+        .expect("Kernel entry symbol not found");
+    let entry_point = Address::<Virtual>::new(entry_point);
+    let region = loader.mmu.ttbl1::<IdentMapper>();
+    assert!(
+        region.translate(entry_point).is_some(),
+        "entry point {:#018x} not mapped",
+        entry_point
+    );
+    assert!(
+        region.is_executable(entry_point),
+        "entry point {:#018x} not executable",
+        entry_point
+    );
+    jump_to_entry(entry_point.into_usize(), stack_end)
 }
@@ -1,7 +1,11 @@
 use core::panic::PanicInfo;
+use stellaros::cpu;
 
 #[panic_handler]
 fn on_panic(info: &PanicInfo) -> ! {
     println!("{}", info);
-    loop {}
+    #[cfg(feature = "mmu_trace")]
+    stellaros::mmu::mmu::trace::dump();
+    crate::debug::flush();
+    cpu::end_panic()
 }
\ No newline at end of file
@@ -0,0 +1,33 @@
+//! Memory barriers with the shareability domains the kernel needs.
+
+use cortex_a::barrier;
+
+/// Data synchronization barrier over the full system.
+#[inline(always)]
+pub fn dsb_sy() {
+    unsafe { barrier::dsb(barrier::SY) }
+}
+
+/// Data synchronization barrier over the inner shareable domain.
+#[inline(always)]
+pub fn dsb_ish() {
+    unsafe { barrier::dsb(barrier::ISH) }
+}
+
+/// Data synchronization barrier for stores over the inner shareable domain.
+#[inline(always)]
+pub fn dsb_ishst() {
+    unsafe { barrier::dsb(barrier::ISHST) }
+}
+
+/// Data memory barrier over the inner shareable domain.
+#[inline(always)]
+pub fn dmb_ish() {
+    unsafe { barrier::dmb(barrier::ISH) }
+}
+
+/// Instruction synchronization barrier.
+#[inline(always)]
+pub fn isb() {
+    unsafe { barrier::isb(barrier::SY) }
+}
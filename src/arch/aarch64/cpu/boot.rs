@@ -1,82 +1,84 @@
-// global_asm!(include_str!("start.s"));
+//! Entry of a kernel started without the bootloader, e.g. with QEMU `-kernel`.
+//!
+//! QEMU loads the image at its physical load address, `__load_paddr` in the linker script, and
+//! jumps to `_direct_start` in `boot.s` with the MMU off. The stub maps what the bootloader would
+//! map for the kernel: the image at its link address, the kernel stack at
+//! `config::KERNEL_STACK_VBASE` and all physical memory up to the end of RAM at
+//! `config::PHYS_MAP_OFFSET`, devices included. It then enables the MMU and continues in
+//! `runtime_init::direct_boot_init` on the kernel stack, which synthesizes the `BootInfo`.
+//!
+//! The bootloader does not come through here, it jumps to `runtime_init` directly.
 
-use crate::runtime_init;
-use crate::{bsp, cpu};
-use cortex_a::{asm, regs::*};
+use crate::{
+    bsp::{
+        self,
+        config::{self, MmuGranule},
+    },
+    mmu::mmu::{mair, T0SZ, T1SZ},
+};
 
-use crate::arch::reg;
+// Assembly counterpart to this file.
+global_asm!(
+    include_str!("boot.s"),
+    boot_core_id = const bsp::cpu::BOOT_CORE_ID,
+    mair = const mair::MAIR.value(),
+    tcr = const TCR,
+    block_code = const BLOCK_CODE,
+    block_data = const BLOCK_DATA,
+    block_device = const BLOCK_DEVICE,
+    ram_base = const config::RAM_BASE,
+    ram_size = const config::RAM_SIZE,
+    phys_map_offset = const config::PHYS_MAP_OFFSET,
+    stack_vbase = const config::KERNEL_STACK_VBASE,
+    stack_size = const STACK_SIZE,
+);
 
-/// Transition from EL2 to EL1.
-///
-/// # Safety
-///
-/// - The HW state of EL1 must be prepared in a sound way.
-/// - Exception return from EL2 must must continue execution in EL1 with
-///   `runtime_init::runtime_init()`.
-/// - We have to hope that the compiler omits any stack pointer usage, because we are not setting up
-///   a stack for EL2.
-#[inline(always)]
-unsafe fn el2_to_el1_transition() -> ! {
-    // Enable timer counter registers for EL1.
-    CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
+/// Size of the kernel stack.
+const STACK_SIZE: usize = config::KERNEL_STACK_PAGES * MmuGranule::SIZE;
 
-    // No offset for reading the counters.
-    CNTVOFF_EL2.set(0);
+/// A level 2 block, which the image and the stack are mapped with.
+const L2_BLOCK_SIZE: usize = 1 << 21;
 
-    // Set EL1 execution state to AArch64.
-    HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
+/// A level 1 block, which physical memory is mapped with.
+const L1_BLOCK_SIZE: usize = 1 << 30;
 
-    // Set up a simulated exception return.
-    //
-    // First, fake a saved program status where all interrupts were masked and SP_EL1 was used as a
-    // stack pointer.
-    SPSR_EL2.write(
-        SPSR_EL2::D::Masked
-            + SPSR_EL2::A::Masked
-            + SPSR_EL2::I::Masked
-            + SPSR_EL2::F::Masked
-            + SPSR_EL2::M::EL1h,
-    );
+// The stub builds the tables of a 4 KiB granule and 48 bit addresses, four levels in each half.
+const _: () = assert!(MmuGranule::SIZE == 4096);
+const _: () = assert!(T0SZ == 16 && T1SZ == 16);
+const _: () = assert!(STACK_SIZE % L2_BLOCK_SIZE == 0 && STACK_SIZE <= L1_BLOCK_SIZE);
+const _: () = assert!(config::KERNEL_STACK_VBASE % L1_BLOCK_SIZE == 0);
+const _: () = assert!(config::RAM_BASE % L1_BLOCK_SIZE == 0);
+const _: () = assert!(config::RAM_SIZE % L1_BLOCK_SIZE == 0);
+// A single level 1 table holds all of physical memory.
+const _: () = assert!(config::PHYS_MAP_OFFSET % (512 * L1_BLOCK_SIZE) == 0);
+const _: () = assert!(config::RAM_BASE + config::RAM_SIZE <= 512 * L1_BLOCK_SIZE);
 
-    // Second, let the link register point to runtime_init().
-    ELR_EL2.set(runtime_init::runtime_init as *const () as u64);
+/// Block descriptor bits, as `STAGE1_PAGE_DESCRIPTOR` describes them.
+const VALID_BLOCK: u64 = 0b01;
+const SH_INNER: u64 = 0b11 << 8;
+const SH_OUTER: u64 = 0b10 << 8;
+const AF: u64 = 1 << 10;
+const PXN: u64 = 1 << 53;
+const UXN: u64 = 1 << 54;
 
-    // Set up SP_EL1 (stack pointer), which will be used by EL1 once we "return" to it.
-    SP_EL1.set(bsp::memory::phys_boot_core_stack_end().into_usize() as u64);
+/// Normal memory, read-write and executable, for the image and the identity map of RAM the stub
+/// runs from until it jumps to the link address.
+const BLOCK_CODE: u64 = VALID_BLOCK | (mair::NORMAL << 2) | SH_INNER | AF | UXN;
 
-    // Use `eret` to "return" to EL1. This results in execution of runtime_init() in EL1.
-    asm::eret()
-}
+/// Normal memory, read-write, for the stack and the linear map of RAM.
+const BLOCK_DATA: u64 = BLOCK_CODE | PXN;
 
-#[inline(always)]
-fn config_el1() {
-    DAIF.write(DAIF::D::Masked + DAIF::A::Masked + DAIF::F::Masked + DAIF::I::Masked);
-    reg::cpacr_el1::CPACR_EL1.write(reg::cpacr_el1::CPACR_EL1::FPEN::NONE);
-}
+/// Device memory, read-write.
+const BLOCK_DEVICE: u64 = VALID_BLOCK | (mair::DEVICE << 2) | SH_OUTER | AF | PXN | UXN;
 
-/// The entry of the `kernel` binary.
-///
-/// The function must be named `_start`, because the linker is looking for this exact name.
-///
-/// # Safety
-///
-/// - Linker script must ensure to place this function where it is expected by the target machine.
-/// - Before calling this function, stack and MMU should be initialized.
-#[no_mangle]
-pub unsafe fn start() -> ! {
-    // Expect the boot core to start in EL2.
-    if bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id() {
-        if CurrentEL.get() == CurrentEL::EL::EL2.value {
-            el2_to_el1_transition()
-        } else if CurrentEL.get() == CurrentEL::EL::EL1.value {
-            config_el1();
-
-            runtime_init::runtime_init()
-        } else {
-            cpu::wait_forever()
-        }
-    } else {
-        // If not core0, infinitely wait for events.
-        cpu::wait_forever()
-    }
-}
+/// `TCR_EL1` as `MemoryManagementUnit::enable` programs it, but for `IPS`, which the stub takes
+/// from `ID_AA64MMFR0_EL1`: 4 KiB granules, walks inner shareable and write-back cacheable.
+const TCR: u64 = T0SZ as u64
+    | 0b01 << 8 // IRGN0
+    | 0b01 << 10 // ORGN0
+    | 0b11 << 12 // SH0
+    | (T1SZ as u64) << 16
+    | 0b01 << 24 // IRGN1
+    | 0b01 << 26 // ORGN1
+    | 0b11 << 28 // SH1
+    | 0b10 << 30; // TG1, 4 KiB
@@ -0,0 +1,220 @@
+/// Entry of the kernel image when started without the bootloader, see `boot.rs`.
+///
+/// Runs at the physical load address with the MMU off, so everything is addressed PC relative and
+/// link addresses are only loaded as values.
+
+/// Point the entry for `\virt` in the table `\tbl` of the level translating bits [\shift+8:\shift]
+/// to the next level table `\next`.
+.macro TABLE_ENTRY tbl, next, virt, shift, tmp1, tmp2
+    ubfx   \tmp1, \virt, #\shift, #9
+    orr    \tmp2, \next, #0b11
+    str    \tmp2, [\tbl, \tmp1, lsl #3]
+.endm
+
+/// Map `\size` bytes from `\phys` at `\virt` with blocks of `1 << \shift` bytes in the table
+/// `\tbl`, with the descriptor attributes in `\attrs`. `\size` must be a multiple of the block size.
+///
+/// Clobbers `\virt`, `\phys` and `\size`.
+.macro BLOCK_MAP tbl, virt, phys, size, shift, attrs, tmp
+    ubfx   \virt, \virt, #\shift, #9
+    orr    \phys, \phys, \attrs
+    mov    \tmp, #(1 << \shift)
+1:
+    cbz    \size, 2f
+    str    \phys, [\tbl, \virt, lsl #3]
+    add    \virt, \virt, #1
+    add    \phys, \phys, \tmp
+    sub    \size, \size, \tmp
+    b      1b
+2:
+.endm
+
+/// Map physical memory up to the end of RAM at `\offset` with level 1 blocks in `\tbl`: devices
+/// below RAM, RAM with the descriptor attributes in `\ram_attrs`.
+///
+/// Clobbers x1 to x5.
+.macro MAP_PHYSICAL tbl, offset, ram_attrs
+    mov    x1,  \offset
+    mov    x2,  #0
+    ldr    x3,  ={ram_base}
+    ldr    x4,  ={block_device}
+    BLOCK_MAP \tbl, x1, x2, x3, 30, x4, x5
+
+    ldr    x2,  ={ram_base}
+    add    x1,  \offset, x2
+    ldr    x3,  ={ram_size}
+    BLOCK_MAP \tbl, x1, x2, x3, 30, \ram_attrs, x5
+.endm
+
+.section .text._direct_start, "ax"
+
+.global _direct_start
+_direct_start:
+    // A device tree or 0, depending on the loader. QEMU passes 0 to ELF images.
+    mov    x19, x0
+
+    // The other cores stay off until `smp::wake_all`, but a loader may release them all here.
+    mrs    x0,  MPIDR_EL1
+    ldr    x1,  =0xff00ffffff
+    and    x0,  x0,  x1
+    ldr    x1,  ={boot_core_id}
+    cmp    x0,  x1
+    b.ne   .L_direct_hang
+
+    // Drop from EL2 to EL1h with all exceptions masked, EL1 being AArch64 and owning the timer.
+    mrs    x0,  CurrentEL
+    cmp    x0,  #(2 << 2)
+    b.ne   .L_direct_el1
+    mov    x0,  #(1 << 31)
+    msr    HCR_EL2, x0
+    mov    x0,  #0b11
+    msr    CNTHCTL_EL2, x0
+    msr    CNTVOFF_EL2, xzr
+    mov    x0,  #0x3c5
+    msr    SPSR_EL2, x0
+    adr    x0,  .L_direct_el1
+    msr    ELR_EL2, x0
+    eret
+
+.L_direct_el1:
+    mrs    x0,  CurrentEL
+    cmp    x0,  #(1 << 2)
+    b.ne   .L_direct_hang
+    msr    DAIFSet, #0b1111
+    // FP and SIMD trap, as under the bootloader.
+    msr    CPACR_EL1, xzr
+
+    // The tables are not part of the loaded data.
+    adrp   x0,  __direct_tables
+    adrp   x1,  __direct_tables_end
+.L_direct_zero:
+    stp    xzr, xzr, [x0], #16
+    cmp    x0,  x1
+    b.lo   .L_direct_zero
+
+    // x20: physical and x21: virtual start of the image, x22: physical end including the tables,
+    // which come last.
+    adrp   x20, _direct_start
+    ldr    x21, =_direct_start
+    adrp   x22, __direct_tables_end
+    // x23: physical base of the stack, the next level 2 block above the image. x24: end of the
+    // memory the stub uses, from the device tree at the start of RAM on.
+    mov    x0,  #((1 << 21) - 1)
+    add    x23, x22, x0
+    bic    x23, x23, x0
+    ldr    x0,  ={stack_size}
+    add    x24, x23, x0
+
+    // The image, the stack and the linear map each need their own level 0 entry.
+    ldr    x5,  ={stack_vbase}
+    ldr    x6,  ={phys_map_offset}
+    ubfx   x0,  x21, #39, #9
+    ubfx   x1,  x5,  #39, #9
+    ubfx   x2,  x6,  #39, #9
+    cmp    x0,  x1
+    b.eq   .L_direct_hang
+    cmp    x0,  x2
+    b.eq   .L_direct_hang
+    cmp    x1,  x2
+    b.eq   .L_direct_hang
+
+    // The image at its link address, read-write and executable as a whole.
+    adrp   x0,  __direct_ttbr1_l0
+    adrp   x1,  __direct_image_l1
+    TABLE_ENTRY x0, x1, x21, 39, x2, x3
+    adrp   x0,  __direct_image_l2
+    TABLE_ENTRY x1, x0, x21, 30, x2, x3
+    mov    x1,  x21
+    mov    x2,  x20
+    sub    x3,  x23, x20
+    ldr    x4,  ={block_code}
+    BLOCK_MAP x0, x1, x2, x3, 21, x4, x5
+
+    // The stack, below which nothing is mapped.
+    ldr    x5,  ={stack_vbase}
+    adrp   x0,  __direct_ttbr1_l0
+    adrp   x1,  __direct_stack_l1
+    TABLE_ENTRY x0, x1, x5, 39, x2, x3
+    adrp   x0,  __direct_stack_l2
+    TABLE_ENTRY x1, x0, x5, 30, x2, x3
+    mov    x2,  x23
+    ldr    x3,  ={stack_size}
+    ldr    x4,  ={block_data}
+    BLOCK_MAP x0, x5, x2, x3, 21, x4, x6
+
+    // The linear map of physical memory the kernel accesses RAM and devices through.
+    ldr    x6,  ={phys_map_offset}
+    adrp   x0,  __direct_ttbr1_l0
+    adrp   x7,  __direct_phys_l1
+    TABLE_ENTRY x0, x7, x6, 39, x2, x3
+    ldr    x8,  ={block_data}
+    MAP_PHYSICAL x7, x6, x8
+
+    // The identity map the stub runs from until it jumps to the link address, the UART is used
+    // at its physical address until `runtime_init` switches to the linear map.
+    adrp   x0,  __direct_ttbr0_l0
+    adrp   x7,  __direct_ident_l1
+    TABLE_ENTRY x0, x7, xzr, 39, x2, x3
+    ldr    x8,  ={block_code}
+    MAP_PHYSICAL x7, xzr, x8
+
+    // Translation as `MemoryManagementUnit::enable` sets it up.
+    ldr    x0,  ={mair}
+    msr    MAIR_EL1, x0
+    ldr    x0,  ={tcr}
+    mrs    x1,  ID_AA64MMFR0_EL1
+    bfi    x0,  x1,  #32, #3
+    // TCR_EL1.HA where ID_AA64MMFR1_EL1.HAFDBS reports hardware access flag management.
+    mrs    x1,  ID_AA64MMFR1_EL1
+    tst    x1,  #0xf
+    b.eq   .L_direct_no_ha
+    orr    x0,  x0,  #(1 << 39)
+.L_direct_no_ha:
+    msr    TCR_EL1, x0
+    adrp   x0,  __direct_ttbr0_l0
+    msr    TTBR0_EL1, x0
+    adrp   x0,  __direct_ttbr1_l0
+    msr    TTBR1_EL1, x0
+    dsb    sy
+    tlbi   vmalle1
+    dsb    nsh
+    isb
+    // SCTLR_EL1.M only, caches stay off as under the bootloader.
+    mov    x0,  #1
+    msr    SCTLR_EL1, x0
+    isb
+
+    // Continue at the link address, on the kernel stack.
+    ldr    x0,  =({stack_vbase} + {stack_size})
+    mov    sp,  x0
+    mov    x0,  x19
+    mov    x1,  x24
+    ldr    x2,  =direct_boot_init
+    br     x2
+
+.L_direct_hang:
+    wfe
+    b      .L_direct_hang
+
+/// The translation tables of the stub. Not in .bss, which `runtime_init` zeroes while they are in
+/// use, and placed last in the image by the linker script.
+.section .direct_boot_tables, "aw", %nobits
+.balign 4096
+__direct_tables:
+__direct_ttbr0_l0:
+    .space 4096
+__direct_ident_l1:
+    .space 4096
+__direct_ttbr1_l0:
+    .space 4096
+__direct_image_l1:
+    .space 4096
+__direct_image_l2:
+    .space 4096
+__direct_stack_l1:
+    .space 4096
+__direct_stack_l2:
+    .space 4096
+__direct_phys_l1:
+    .space 4096
+__direct_tables_end:
@@ -0,0 +1,124 @@
+//! Cache maintenance by virtual address.
+
+use crate::{
+    bsp::config,
+    common,
+    cpu::barrier,
+    memory::{AddressRange, Virtual},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cortex_a::regs::*;
+
+/// `dcache_line_size` after the first call, zero before.
+static DCACHE_LINE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// `icache_line_size` after the first call, zero before.
+static ICACHE_LINE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// The line size in the 4 bit field of CTR_EL0 at `shift`, or `config::CACHE_LINE_SIZE_OVERRIDE`,
+/// remembered in `cached`.
+fn line_size(cached: &AtomicUsize, shift: u64) -> usize {
+    let size = cached.load(Ordering::Relaxed);
+    if size != 0 {
+        return size;
+    }
+
+    let size = config::CACHE_LINE_SIZE_OVERRIDE.unwrap_or_else(|| {
+        let ctr: u64;
+        unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack)) };
+        // The field holds log2 of the number of words in a line.
+        4 << ((ctr >> shift) & 0xF)
+    });
+    // Every core reads the same value, a race only stores it twice.
+    cached.store(size, Ordering::Relaxed);
+    size
+}
+
+/// Smallest data cache line size of all caches the core can see, from CTR_EL0.DminLine.
+pub fn dcache_line_size() -> usize {
+    line_size(&DCACHE_LINE_SIZE, 16)
+}
+
+/// Smallest instruction cache line size of all caches the core can see, from CTR_EL0.IminLine.
+pub fn icache_line_size() -> usize {
+    line_size(&ICACHE_LINE_SIZE, 0)
+}
+
+/// Start addresses of the lines of `line_size` bytes covering `range`.
+fn lines(range: AddressRange<Virtual>, line_size: usize) -> impl Iterator<Item = usize> {
+    let start = common::align_down(range.addr().into_usize(), line_size);
+    // Counted from the start, so a range at the top of the address space does not overflow.
+    let count = match range.last() {
+        Some(last) => (last.into_usize() - start) / line_size + 1,
+        None => 0,
+    };
+
+    (0..count).map(move |line| start + line * line_size)
+}
+
+/// Start addresses of the data cache lines covering `range`.
+fn dcache_lines(range: AddressRange<Virtual>) -> impl Iterator<Item = usize> {
+    lines(range, dcache_line_size())
+}
+
+/// Clean the data cache lines covering `range` to the point of coherency.
+pub fn clean_dcache_range(range: AddressRange<Virtual>) {
+    for line in dcache_lines(range) {
+        unsafe { asm!("dc cvac, {}", in(reg) line, options(nostack)) };
+    }
+    barrier::dsb_sy();
+}
+
+/// Clean and invalidate the data cache lines covering `range` to the point of coherency.
+pub fn clean_and_invalidate_dcache_range(range: AddressRange<Virtual>) {
+    for line in dcache_lines(range) {
+        unsafe { asm!("dc civac, {}", in(reg) line, options(nostack)) };
+    }
+    barrier::dsb_sy();
+}
+
+/// Invalidate the instruction cache lines covering `range` to the point of unification, on all
+/// cores of the inner shareable domain.
+///
+/// Code written through the data side must be cleaned with `clean_dcache_range` first.
+pub fn invalidate_icache_range(range: AddressRange<Virtual>) {
+    for line in lines(range, icache_line_size()) {
+        unsafe { asm!("ic ivau, {}", in(reg) line, options(nostack)) };
+    }
+    barrier::dsb_ish();
+    barrier::isb();
+}
+
+/// Invalidate the whole instruction cache of the executing core to the point of unification.
+///
+/// Code written through the data side must be cleaned with `clean_dcache_range` first.
+pub fn invalidate_icache_all() {
+    unsafe { asm!("ic iallu", options(nostack)) };
+    barrier::dsb_ish();
+    barrier::isb();
+}
+
+/// Size of the block zeroed by `zero_block`, or `None` if `dc zva` must not be used.
+///
+/// `dc zva` faults on device memory, which is what all data accesses are while the MMU is off.
+pub fn zero_block_size() -> Option<usize> {
+    let dczid: u64;
+    unsafe { asm!("mrs {}, DCZID_EL0", out(reg) dczid, options(nomem, nostack)) };
+
+    // DZP prohibits the instruction.
+    if dczid & (1 << 4) != 0 || !SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable) {
+        return None;
+    }
+    // BS holds log2 of the number of words in a block.
+    Some(4 << (dczid & 0xF))
+}
+
+/// Zero the block of `zero_block_size` bytes containing `addr` with `dc zva`.
+///
+/// # Safety
+///
+/// - `zero_block_size` must have returned `Some`.
+/// - The block must be writable normal memory.
+pub unsafe fn zero_block(addr: usize) {
+    asm!("dc zva, {}", in(reg) addr, options(nostack));
+}
@@ -0,0 +1,71 @@
+//! Switching between threads of execution at EL1.
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("context.s"));
+
+/// The state a thread keeps across a call to `switch`: the callee-saved registers of the AAPCS64.
+///
+/// The layout is shared with `__switch_context` in `context.s` and must be changed in both places
+/// at once.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct Context {
+    /// x19 to x28.
+    gpr: [u64; 10],
+
+    /// The frame pointer, aka x29.
+    fp: u64,
+
+    /// The link register, aka x30. Where `switch` returns to when the thread is resumed.
+    lr: u64,
+
+    sp: u64,
+
+    /// The lower halves of v8 to v15.
+    fpr: [u64; 8],
+}
+
+impl Context {
+    /// A context to be filled in by the first `switch` away from it.
+    pub const fn zeroed() -> Self {
+        Self {
+            gpr: [0; 10],
+            fp: 0,
+            lr: 0,
+            sp: 0,
+            fpr: [0; 8],
+        }
+    }
+
+    /// A context that starts running `entry` on the stack ending at `stack_end`.
+    pub fn new(entry: extern "C" fn() -> !, stack_end: usize) -> Self {
+        assert!(
+            stack_end % 16 == 0,
+            "Stack end {:#x} is not 16 byte aligned",
+            stack_end
+        );
+
+        Self {
+            lr: entry as usize as u64,
+            sp: stack_end as u64,
+            ..Self::zeroed()
+        }
+    }
+}
+
+/// Save the running thread to `from` and resume the one saved in `to`.
+///
+/// Returns once another thread switches back to `from`.
+///
+/// # Safety
+///
+/// - `from` and `to` must stay valid until the respective thread is resumed.
+/// - The stack of `to` must not be in use by any other thread.
+#[inline(never)]
+pub unsafe fn switch(from: *mut Context, to: *const Context) {
+    extern "C" {
+        fn __switch_context(from: *mut Context, to: *const Context);
+    }
+
+    __switch_context(from, to)
+}
@@ -0,0 +1,38 @@
+/// Save the callee-saved state of the running thread to the `Context` in `x0`, then resume the
+/// thread saved in the `Context` in `x1`.
+///
+/// The layout matches `Context` in `context.rs`. Everything else is caller-saved and already
+/// spilled by the compiler around the call.
+.section .text
+.global __switch_context
+__switch_context:
+    // Save the outgoing thread.
+    mov    x9,  sp
+    stp    x19, x20, [x0, #16 * 0]
+    stp    x21, x22, [x0, #16 * 1]
+    stp    x23, x24, [x0, #16 * 2]
+    stp    x25, x26, [x0, #16 * 3]
+    stp    x27, x28, [x0, #16 * 4]
+    stp    x29, lr,  [x0, #16 * 5]
+    str    x9,       [x0, #16 * 6]
+    stp    d8,  d9,  [x0, #16 * 6 + 8]
+    stp    d10, d11, [x0, #16 * 7 + 8]
+    stp    d12, d13, [x0, #16 * 8 + 8]
+    stp    d14, d15, [x0, #16 * 9 + 8]
+
+    // Restore the incoming one.
+    ldp    x19, x20, [x1, #16 * 0]
+    ldp    x21, x22, [x1, #16 * 1]
+    ldp    x23, x24, [x1, #16 * 2]
+    ldp    x25, x26, [x1, #16 * 3]
+    ldp    x27, x28, [x1, #16 * 4]
+    ldp    x29, lr,  [x1, #16 * 5]
+    ldr    x9,       [x1, #16 * 6]
+    ldp    d8,  d9,  [x1, #16 * 6 + 8]
+    ldp    d10, d11, [x1, #16 * 7 + 8]
+    ldp    d12, d13, [x1, #16 * 8 + 8]
+    ldp    d14, d15, [x1, #16 * 9 + 8]
+    mov    sp,  x9
+
+    // Continue where the incoming thread called `__switch_context`, or at its entry point.
+    ret
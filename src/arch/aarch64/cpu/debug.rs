@@ -0,0 +1,206 @@
+//! Self-hosted hardware breakpoints and watchpoints.
+//!
+//! Hits are taken as debug exceptions at EL1 and end up in the exception handlers, which print the
+//! context with `ELR_EL1` pointing at the offending instruction.
+
+use crate::{
+    cpu::barrier,
+    memory::{Address, Virtual},
+};
+use cortex_a::regs::*;
+
+/// Accesses a watchpoint triggers on.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchpointAccess {
+    Load,
+    Store,
+    LoadStore,
+}
+
+/// Number of debug register pairs the architecture can encode.
+const MAX_SLOTS: usize = 16;
+
+/// DBGxCR<n>_EL1.E, the slot is enabled.
+const CR_ENABLE: u64 = 1 << 0;
+
+/// DBGxCR<n>_EL1.PMC/PAC = 0b01, match at EL1 only.
+const CR_EL1_ONLY: u64 = 0b01 << 1;
+
+/// Read `DBG<$reg><n>_EL1`.
+macro_rules! read_indexed {
+    ($reg:literal, $n:expr) => {{
+        let value: u64;
+        match $n {
+            0 => asm!(concat!("mrs {}, DBG", $reg, "0_EL1"), out(reg) value, options(nomem, nostack)),
+            1 => asm!(concat!("mrs {}, DBG", $reg, "1_EL1"), out(reg) value, options(nomem, nostack)),
+            2 => asm!(concat!("mrs {}, DBG", $reg, "2_EL1"), out(reg) value, options(nomem, nostack)),
+            3 => asm!(concat!("mrs {}, DBG", $reg, "3_EL1"), out(reg) value, options(nomem, nostack)),
+            4 => asm!(concat!("mrs {}, DBG", $reg, "4_EL1"), out(reg) value, options(nomem, nostack)),
+            5 => asm!(concat!("mrs {}, DBG", $reg, "5_EL1"), out(reg) value, options(nomem, nostack)),
+            6 => asm!(concat!("mrs {}, DBG", $reg, "6_EL1"), out(reg) value, options(nomem, nostack)),
+            7 => asm!(concat!("mrs {}, DBG", $reg, "7_EL1"), out(reg) value, options(nomem, nostack)),
+            8 => asm!(concat!("mrs {}, DBG", $reg, "8_EL1"), out(reg) value, options(nomem, nostack)),
+            9 => asm!(concat!("mrs {}, DBG", $reg, "9_EL1"), out(reg) value, options(nomem, nostack)),
+            10 => asm!(concat!("mrs {}, DBG", $reg, "10_EL1"), out(reg) value, options(nomem, nostack)),
+            11 => asm!(concat!("mrs {}, DBG", $reg, "11_EL1"), out(reg) value, options(nomem, nostack)),
+            12 => asm!(concat!("mrs {}, DBG", $reg, "12_EL1"), out(reg) value, options(nomem, nostack)),
+            13 => asm!(concat!("mrs {}, DBG", $reg, "13_EL1"), out(reg) value, options(nomem, nostack)),
+            14 => asm!(concat!("mrs {}, DBG", $reg, "14_EL1"), out(reg) value, options(nomem, nostack)),
+            15 => asm!(concat!("mrs {}, DBG", $reg, "15_EL1"), out(reg) value, options(nomem, nostack)),
+            _ => unreachable!(),
+        }
+        value
+    }};
+}
+
+/// Write `$value` to `DBG<$reg><n>_EL1`.
+macro_rules! write_indexed {
+    ($reg:literal, $n:expr, $value:expr) => {{
+        let value: u64 = $value;
+        match $n {
+            0 => asm!(concat!("msr DBG", $reg, "0_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            1 => asm!(concat!("msr DBG", $reg, "1_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            2 => asm!(concat!("msr DBG", $reg, "2_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            3 => asm!(concat!("msr DBG", $reg, "3_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            4 => asm!(concat!("msr DBG", $reg, "4_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            5 => asm!(concat!("msr DBG", $reg, "5_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            6 => asm!(concat!("msr DBG", $reg, "6_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            7 => asm!(concat!("msr DBG", $reg, "7_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            8 => asm!(concat!("msr DBG", $reg, "8_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            9 => asm!(concat!("msr DBG", $reg, "9_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            10 => asm!(concat!("msr DBG", $reg, "10_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            11 => asm!(concat!("msr DBG", $reg, "11_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            12 => asm!(concat!("msr DBG", $reg, "12_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            13 => asm!(concat!("msr DBG", $reg, "13_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            14 => asm!(concat!("msr DBG", $reg, "14_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            15 => asm!(concat!("msr DBG", $reg, "15_EL1, {}"), in(reg) value, options(nomem, nostack)),
+            _ => unreachable!(),
+        }
+    }};
+}
+
+fn id_aa64dfr0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64DFR0_EL1", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+/// Number of implemented breakpoints, from ID_AA64DFR0_EL1.BRPs.
+pub fn breakpoint_count() -> usize {
+    ((id_aa64dfr0() >> 12) & 0xF) as usize + 1
+}
+
+/// Number of implemented watchpoints, from ID_AA64DFR0_EL1.WRPs.
+pub fn watchpoint_count() -> usize {
+    ((id_aa64dfr0() >> 20) & 0xF) as usize + 1
+}
+
+/// Enable debug exceptions taken to EL1.
+///
+/// # Safety
+///
+/// - Unmasks debug exceptions on the executing core.
+pub unsafe fn enable() {
+    // Release the OS lock, which blocks debug exceptions while set.
+    asm!("msr OSLAR_EL1, xzr", options(nomem, nostack));
+
+    // MDSCR_EL1.MDE enables breakpoints and watchpoints, MDSCR_EL1.KDE allows them at EL1.
+    const MDSCR_KDE: u64 = 1 << 13;
+    const MDSCR_MDE: u64 = 1 << 15;
+    let mut mdscr: u64;
+    asm!("mrs {}, MDSCR_EL1", out(reg) mdscr, options(nomem, nostack));
+    mdscr |= MDSCR_KDE | MDSCR_MDE;
+    asm!("msr MDSCR_EL1, {}", in(reg) mdscr, options(nomem, nostack));
+
+    DAIF.modify(DAIF::D::Unmasked);
+    barrier::isb();
+}
+
+/// Find a slot whose control register does not have the enable bit set.
+fn free_slot(count: usize, is_enabled: impl Fn(usize) -> bool) -> Result<usize, &'static str> {
+    (0..count.min(MAX_SLOTS))
+        .find(|n| !is_enabled(*n))
+        .ok_or("No free debug register")
+}
+
+/// Trap execution of the instruction at `addr`. Returns the slot used.
+pub fn set_breakpoint(addr: Address<Virtual>) -> Result<usize, &'static str> {
+    if !addr.is_aligned(4) {
+        return Err("Breakpoint address not aligned to an instruction");
+    }
+    let n = free_slot(breakpoint_count(), |n| {
+        let control = unsafe { read_indexed!("BCR", n) };
+        control & CR_ENABLE != 0
+    })?;
+
+    // DBGBCR<n>_EL1.BAS = 0b1111 matches the whole A64 instruction, BT = 0 is an unlinked address
+    // match.
+    const BCR_BAS_A64: u64 = 0b1111 << 5;
+    unsafe {
+        write_indexed!("BVR", n, addr.into_usize() as u64);
+        write_indexed!("BCR", n, BCR_BAS_A64 | CR_EL1_ONLY | CR_ENABLE);
+        barrier::isb();
+    }
+    Ok(n)
+}
+
+/// Disable breakpoint slot `n`.
+pub fn clear_breakpoint(n: usize) {
+    assert!(n < breakpoint_count().min(MAX_SLOTS), "No breakpoint {}", n);
+    unsafe {
+        write_indexed!("BCR", n, 0);
+        barrier::isb();
+    }
+}
+
+/// Trap `access`es to the `len` bytes at `addr`. Returns the slot used.
+///
+/// `len` either has to fit into the double word containing `addr`, or be a power of two of at
+/// least 8 bytes with `addr` aligned to it.
+pub fn set_watchpoint(
+    addr: Address<Virtual>,
+    len: usize,
+    access: WatchpointAccess,
+) -> Result<usize, &'static str> {
+    let offset = addr.into_usize() & 0x7;
+    let (bas, mask) = if len > 0 && offset + len <= 8 {
+        // Byte address select inside the double word.
+        ((((1u64 << len) - 1) << offset), 0)
+    } else if len >= 8 && len.is_power_of_two() && addr.is_aligned(len) {
+        // Address mask, the low log2(len) bits are ignored.
+        (0xFF, len.trailing_zeros() as u64)
+    } else {
+        return Err("Watchpoint range can not be encoded");
+    };
+    if mask > 31 {
+        return Err("Watchpoint range too large");
+    }
+
+    let lsc: u64 = match access {
+        WatchpointAccess::Load => 0b01,
+        WatchpointAccess::Store => 0b10,
+        WatchpointAccess::LoadStore => 0b11,
+    };
+    let n = free_slot(watchpoint_count(), |n| {
+        let control = unsafe { read_indexed!("WCR", n) };
+        control & CR_ENABLE != 0
+    })?;
+
+    let wcr = (mask << 24) | (bas << 5) | (lsc << 3) | CR_EL1_ONLY | CR_ENABLE;
+    unsafe {
+        write_indexed!("WVR", n, (addr.into_usize() & !0x7) as u64);
+        write_indexed!("WCR", n, wcr);
+        barrier::isb();
+    }
+    Ok(n)
+}
+
+/// Disable watchpoint slot `n`.
+pub fn clear_watchpoint(n: usize) {
+    assert!(n < watchpoint_count().min(MAX_SLOTS), "No watchpoint {}", n);
+    unsafe {
+        write_indexed!("WCR", n, 0);
+        barrier::isb();
+    }
+}
@@ -0,0 +1,47 @@
+//! Scoped masking of interrupts on the executing core.
+
+use core::marker::PhantomData;
+use cortex_a::regs::*;
+
+/// Masks IRQ and FIQ while alive and restores the previous `DAIF` state on drop.
+///
+/// Guards nest: an inner guard restores the masked state the outer one set up. The guard is not
+/// `Send`, as the state belongs to the core that created it.
+#[must_use]
+pub struct InterruptGuard {
+    daif: u64,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptGuard {
+    /// Mask IRQ and FIQ, remembering the current state.
+    pub fn new() -> Self {
+        let daif = DAIF.get();
+        DAIF.modify(DAIF::I::Masked + DAIF::F::Masked);
+        Self {
+            daif,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        DAIF.set(self.daif);
+    }
+}
+
+/// Unmask IRQ on the executing core.
+///
+/// # Safety
+///
+/// - The exception vectors must be installed and able to handle whatever is enabled in the GIC.
+pub unsafe fn enable_irq() {
+    DAIF.modify(DAIF::I::Unmasked);
+}
+
+/// Run `f` with IRQ and FIQ masked on the executing core.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let _guard = InterruptGuard::new();
+    f()
+}
@@ -11,16 +11,107 @@
 //!
 //! crate::cpu::arch_cpu
 
-use cortex_a::asm;
+use cortex_a::{asm, regs::*};
 
+pub mod barrier;
 pub mod boot;
+pub mod cache;
+pub mod context;
+pub mod debug;
+mod interrupt;
+pub mod psci;
 pub mod smp;
+pub mod timer;
+pub mod tlb;
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
 pub use asm::nop;
+pub use interrupt::{enable_irq, without_interrupts, InterruptGuard};
+
+/// Return the current stack pointer.
+#[inline(always)]
+pub fn stack_pointer() -> usize {
+    SP.get() as usize
+}
+
+/// Return the exception level the core runs at.
+#[inline(always)]
+pub fn current_el() -> u8 {
+    CurrentEL.read(CurrentEL::EL) as u8
+}
+
+/// Panic with the actual exception level if the core does not run at `expected`.
+#[track_caller]
+pub fn assert_el(expected: u8) {
+    let el = current_el();
+    assert!(
+        el == expected,
+        "Running at EL{}, expected EL{}",
+        el,
+        expected
+    );
+}
+
+/// Stop trapping FP/SIMD instructions at EL0 and EL1.
+///
+/// `CPACR_EL1::FPEN::NONE` (0b11) is the encoding that traps nothing.
+#[inline(always)]
+pub fn enable_fp_simd() {
+    use crate::arch::reg::cpacr_el1::CPACR_EL1;
+
+    CPACR_EL1.modify(CPACR_EL1::FPEN::NONE);
+    barrier::isb();
+}
+
+/// Trap FP/SIMD instructions at EL0 only, for lazy enabling: the first use at EL0 raises a
+/// synchronous exception whose handler calls `enable_fp_simd` and retries the instruction.
+#[inline(always)]
+pub fn trap_fp_simd_el0() {
+    use crate::arch::reg::cpacr_el1::CPACR_EL1;
+
+    CPACR_EL1.modify(CPACR_EL1::FPEN::EL0_ONLY);
+    barrier::isb();
+}
+
+/// Return `ID_AA64MMFR0_EL1`, the supported physical address size and translation granules.
+#[inline(always)]
+pub fn memory_model_features() -> u64 {
+    let mmfr0: u64;
+    unsafe { asm!("mrs {}, ID_AA64MMFR0_EL1", out(reg) mmfr0, options(nomem, nostack)) };
+    mmfr0
+}
+
+/// Return `ID_AA64MMFR1_EL1`, the supported hardware management of the access flag and dirty state
+/// among others.
+#[inline(always)]
+pub fn memory_model_features1() -> u64 {
+    let mmfr1: u64;
+    unsafe { asm!("mrs {}, ID_AA64MMFR1_EL1", out(reg) mmfr1, options(nomem, nostack)) };
+    mmfr1
+}
+
+/// Wake every core waiting in `wfe`, after the stores before it became visible to them.
+#[inline(always)]
+pub fn send_event() {
+    unsafe { asm!("dsb ishst", "sev", options(nostack)) };
+}
+
+/// Sleep until an event, e.g. from `send_event` on another core. May also return spuriously.
+///
+/// Use it for loops waiting on a memory location whose writer calls `send_event` afterwards.
+#[inline(always)]
+pub fn wait_for_event() {
+    asm::wfe()
+}
+
+/// Tell the core it runs a busy-wait loop, call once per iteration.
+#[inline(always)]
+pub fn spin_hint() {
+    unsafe { asm!("yield", options(nomem, nostack, preserves_flags)) };
+}
 
 /// Pause execution on the core.
 #[inline(always)]
@@ -30,6 +121,22 @@ pub fn wait_forever() -> ! {
     }
 }
 
+/// Reboot the machine through PSCI `SYSTEM_RESET`.
+///
+/// Waits forever if the firmware refuses.
+pub fn reset() -> ! {
+    unsafe { psci::call(psci::SYSTEM_RESET, 0, 0, 0) };
+    wait_forever()
+}
+
+/// Power the machine off through PSCI `SYSTEM_OFF`.
+///
+/// Waits forever if the firmware refuses.
+pub fn poweroff() -> ! {
+    unsafe { psci::call(psci::SYSTEM_OFF, 0, 0, 0) };
+    wait_forever()
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
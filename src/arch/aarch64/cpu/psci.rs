@@ -0,0 +1,54 @@
+//! Power State Coordination Interface calls to the firmware.
+
+use crate::bsp::config;
+
+/// Instruction used to reach the PSCI implementation.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PsciConduit {
+    /// Firmware at EL3.
+    Smc,
+    /// Hypervisor at EL2, e.g. QEMU's PSCI emulation when EL2/EL3 are not emulated.
+    Hvc,
+}
+
+pub const CPU_ON: u32 = 0xC400_0003;
+pub const SYSTEM_OFF: u32 = 0x8400_0008;
+pub const SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// Issue PSCI `function` with up to three arguments and return the firmware's status.
+///
+/// # Safety
+///
+/// - The effects depend on `function`, e.g. `CPU_ON` starts executing code at a given address.
+pub unsafe fn call(function: u32, arg0: u64, arg1: u64, arg2: u64) -> i64 {
+    let ret: i64;
+    // SMCCC v1.0 allows the callee to corrupt x0-x17.
+    match config::PSCI_CONDUIT {
+        PsciConduit::Smc => asm!(
+            "smc #0",
+            inlateout("x0") function as u64 => ret,
+            inlateout("x1") arg0 => _,
+            inlateout("x2") arg1 => _,
+            inlateout("x3") arg2 => _,
+            lateout("x4") _, lateout("x5") _, lateout("x6") _, lateout("x7") _,
+            lateout("x8") _, lateout("x9") _, lateout("x10") _, lateout("x11") _,
+            lateout("x12") _, lateout("x13") _, lateout("x14") _, lateout("x15") _,
+            lateout("x16") _, lateout("x17") _,
+            options(nostack)
+        ),
+        PsciConduit::Hvc => asm!(
+            "hvc #0",
+            inlateout("x0") function as u64 => ret,
+            inlateout("x1") arg0 => _,
+            inlateout("x2") arg1 => _,
+            inlateout("x3") arg2 => _,
+            lateout("x4") _, lateout("x5") _, lateout("x6") _, lateout("x7") _,
+            lateout("x8") _, lateout("x9") _, lateout("x10") _, lateout("x11") _,
+            lateout("x12") _, lateout("x13") _, lateout("x14") _, lateout("x15") _,
+            lateout("x16") _, lateout("x17") _,
+            options(nostack)
+        ),
+    }
+    ret
+}
@@ -11,19 +11,209 @@
 //!
 //! crate::cpu::smp::arch_smp
 
+use super::psci;
+use crate::{
+    bsp,
+    memory::{AddrMapper, Address, Physical},
+};
+use core::{
+    cell::UnsafeCell,
+    convert::TryInto,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use cortex_a::regs::*;
 
+pub mod secondary;
+pub mod spin_table;
+
+pub use secondary::wake_all;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Interior mutability for statics that only the boot core may touch, until they are protected
+/// by proper locks.
+///
+/// Panics if any other core accesses the value, or if it is accessed again while `with` runs, e.g.
+/// from an exception taken inside it.
+pub struct SingleCoreCell<T> {
+    value: UnsafeCell<T>,
+    borrowed: AtomicBool,
+}
+
+unsafe impl<T: Send> Sync for SingleCoreCell<T> {}
+
+/// How a secondary core is started, from the `enable-method` property of its DTB `cpu` node.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EnableMethod {
+    /// PSCI `CPU_ON`.
+    Psci,
+    /// The core polls `release_addr` until it holds an entry address.
+    SpinTable { release_addr: Address<Physical> },
+}
+
+/// A core as described by its DTB `cpu` node.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CoreDesc {
+    /// The affinity fields of the core's `MPIDR_EL1`, from the `reg` property.
+    pub mpidr: u64,
+    pub method: EnableMethod,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
 /// Return the executing core's id.
+///
+/// The id is the core's affinity packed as `Aff3:Aff2:Aff1:Aff0`, one byte each. The RES1, U and
+/// MT bits of `MPIDR_EL1` are not part of it, so the id is stable across implementations that set
+/// them differently.
+#[inline(always)]
+pub fn core_id() -> usize {
+    affinity_id(MPIDR_EL1.get())
+}
+
+/// Pack the affinity fields of `mpidr` into a core id, see `core_id`.
+#[inline(always)]
+fn affinity_id(mpidr: u64) -> usize {
+    // Aff3 is held in [39:32], Aff2..Aff0 in [23:0].
+    const AFF3_MASK: u64 = 0xFF_0000_0000;
+    const AFF2_AFF0_MASK: u64 = 0xFF_FFFF;
+
+    (((mpidr & AFF3_MASK) >> 8) | (mpidr & AFF2_AFF0_MASK)) as usize
+}
+
+/// Check if the executing core is the one the BSP boots on.
 #[inline(always)]
-pub fn core_id<T>() -> T
-where
-    T: From<u8>,
-{
-    const CORE_MASK: u64 = 0b11;
+pub fn is_boot_core() -> bool {
+    core_id() == bsp::cpu::BOOT_CORE_ID
+}
+
+/// Start the core with affinity `mpidr` at `entry`.
+///
+/// `context` is handed to the core in `x0` by PSCI. A spin table passes nothing, so the entry
+/// code must not rely on it.
+///
+/// # Safety
+///
+/// - `entry` must be code the core can run with the MMU and the caches off.
+/// - For a spin table, `MAPPER` must map the release address writable.
+pub unsafe fn start_core<MAPPER: AddrMapper>(
+    mpidr: u64,
+    method: EnableMethod,
+    entry: Address<Physical>,
+    context: u64,
+) -> Result<(), &'static str> {
+    match method {
+        EnableMethod::Psci => {
+            match psci::call(psci::CPU_ON, mpidr, entry.into_usize() as u64, context) {
+                0 => Ok(()),
+                _ => Err("PSCI CPU_ON failed"),
+            }
+        }
+        EnableMethod::SpinTable { release_addr } => {
+            spin_table::release::<MAPPER>(release_addr, entry);
+            Ok(())
+        }
+    }
+}
+
+impl EnableMethod {
+    /// Parse the raw values of the `enable-method` and `cpu-release-addr` properties.
+    ///
+    /// Returns `None` for unknown methods and for a spin table without a valid release address.
+    pub fn from_properties(enable_method: &[u8], release_addr: Option<&[u8]>) -> Option<Self> {
+        // String properties include their NUL terminator.
+        match enable_method.split(|&byte| byte == 0).next()? {
+            b"psci" => Some(Self::Psci),
+            b"spin-table" => {
+                // Two big endian cells.
+                let cells: [u8; 8] = release_addr?.try_into().ok()?;
+                Some(Self::SpinTable {
+                    release_addr: Address::new(u64::from_be_bytes(cells) as usize),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl CoreDesc {
+    /// Describe the core of a DTB `cpu` node, `None` if its enable method is unknown.
+    pub fn from_node(node: &bsp::dtb::CpuNode<'_>) -> Option<Self> {
+        Some(Self {
+            mpidr: node.reg,
+            method: EnableMethod::from_properties(node.enable_method, node.release_addr)?,
+        })
+    }
+
+    /// The id `core_id` returns on this core.
+    pub fn id(&self) -> usize {
+        affinity_id(self.mpidr)
+    }
+}
+
+impl<T> SingleCoreCell<T> {
+    /// Create an instance.
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            borrowed: AtomicBool::new(false),
+        }
+    }
+
+    #[inline(always)]
+    fn check_core(&self) {
+        if !is_boot_core() {
+            panic!("Single core state accessed from core {:#x}", core_id());
+        }
+    }
+
+    /// Run `f` with exclusive access to the value.
+    ///
+    /// Panics if `with` is already running for the same cell.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.check_core();
+        match self.try_with(f) {
+            Some(result) => result,
+            None => panic!("Single core state accessed again while in use"),
+        }
+    }
+
+    /// Like `with`, but return `None` instead of running `f` on another core or if `with` is
+    /// already running, e.g. on the panic path.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        if !is_boot_core() || self.borrowed.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        let _borrow = Borrow(&self.borrowed);
+        Some(f(unsafe { &mut *self.value.get() }))
+    }
+
+    /// Get a mutable reference to the value.
+    ///
+    /// Panics if `with` is running.
+    ///
+    /// # Safety
+    ///
+    /// - No other reference to the value may be alive at the same time.
+    pub unsafe fn get_mut(&self) -> &mut T {
+        self.check_core();
+        assert!(
+            !self.borrowed.load(Ordering::Acquire),
+            "Single core state accessed while in use"
+        );
+        &mut *self.value.get()
+    }
+}
+
+/// Clears the borrow flag of a `SingleCoreCell` when `with` returns or unwinds.
+struct Borrow<'a>(&'a AtomicBool);
 
-    T::from((MPIDR_EL1.get() & CORE_MASK) as u8)
+impl Drop for Borrow<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
 }
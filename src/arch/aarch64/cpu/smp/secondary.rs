@@ -0,0 +1,222 @@
+//! Bringing all secondary cores online.
+//!
+//! Each core is started at `__secondary_entry` with the MMU off, takes over the translation tables
+//! and the exception vectors of the boot core, switches to its own stack and checks in by counting
+//! itself in `ONLINE` before running the common entry.
+
+use super::{start_core, CoreDesc, EnableMethod};
+use crate::{
+    bsp::{self, config::MmuGranule},
+    cpu::{self, cache, smp::SingleCoreCell, timer, tlb},
+    memory::{AddrMapper, Address, AddressRange, Physical, Virtual},
+};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use cortex_a::regs::*;
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("secondary.s"));
+
+/// Number of cores `wake_all` can start besides the boot core.
+const MAX_SECONDARIES: usize = bsp::cpu::MAX_CORES - 1;
+
+/// Stack size of a secondary core.
+const STACK_SIZE: usize = 4 * MmuGranule::SIZE;
+
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+/// What a secondary core needs to join the boot core's address space, read by `__secondary_entry`
+/// with the MMU and the caches off.
+///
+/// The layout is shared with `secondary.s` and must be changed in both places at once.
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SecondaryBoot {
+    mair: u64,
+    tcr: u64,
+    ttbr0: u64,
+    ttbr1: u64,
+    vbar: u64,
+    sctlr: u64,
+    stack_end: u64,
+    /// Virtual address of `secondary_main`.
+    rust_entry: u64,
+    slot: u64,
+    main: u64,
+}
+
+/// Number of secondary cores that checked in.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Bit `n` is set once the core of slot `n` checked in.
+static READY: AtomicUsize = AtomicUsize::new(0);
+
+static BOOT: SingleCoreCell<[SecondaryBoot; MAX_SECONDARIES]> =
+    SingleCoreCell::new([SecondaryBoot::zeroed(); MAX_SECONDARIES]);
+
+static STACKS: SingleCoreCell<[Stack; MAX_SECONDARIES]> =
+    SingleCoreCell::new([Stack([0; STACK_SIZE]); MAX_SECONDARIES]);
+
+extern "C" {
+    fn __secondary_entry();
+    fn __secondary_entry_spin();
+    static mut __spin_boot_paddr: u64;
+}
+
+impl SecondaryBoot {
+    const fn zeroed() -> Self {
+        Self {
+            mair: 0,
+            tcr: 0,
+            ttbr0: 0,
+            ttbr1: 0,
+            vbar: 0,
+            sctlr: 0,
+            stack_end: 0,
+            rust_entry: 0,
+            slot: 0,
+            main: 0,
+        }
+    }
+}
+
+/// The first Rust code of a secondary core, on its own stack with the MMU on.
+extern "C" fn secondary_main(slot: usize, main: extern "C" fn() -> !) -> ! {
+    READY.fetch_or(1 << slot, Ordering::SeqCst);
+    ONLINE.fetch_add(1, Ordering::SeqCst);
+    cpu::send_event();
+    main()
+}
+
+/// Physical address of the kernel code or data at `vaddr`, as a core with the MMU off sees it.
+fn physical(vaddr: usize) -> Result<Address<Physical>, &'static str> {
+    tlb::translate(Address::new(vaddr)).ok_or("Secondary boot code or data not mapped")
+}
+
+/// Fill in the boot data of `slot` and push it to memory, where the core reads it with its caches
+/// off. Returns its physical address.
+fn prepare(slot: usize, main: extern "C" fn() -> !) -> Result<Address<Physical>, &'static str> {
+    let stack_end = STACKS.with(|stacks| stacks[slot].0.as_ptr_range().end as u64);
+    BOOT.with(|boot| {
+        let boot = &mut boot[slot];
+        *boot = SecondaryBoot {
+            mair: MAIR_EL1.get(),
+            tcr: TCR_EL1.get(),
+            ttbr0: TTBR0_EL1.get(),
+            ttbr1: TTBR1_EL1.get(),
+            vbar: VBAR_EL1.get(),
+            sctlr: SCTLR_EL1.get(),
+            stack_end,
+            rust_entry: secondary_main as usize as u64,
+            slot: slot as u64,
+            main: main as usize as u64,
+        };
+
+        let vaddr = boot as *const SecondaryBoot as usize;
+        cache::clean_dcache_range(AddressRange::<Virtual>::new_raw(
+            vaddr,
+            core::mem::size_of::<SecondaryBoot>(),
+        ));
+        physical(vaddr)
+    })
+}
+
+/// Leave the physical address of a spin table core's boot data where `__secondary_entry_spin`
+/// looks for it.
+unsafe fn publish_spin_boot(boot: Address<Physical>) {
+    __spin_boot_paddr = boot.into_usize() as u64;
+    cache::clean_dcache_range(AddressRange::<Virtual>::new_raw(
+        &__spin_boot_paddr as *const u64 as usize,
+        core::mem::size_of::<u64>(),
+    ));
+}
+
+/// Spin until `count` cores are online or `deadline` passes. Returns whether they all made it.
+fn wait_online(count: usize, deadline: u64) -> bool {
+    while ONLINE.load(Ordering::SeqCst) < count {
+        if timer::counter() > deadline {
+            return false;
+        }
+        cpu::spin_hint();
+    }
+    true
+}
+
+/// Start every core of `cores` but the boot core at `main` and wait until all have checked in,
+/// giving up on those that did not after `timeout`.
+///
+/// PSCI cores are started all at once. Spin table cores are released one at a time, as they are
+/// handed their boot data through a single variable, and once one of them misses the deadline no
+/// further cores are started. Returns the number of cores online.
+///
+/// # Safety
+///
+/// - Must be called once, on the boot core, with the final kernel translation tables and
+///   exception vectors installed. TTBR0 must still identity map the kernel image, i.e.
+///   `mmu::identity_unmap_low_half` must not have run yet.
+/// - For a spin table, `MAPPER` must map the release address writable.
+pub unsafe fn wake_all<MAPPER: AddrMapper>(
+    cores: &[CoreDesc],
+    main: extern "C" fn() -> !,
+    timeout: Duration,
+) -> Result<usize, &'static str> {
+    let deadline = timer::counter().saturating_add(timer::ticks(timeout));
+    let mut started = 0;
+    let mut slots = [0; MAX_SECONDARIES];
+    let mut stalled = false;
+    for core in cores.iter().filter(|core| core.id() != super::core_id()) {
+        if started == MAX_SECONDARIES {
+            println!("Core {:#x} skipped, too many cores", core.id());
+            continue;
+        }
+        let boot = prepare(started, main)?;
+        let result = match core.method {
+            EnableMethod::Psci => start_core::<MAPPER>(
+                core.mpidr,
+                core.method,
+                physical(__secondary_entry as usize)?,
+                boot.into_usize() as u64,
+            ),
+            EnableMethod::SpinTable { .. } => {
+                publish_spin_boot(boot);
+                start_core::<MAPPER>(
+                    core.mpidr,
+                    core.method,
+                    physical(__secondary_entry_spin as usize)?,
+                    0,
+                )
+            }
+        };
+        if let Err(err) = result {
+            println!("Core {:#x} failed to start: {}", core.id(), err);
+            continue;
+        }
+        slots[started] = core.id();
+        started += 1;
+
+        if let EnableMethod::SpinTable { .. } = core.method {
+            // The next spin table core is handed its boot data through the same variable. Releasing
+            // it before this one checked in would leave a late core with the wrong slot and stack.
+            if !wait_online(started, deadline) {
+                stalled = true;
+                println!("Spin table stalled, no further cores released");
+                break;
+            }
+        }
+    }
+
+    if !wait_online(started, deadline) || stalled {
+        let ready = READY.load(Ordering::SeqCst);
+        for (slot, id) in slots.iter().enumerate().take(started) {
+            if ready & (1 << slot) == 0 {
+                println!("Core {:#x} did not check in", id);
+            }
+        }
+        return Err("Secondary cores failed to start");
+    }
+    Ok(ONLINE.load(Ordering::SeqCst))
+}
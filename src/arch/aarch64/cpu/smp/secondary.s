@@ -0,0 +1,53 @@
+/// Entry of the cores started by `wake_all`, running from physical addresses with the MMU and the
+/// caches off.
+///
+/// x0: physical address of the core's `SecondaryBoot`, see `secondary.rs` for the layout.
+.section .text
+.global __secondary_entry
+__secondary_entry:
+    // Only an EL1 entry is set up for, park the core otherwise.
+    mrs    x1, CurrentEL
+    cmp    x1, #(1 << 2)
+    b.ne   2f
+
+    // Take over the translation setup of the boot core.
+    ldp    x1, x2, [x0, #8 * 0]
+    msr    MAIR_EL1, x1
+    msr    TCR_EL1, x2
+    ldp    x1, x2, [x0, #8 * 2]
+    msr    TTBR0_EL1, x1
+    msr    TTBR1_EL1, x2
+    ldp    x1, x2, [x0, #8 * 4]
+    msr    VBAR_EL1, x1
+    isb
+    tlbi   vmalle1
+    dsb    nsh
+    msr    SCTLR_EL1, x2
+    isb
+
+    // TTBR0 still identity maps this code, continue at the virtual address of the Rust entry.
+    ldp    x1, x2, [x0, #8 * 6]
+    ldp    x3, x4, [x0, #8 * 8]
+    mov    sp, x1
+    mov    x0, x3
+    mov    x1, x4
+    br     x2
+
+2:  wfe
+    b      2b
+
+/// Entry of the cores released from a spin table, which pass nothing in x0. `wake_all` releases
+/// them one at a time and leaves the physical address of the core's `SecondaryBoot` in
+/// `__spin_boot_paddr`.
+.global __secondary_entry_spin
+__secondary_entry_spin:
+    adrp   x0, __spin_boot_paddr
+    add    x0, x0, :lo12:__spin_boot_paddr
+    ldr    x0, [x0]
+    b      __secondary_entry
+
+.section .data
+.balign 8
+.global __spin_boot_paddr
+__spin_boot_paddr:
+    .quad  0
@@ -0,0 +1,29 @@
+//! Spin table bringup, for firmware without PSCI.
+//!
+//! The firmware parks each secondary core in a `wfe` loop that polls the core's
+//! `cpu-release-addr` until it holds a non-zero entry address, then jumps there with the MMU and
+//! the caches off.
+
+use crate::{
+    common::volatile::Mmio,
+    cpu::cache,
+    memory::{AddrMapper, Address, AddressRange, Physical},
+};
+
+/// Release the core spinning on `release_addr` to `entry`.
+///
+/// # Safety
+///
+/// - `release_addr` must be the core's `cpu-release-addr` and `MAPPER` must map it writable.
+/// - `entry` must be code the core can run with the MMU and the caches off.
+pub unsafe fn release<MAPPER: AddrMapper>(
+    release_addr: Address<Physical>,
+    entry: Address<Physical>,
+) {
+    let vaddr = MAPPER::map_to_vaddr(release_addr);
+    Mmio::<u64>::new(vaddr.into_usize()).write(entry.into_usize() as u64);
+
+    // The spinning core reads with its caches off, so the write has to reach memory first.
+    cache::clean_dcache_range(AddressRange::new(vaddr, core::mem::size_of::<u64>()));
+    asm!("sev", options(nomem, nostack));
+}
@@ -0,0 +1,36 @@
+//! EL1 physical timer of the generic timer.
+
+use super::barrier;
+use core::time::Duration;
+use cortex_a::regs::*;
+
+/// Frequency of the system counter in Hz.
+#[inline(always)]
+pub fn frequency() -> u64 {
+    CNTFRQ_EL0.get()
+}
+
+/// Current value of the system counter.
+#[inline(always)]
+pub fn counter() -> u64 {
+    // Keep the read from being hoisted above earlier instructions.
+    barrier::isb();
+    CNTPCT_EL0.get()
+}
+
+/// Convert `duration` into counter ticks, saturating.
+pub fn ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos() * frequency() as u128 / 1_000_000_000;
+    ticks.min(u64::MAX as u128) as u64
+}
+
+/// Raise the timer interrupt `ticks` from now, replacing the pending deadline.
+pub fn set_deadline(ticks: u64) {
+    CNTP_CVAL_EL0.set(counter().saturating_add(ticks));
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+/// Stop the timer, a raised interrupt is withdrawn.
+pub fn disable() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR);
+}
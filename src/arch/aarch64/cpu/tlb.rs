@@ -0,0 +1,61 @@
+//! TLB maintenance and address translation queries.
+
+use crate::{
+    common::bit,
+    cpu::barrier,
+    memory::{Address, Physical, Virtual},
+};
+
+/// Invalidate the TLB entries of `vaddr` for all ASIDs on the inner shareable domain.
+#[inline(always)]
+pub fn invalidate_page(vaddr: Address<Virtual>) {
+    // The TLBI operand holds VA[55:12] regardless of the granule size.
+    let operand = vaddr.into_usize() >> 12;
+    barrier::dsb_ishst();
+    unsafe { asm!("tlbi vaae1is, {}", in(reg) operand, options(nostack)) };
+    barrier::dsb_ish();
+    barrier::isb();
+}
+
+/// Invalidate all EL1 TLB entries on the inner shareable domain, including cached table walks.
+#[inline(always)]
+pub fn invalidate_all() {
+    barrier::dsb_ishst();
+    unsafe { asm!("tlbi vmalle1is", options(nostack)) };
+    barrier::dsb_ish();
+    barrier::isb();
+}
+
+/// Ask the MMU whether EL1 can currently write to `vaddr`.
+///
+/// With the MMU disabled every address translates flat, so this only rejects addresses once
+/// translation is enabled.
+pub fn is_writable(vaddr: Address<Virtual>) -> bool {
+    let par: u64;
+    unsafe {
+        asm!("at s1e1w, {}", in(reg) vaddr.into_usize(), options(nostack));
+        barrier::isb();
+        asm!("mrs {}, PAR_EL1", out(reg) par, options(nomem, nostack));
+    }
+    // PAR_EL1.F is set if the translation faulted.
+    par & 1 == 0
+}
+
+/// Ask the MMU which physical address EL1 reads from at `vaddr`, or `None` if the read would fault.
+///
+/// With the MMU disabled every address translates flat.
+pub fn translate(vaddr: Address<Virtual>) -> Option<Address<Physical>> {
+    let par: u64;
+    unsafe {
+        asm!("at s1e1r, {}", in(reg) vaddr.into_usize(), options(nostack));
+        barrier::isb();
+        asm!("mrs {}, PAR_EL1", out(reg) par, options(nomem, nostack));
+    }
+    if par & 1 != 0 {
+        return None;
+    }
+    // PAR_EL1.PA holds bits [47:12] of the output address whatever the granule, the rest is the
+    // offset into the page.
+    let page = bit::extract(par as usize, 12, 36) << 12;
+    Some(Address::new(page | bit::extract(vaddr.into_usize(), 0, 12)))
+}
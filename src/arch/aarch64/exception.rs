@@ -1,18 +1,41 @@
-use core::{cell::UnsafeCell, fmt};
-use cortex_a::{barrier, regs::*};
+use crate::{
+    bsp::{
+        self,
+        config::{self, MmuGranule},
+    },
+    common,
+    cpu::barrier,
+    memory::{Address, Virtual},
+    mmu::mmu::poison,
+};
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use cortex_a::regs::*;
 use register::InMemoryRegister;
 use tock_registers::registers::Readable;
 
 // Assembly counterpart to this file.
-global_asm!(include_str!("exception.s"));
+global_asm!(
+    include_str!("exception.s"),
+    kernel_stack_vbase = const config::KERNEL_STACK_VBASE,
+    granule_size = const MmuGranule::SIZE,
+    max_cores = const bsp::cpu::MAX_CORES,
+);
 
 /// Wrapper struct for memory copy of SPSR_EL1.
 #[repr(transparent)]
 struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
 
 /// The exception context as it is stored on the stack on exception entry.
+///
+/// The layout is shared with `CALL_WITH_CONTEXT` in `exception.s` and must be changed in both
+/// places at once. Handlers receive it mutably: whatever it holds when the handler returns is
+/// restored by `__exception_restore_context` before the `eret`.
 #[repr(C)]
-struct ExceptionContext {
+pub struct ExceptionContext {
     /// General Purpose Registers.
     gpr: [u64; 30],
 
@@ -24,30 +47,190 @@ struct ExceptionContext {
 
     /// Saved program status.
     spsr_el1: SpsrEL1,
+
+    /// Exception syndrome, captured on entry.
+    esr_el1: EsrEL1,
+
+    /// Fault address, captured on entry.
+    far_el1: u64,
 }
 
-/// Wrapper struct for pretty printing ESR_EL1.
-struct EsrEL1;
+/// Wrapper struct for memory copy of ESR_EL1.
+#[repr(transparent)]
+struct EsrEL1(InMemoryRegister<u64, ESR_EL1::Register>);
+
+impl ExceptionContext {
+    /// Argument register `n`, i.e. `x<n>` of the interrupted code.
+    ///
+    /// Only x0 to x7 carry arguments in the AAPCS64.
+    #[inline(always)]
+    pub fn arg(&self, n: usize) -> u64 {
+        assert!(n < 8, "x{} is not an argument register", n);
+        self.gpr[n]
+    }
+
+    /// Set the value the interrupted code finds in x0 when it resumes.
+    #[inline(always)]
+    pub fn set_return(&mut self, value: u64) {
+        self.gpr[0] = value;
+    }
+
+    /// Address of the instruction the interrupted code resumes at.
+    #[inline(always)]
+    pub fn pc(&self) -> u64 {
+        self.elr_el1
+    }
+
+    /// Resume the interrupted code at `pc` instead.
+    #[inline(always)]
+    pub fn set_pc(&mut self, pc: u64) {
+        self.elr_el1 = pc;
+    }
+
+    /// Resume after the instruction at `pc`, e.g. one that was emulated or should be ignored.
+    #[inline(always)]
+    pub fn skip_instr(&mut self) {
+        self.elr_el1 += 4;
+    }
+}
 
 /// Prints verbose information about the exception and then panics.
 fn default_exception_handler(e: &ExceptionContext) {
+    if let Some(kind) = poison::lookup(e.far_el1 as usize) {
+        println!("Fault address {:#018x} is in a {:?} page", e.far_el1, kind);
+    }
+    let far = Address::<Virtual>::new(e.far_el1 as usize);
+    if !far.is_kernel_half() && !far.is_user_half() {
+        println!(
+            "Fault address {:#018x} is in neither translation table half",
+            e.far_el1
+        );
+    }
     panic!(
         "\n\nCPU Exception!\n\
          FAR_EL1: {:#018x}\n\
          {}\n\
          {}",
-        FAR_EL1.get(),
-        EsrEL1 {},
-        e
+        e.far_el1, e.esr_el1, e
     );
 }
 
+/// Set while `read_faults` expects its load to fault.
+static PROBE_ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the handler if the armed probe load faulted.
+static PROBE_FAULTED: AtomicBool = AtomicBool::new(false);
+
+/// Check if reading `vaddr` at EL1 faults, recovering from the fault instead of panicking.
+pub fn read_faults(vaddr: usize) -> bool {
+    PROBE_FAULTED.store(false, Ordering::SeqCst);
+    PROBE_ARMED.store(true, Ordering::SeqCst);
+    unsafe {
+        // A single instruction, so the handler can skip it without leaving a partial access.
+        asm!("ldr {}, [{}]", out(reg) _, in(reg) vaddr, options(nostack, readonly));
+    }
+    PROBE_ARMED.store(false, Ordering::SeqCst);
+    PROBE_FAULTED.load(Ordering::SeqCst)
+}
+
+/// `VBAR_EL1` ignores the low 11 bits, the table must be aligned to them.
+const VECTOR_TABLE_ALIGNMENT: usize = 0x800;
+
+/// The `svc` immediate `vector_self_test` raises, not used for anything else.
+#[cfg(feature = "vector_selftest")]
+const VECTOR_TEST_SVC: u64 = 0xffff;
+
+/// Set by the handler when the `svc` of `vector_self_test` arrived.
+#[cfg(feature = "vector_selftest")]
+static VECTOR_TEST_SEEN: AtomicBool = AtomicBool::new(false);
+
+/// The `brk` immediate of a breakpoint to report and resume from, i.e. `brk #0x5354`.
+///
+/// Any other `brk` is fatal: `core::intrinsics::abort` and the traps the compiler emits for
+/// unreachable code lower to `brk #1`.
+pub const DEBUG_BRK_IMM: u64 = 0x5354;
+
+/// `ESR_EL1.EC` of an FP/SIMD instruction trapped by `CPACR_EL1.FPEN`.
+const EC_TRAPPED_FP: u64 = 0b00_0111;
+
+/// Handle a synchronous exception the interrupted code can resume from.
+///
+/// Returns `false` if the exception is not one of those, leaving `e` untouched.
+fn handle_resumable_synchronous(e: &mut ExceptionContext) -> bool {
+    if e.esr_el1.0.read(ESR_EL1::EC) == EC_TRAPPED_FP {
+        // Lazily enabled FP/SIMD, see `cpu::trap_fp_simd_el0`. `ELR_EL1` points at the trapped
+        // instruction, so it is retried.
+        crate::cpu::enable_fp_simd();
+        return true;
+    }
+
+    match e.esr_el1.0.read_as_enum(ESR_EL1::EC) {
+        // `ELR_EL1` points at the `brk` itself, resume after it.
+        Some(ESR_EL1::EC::Value::Brk64) if e.esr_el1.0.read(ESR_EL1::ISS) == DEBUG_BRK_IMM => {
+            println!("Breakpoint at {:#018x}", e.pc());
+            e.skip_instr();
+            true
+        }
+        // Raised by `vector_self_test`. `ELR_EL1` already points after the `svc`.
+        #[cfg(feature = "vector_selftest")]
+        Some(ESR_EL1::EC::Value::SVC64) if e.esr_el1.0.read(ESR_EL1::ISS) == VECTOR_TEST_SVC => {
+            VECTOR_TEST_SEEN.store(true, Ordering::SeqCst);
+            true
+        }
+        // The load in `read_faults`, skip it.
+        Some(ESR_EL1::EC::Value::DataAbortCurrentEL) if PROBE_ARMED.load(Ordering::SeqCst) => {
+            PROBE_FAULTED.store(true, Ordering::SeqCst);
+            e.skip_instr();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Hand the pending interrupt to its driver. Returns `false` if nobody handled it.
+#[cfg(feature = "watchdog")]
+fn dispatch_irq() -> bool {
+    use crate::bsp::gic;
+
+    match gic::acknowledge() {
+        Some(intid) => {
+            let handled = crate::watchdog::handle_irq(intid);
+            gic::end_of_interrupt(intid);
+            handled
+        }
+        None => false,
+    }
+}
+
+/// No interrupt is enabled without a driver for it.
+#[cfg(not(feature = "watchdog"))]
+fn dispatch_irq() -> bool {
+    false
+}
+
+//------------------------------------------------------------------------------
+// Handler ABI
+//------------------------------------------------------------------------------
+//
+// Every handler below is called by `CALL_WITH_CONTEXT` in `exception.s`:
+//
+// - The symbol name must match the one used in the vector table.
+// - It takes a single `&mut ExceptionContext` pointing to the context saved on the stack.
+// - Returning normally resumes the interrupted code with the (possibly modified) context, e.g.
+//   after `skip_instr` or with a value passed to `set_return`.
+// - Handlers must not return for exceptions they did not handle; `default_exception_handler` is
+//   the catch-all.
+
 //------------------------------------------------------------------------------
 // Current, EL0
 //------------------------------------------------------------------------------
 
 #[no_mangle]
 unsafe extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    if handle_resumable_synchronous(e) {
+        return;
+    }
+
     default_exception_handler(e);
 }
 
@@ -67,11 +250,32 @@ unsafe extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
 
 #[no_mangle]
 unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    if handle_resumable_synchronous(e) {
+        return;
+    }
+
     default_exception_handler(e);
 }
 
+/// Entered on the emergency stack if the exception context did not fit on the kernel stack.
+#[no_mangle]
+unsafe extern "C" fn current_elx_stack_overflow(e: &mut ExceptionContext) {
+    panic!(
+        "\n\nKernel stack overflow!\n\
+         FAR_EL1: {:#018x}\n\
+         {}\n\
+         {}",
+        e.far_el1, e.esr_el1, e
+    );
+}
+
 #[no_mangle]
 unsafe extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
+    crate::cpu::check_halt();
+    if dispatch_irq() {
+        return;
+    }
+
     default_exception_handler(e);
 }
 
@@ -86,6 +290,10 @@ unsafe extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
 
 #[no_mangle]
 unsafe extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
+    if handle_resumable_synchronous(e) {
+        return;
+    }
+
     default_exception_handler(e);
 }
 
@@ -122,7 +330,7 @@ unsafe extern "C" fn lower_aarch32_serror(e: &mut ExceptionContext) {
 #[rustfmt::skip]
 impl fmt::Display for EsrEL1 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let esr_el1 = ESR_EL1.extract();
+        let esr_el1 = &self.0;
 
         // Raw print of whole register.
         writeln!(f, "ESR_EL1: {:#010x}", esr_el1.get())?;
@@ -133,6 +341,11 @@ impl fmt::Display for EsrEL1 {
         // Exception class, translation.
         let ec_translation = match esr_el1.read_as_enum(ESR_EL1::EC) {
             Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => "Data Abort, current EL",
+            Some(ESR_EL1::EC::Value::BreakpointLowerEL) => "Breakpoint, lower EL",
+            Some(ESR_EL1::EC::Value::BreakpointCurrentEL) => "Breakpoint, current EL",
+            Some(ESR_EL1::EC::Value::WatchpointLowerEL) => "Watchpoint, lower EL",
+            Some(ESR_EL1::EC::Value::WatchpointCurrentEL) => "Watchpoint, current EL",
+            Some(ESR_EL1::EC::Value::Brk64) => "BRK instruction",
             _ => "N/A",
         };
         writeln!(f, " - {}", ec_translation)?;
@@ -216,8 +429,27 @@ pub unsafe fn handling_init() {
         static __exception_vector_start: UnsafeCell<()>;
     }
 
-    VBAR_EL1.set(__exception_vector_start.get() as u64);
+    let vbar = __exception_vector_start.get() as usize;
+    assert!(
+        common::is_aligned(vbar, VECTOR_TABLE_ALIGNMENT),
+        "Exception vectors at {:#x} are not 2 KiB aligned",
+        vbar
+    );
+    VBAR_EL1.set(vbar as u64);
 
     // Force VBAR update to complete before next instruction.
-    barrier::isb(barrier::SY);
+    barrier::isb();
+}
+
+/// Raise a harmless synchronous exception and check that it reached the handler, i.e. that
+/// `VBAR_EL1` points at working vectors.
+#[cfg(feature = "vector_selftest")]
+pub fn vector_self_test() -> Result<(), &'static str> {
+    VECTOR_TEST_SEEN.store(false, Ordering::SeqCst);
+    // The immediate is `VECTOR_TEST_SVC`. The handler writes memory, so no `nomem`.
+    unsafe { asm!("svc #0xffff", options(nostack)) };
+    if !VECTOR_TEST_SEEN.load(Ordering::SeqCst) {
+        return Err("svc did not reach the exception handler");
+    }
+    Ok(())
 }
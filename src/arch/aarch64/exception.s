@@ -4,9 +4,13 @@
 
 /// Call the function provided by parameter `\handler` after saving the exception context. Provide
 /// the context as the first parameter to '\handler'.
+///
+/// The saved context matches `ExceptionContext` in `exception.rs`. If `\handler` returns, the
+/// context is restored from the stack, including any changes the handler made to it, and the
+/// exception returns through `eret`.
 .macro CALL_WITH_CONTEXT handler
     // Make room on the stack for the exception context.
-    sub    sp,  sp,  #16 * 17
+    sub    sp,  sp,  #16 * 18
 
     // Store all general purpose registers on the stack.
     stp    x0,  x1,  [sp, #16 * 0]
@@ -29,8 +33,14 @@
     mrs    x1,  ELR_EL1
     mrs    x2,  SPSR_EL1
 
+    // Add the syndrome (ESR_EL1) and fault address (FAR_EL1) before a nested exception can
+    // overwrite them.
+    mrs    x3,  ESR_EL1
+    mrs    x4,  FAR_EL1
+
     stp    lr,  x1,  [sp, #16 * 15]
-    str    x2,       [sp, #16 * 16]
+    stp    x2,  x3,  [sp, #16 * 16]
+    str    x4,       [sp, #16 * 17]
 
     // x0 is the first argument for the function called through `\handler`.
     mov    x0,  sp
@@ -42,6 +52,42 @@
     b      __exception_restore_context
 .endm
 
+/// Bottom of the kernel stack mapped by the bootloader, with an unmapped guard page of one granule
+/// below it. Both are passed in by `global_asm!` from `bsp::config`.
+.equ KERNEL_STACK_BOTTOM,       {kernel_stack_vbase}
+.equ KERNEL_STACK_GUARD_START,  (KERNEL_STACK_BOTTOM - {granule_size})
+
+/// The lowest bytes of the kernel stack, too few for the exception context and the handler. A
+/// power of two, as it is tested for as an aligned window.
+.equ KERNEL_STACK_RESERVE,      512
+.if KERNEL_STACK_RESERVE < 16 * 18
+.error "KERNEL_STACK_RESERVE does not fit the exception context"
+.endif
+.if KERNEL_STACK_BOTTOM & (KERNEL_STACK_RESERVE - 1)
+.error "KERNEL_STACK_BOTTOM is not aligned to KERNEL_STACK_RESERVE"
+.endif
+
+/// Size of the emergency stack of each core.
+.equ EMERGENCY_STACK_SIZE,      0x2000
+
+/// Exclusive-or `\reg` with the constant `\value`, one run of set bits at a time, as only runs are
+/// encodable as logical immediates.
+.macro EOR_CONST reg, value
+.if (\value) != 0
+    eor    \reg, \reg, #((\value) & ~((\value) + ((\value) & -(\value))))
+    EOR_CONST \reg, ((\value) & ((\value) + ((\value) & -(\value))))
+.endif
+.endm
+
+/// Branch to `\label` if x0 is in `[\base, \base + \size)`, `\base` being aligned to the power of two
+/// `\size`. Leaves x0 as it was.
+.macro B_IN_WINDOW base, size, label
+    EOR_CONST x0, \base
+    tst    x0,  #~(\size - 1)
+    EOR_CONST x0, \base
+    b.eq   \label
+.endm
+
 .macro FIQ_SUSPEND
 1:  wfe
     b      1b
@@ -76,7 +122,7 @@ __exception_vector_start:
 
 // Current exception level with SP_ELx, x > 0.
 .org 0x200
-    CALL_WITH_CONTEXT current_elx_synchronous
+    b      __current_elx_synchronous_stack_checked
 .org 0x280
     CALL_WITH_CONTEXT current_elx_irq
 .org 0x300
@@ -110,6 +156,54 @@ __exception_vector_start:
 //--------------------------------------------------------------------------------------------------
 .section .text
 
+// Check that the exception context still fits on the kernel stack before saving it. A fault in the
+// guard page below the stack would otherwise fault again on every push, so it is handled on an
+// emergency stack instead.
+//
+// No register is free yet, and every one may belong to the interrupted code, SP_EL0 included. The
+// check swaps sp into x0 arithmetically instead, with x0 kept in sp as the sum of both.
+__current_elx_synchronous_stack_checked:
+    add    sp,  sp,  x0
+    sub    x0,  sp,  x0
+
+    // In the guard page or the reserve at the bottom of the stack. Other stacks never match.
+    B_IN_WINDOW KERNEL_STACK_GUARD_START, {granule_size}, 2f
+    B_IN_WINDOW KERNEL_STACK_BOTTOM, KERNEL_STACK_RESERVE, 2f
+
+    sub    x0,  sp,  x0
+    sub    sp,  sp,  x0
+    CALL_WITH_CONTEXT current_elx_synchronous
+
+2:
+    // The overflow is fatal and the context does not hold sp, so sp keeps x0 while x0 looks up the
+    // emergency stack of this core, indexed by `core_id` like the other per-core data.
+    sub    sp,  sp,  x0
+    mrs    x0,  MPIDR_EL1
+    tst    x0,  #0xff00000000
+    b.ne   3f
+    and    x0,  x0,  #0xffffff
+
+    .set   EMERGENCY_CORE, 0
+    .rept  {max_cores}
+    cmp    x0,  #EMERGENCY_CORE
+    b.ne   1f
+    ldr    x0,  =(__emergency_stacks_start + (EMERGENCY_CORE + 1) * EMERGENCY_STACK_SIZE)
+    b      4f
+1:
+    .set   EMERGENCY_CORE, EMERGENCY_CORE + 1
+    .endr
+
+    // A core without an emergency stack can only stop.
+3:  wfe
+    b      3b
+
+4:
+    // Swap x0 and sp, leaving sp at the top of the emergency stack.
+    add    sp,  sp,  x0
+    sub    x0,  sp,  x0
+    sub    sp,  sp,  x0
+    CALL_WITH_CONTEXT current_elx_stack_overflow
+
 __exception_restore_context:
     ldr    w19,      [sp, #16 * 16]
     ldp    lr,  x20, [sp, #16 * 15]
@@ -133,6 +227,16 @@ __exception_restore_context:
     ldp    x26, x27, [sp, #16 * 13]
     ldp    x28, x29, [sp, #16 * 14]
 
-    add    sp,  sp,  #16 * 17
+    add    sp,  sp,  #16 * 18
 
     eret
+
+//--------------------------------------------------------------------------------------------------
+// Emergency stacks for reporting a kernel stack overflow, one per core
+//--------------------------------------------------------------------------------------------------
+.section .bss
+
+.align 4
+__emergency_stacks_start:
+    .space {max_cores} * EMERGENCY_STACK_SIZE
+__emergency_stacks_end:
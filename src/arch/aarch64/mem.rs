@@ -0,0 +1,8 @@
+//! `memcpy`, `memmove` and `memset`.
+//!
+//! The compiler lowers `core::ptr::copy`, large moves and array initializations to calls of these,
+//! and there is no libc to provide them. They are written in assembly so the compiler cannot turn
+//! their loops back into calls to themselves.
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("mem.s"));
@@ -0,0 +1,77 @@
+/// The memory functions the compiler emits calls to, which a freestanding program has to provide.
+///
+/// Pointers that are both 8 byte aligned are handled 16 bytes at a time, everything else bytewise.
+/// No unaligned accesses are made, so these also work with the MMU off, where all memory is
+/// Device memory.
+
+/// void *memcpy(void *dst, const void *src, size_t n)
+.section .text.memcpy
+.global memcpy
+memcpy:
+    mov    x3, x0
+    orr    x4, x0, x1
+    tst    x4, #7
+    b.ne   2f
+1:  cmp    x2, #16
+    b.lo   2f
+    ldp    x5, x6, [x1], #16
+    stp    x5, x6, [x3], #16
+    sub    x2, x2, #16
+    b      1b
+2:  cbz    x2, 3f
+    ldrb   w5, [x1], #1
+    strb   w5, [x3], #1
+    sub    x2, x2, #1
+    b      2b
+3:  ret
+
+/// void *memmove(void *dst, const void *src, size_t n)
+.section .text.memmove
+.global memmove
+memmove:
+    // Copying forwards is safe unless dst lies within [src, src + n).
+    sub    x4, x0, x1
+    cmp    x4, x2
+    b.hs   memcpy
+
+    // Copy backwards from the end.
+    add    x3, x0, x2
+    add    x1, x1, x2
+    orr    x4, x3, x1
+    tst    x4, #7
+    b.ne   2f
+1:  cmp    x2, #16
+    b.lo   2f
+    ldp    x5, x6, [x1, #-16]!
+    stp    x5, x6, [x3, #-16]!
+    sub    x2, x2, #16
+    b      1b
+2:  cbz    x2, 3f
+    ldrb   w5, [x1, #-1]!
+    strb   w5, [x3, #-1]!
+    sub    x2, x2, #1
+    b      2b
+3:  ret
+
+/// void *memset(void *dst, int c, size_t n)
+.section .text.memset
+.global memset
+memset:
+    mov    x3, x0
+    and    x1, x1, #0xff
+    tst    x0, #7
+    b.ne   2f
+    // Replicate the byte into all of x1.
+    orr    x1, x1, x1, lsl #8
+    orr    x1, x1, x1, lsl #16
+    orr    x1, x1, x1, lsl #32
+1:  cmp    x2, #16
+    b.lo   2f
+    stp    x1, x1, [x3], #16
+    sub    x2, x2, #16
+    b      1b
+2:  cbz    x2, 3f
+    strb   w1, [x3], #1
+    sub    x2, x2, #1
+    b      2b
+3:  ret
@@ -14,15 +14,21 @@
 //! crate::memory::mmu::translation_table::arch_translation_table
 
 use crate::{
-    bsp::config::MmuGranule,
+    arch::{TTBR0_EL1, TTBR1_EL1},
+    bsp::config::{self, MmuGranule},
+    common::bit,
+    cpu::{self, barrier, tlb},
     memory::{
-        AccessPermissions, AddrMapper, Address, AddressRange, AttributeFields, MemAttributes,
-        PageAllocator, Physical, Virtual,
+        self, frame_refcount::FrameRefCount, AccessPermissions, AddrMapper, Address, AddressRange,
+        AttributeFields, MemAttributes, Page, PageAllocator, PhysFrame, Physical, Virtual,
     },
-    mmu::TranslationGranule,
+    mmu::{KernelVirtualLayout, TranslationGranule},
 };
-use core::{convert, marker::PhantomData, mem::ManuallyDrop};
-use cortex_a::regs::{RegisterReadWrite, MAIR_EL1, SCTLR_EL1, TCR_EL1, TTBR0_EL1, TTBR1_EL1};
+use core::{
+    convert::{self, TryFrom},
+    marker::PhantomData,
+};
+use cortex_a::regs::{RegisterReadWrite, MAIR_EL1, SCTLR_EL1, TCR_EL1};
 use register::{mmio::ReadWrite, register_bitfields, InMemoryRegister};
 use tock_registers::registers::{Readable, Writeable};
 
@@ -32,12 +38,117 @@ pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
 
 pub const ENTRY_PER_TABLE: usize = MmuGranule::SIZE >> 3;
 
+/// Size offset of the TTBR0 region.
+pub const T0SZ: u8 = (64 - config::TTBR0_VA_BITS) as u8;
+
+/// Size offset of the TTBR1 region.
+pub const T1SZ: u8 = (64 - config::TTBR1_VA_BITS) as u8;
+
+/// Address bits resolved by one level of translation.
+const BITS_PER_LEVEL: usize = MmuGranule::SHIFT - 3;
+
+/// `ID_AA64MMFR0_EL1.PARange` and `TCR_EL1.IPS` encoding of a 48 bit physical address size, the
+/// largest the descriptors can output without `FEAT_LPA`.
+const PA_RANGE_48_BITS: usize = 0b0101;
+
 // /// The min supported address space size.
 // pub const MIN_ADDR_SPACE_SIZE: usize = 1024 * 1024 * 1024; // 1 GiB
 
 // /// The max supported address space size.
 // pub const MAX_ADDR_SPACE_SIZE: usize = 32 * 1024 * 1024 * 1024; // 32 GiB
 
+/// Errors reported by the translation table operations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MmuError {
+    /// The physical and the virtual range of a mapping differ in size.
+    SizeMismatch {
+        physical: usize,
+        virtual_: usize,
+    },
+    /// A physical range does not start at a multiple of `alignment`.
+    UnalignedPhysical {
+        addr: usize,
+        alignment: usize,
+    },
+    /// A virtual range does not start at a multiple of `alignment`.
+    UnalignedVirtual {
+        addr: usize,
+        alignment: usize,
+    },
+    /// A physical address does not fit into the physical address size in use.
+    PhysicalOutOfRange {
+        addr: usize,
+        pa_bits: u32,
+    },
+    NotInitialized,
+    NotMapped,
+    AlreadyMappedInBlock,
+    AlreadyMappedInPage,
+    MappedInBlock,
+    BlockInLevel0,
+    NullTable,
+    TableNotMapped,
+    /// A virtual address in the gap between the TTBR0 and the TTBR1 half.
+    NotInEitherHalf {
+        addr: usize,
+    },
+    /// A virtual address in the other half than the one the region translates.
+    WrongHalf {
+        addr: usize,
+    },
+    /// An error passed through from the page allocator or the virtual layout.
+    Other(&'static str),
+}
+
+impl From<&'static str> for MmuError {
+    fn from(msg: &'static str) -> Self {
+        Self::Other(msg)
+    }
+}
+
+/// The messages the translation table operations returned as `&'static str` before `MmuError`.
+impl core::fmt::Display for MmuError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SizeMismatch { physical, virtual_ } => write!(
+                f,
+                "prange size {:#x} does not match vrange size {:#x}",
+                physical, virtual_
+            ),
+            Self::UnalignedPhysical { addr, alignment } => {
+                write!(f, "prange = {:#x} not aligned with {:#x}", addr, alignment)
+            }
+            Self::UnalignedVirtual { addr, alignment } => {
+                write!(f, "vrange = {:#x} not aligned with {:#x}", addr, alignment)
+            }
+            Self::PhysicalOutOfRange { addr, pa_bits } => write!(
+                f,
+                "Physical address {:#x} beyond the {} bit physical address size",
+                addr, pa_bits
+            ),
+            Self::NotInitialized => f.write_str("Translation table not initialized"),
+            Self::NotMapped => f.write_str("Address not mapped"),
+            Self::AlreadyMappedInBlock => f.write_str("Address already mapped in a block"),
+            Self::AlreadyMappedInPage => f.write_str("Address already mapped in a page"),
+            Self::MappedInBlock => f.write_str("Address mapped in a block"),
+            Self::BlockInLevel0 => f.write_str("Block descriptor cannot be in level0"),
+            Self::NullTable => f.write_str("Translation table address is null"),
+            Self::TableNotMapped => f.write_str("Translation table is not mapped"),
+            Self::NotInEitherHalf { addr } => write!(
+                f,
+                "Virtual address {:#x} in neither translation table half",
+                addr
+            ),
+            Self::WrongHalf { addr } => write!(
+                f,
+                "Virtual address {:#x} not in the region's translation table half",
+                addr
+            ),
+            Self::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MmuLevel {
     Level0,
@@ -54,6 +165,72 @@ impl MmuLevel {
             _ => None,
         }
     }
+
+    /// The level with the given number, 0 being the top level.
+    pub const fn from_index(index: usize) -> Option<MmuLevel> {
+        match index {
+            0 => Some(Self::Level0),
+            1 => Some(Self::Level1),
+            2 => Some(Self::Level2),
+            3 => Some(Self::Level3),
+            _ => None,
+        }
+    }
+
+    /// The number of this level, 0 being the top level.
+    pub const fn index(&self) -> usize {
+        match self {
+            Self::Level0 => 0,
+            Self::Level1 => 1,
+            Self::Level2 => 2,
+            Self::Level3 => 3,
+        }
+    }
+
+    /// The lowest address bit indexing the tables of this level.
+    const fn shift(&self) -> usize {
+        MmuGranule::SHIFT + (3 - self.index()) * BITS_PER_LEVEL
+    }
+
+    /// The address bits indexing a full table of this level.
+    pub const fn address_mask(&self) -> usize {
+        bit::insert(0, usize::MAX, self.shift(), BITS_PER_LEVEL)
+    }
+
+    /// The size of the memory an entry of this level maps, i.e. the block or page size.
+    pub const fn block_size(&self) -> usize {
+        1 << self.shift()
+    }
+}
+
+impl TryFrom<u64> for MmuLevel {
+    type Error = MmuError;
+
+    fn try_from(index: u64) -> Result<Self, Self::Error> {
+        Self::from_index(index as usize).ok_or(MmuError::Other("Invalid translation level"))
+    }
+}
+
+/// The level a table walk of a `va_bits` wide address space starts at, and the index mask of
+/// that level.
+///
+/// The first level only resolves the bits left over by the lower levels, so its table may use
+/// fewer than `ENTRY_PER_TABLE` entries.
+const fn walk_start(va_bits: usize) -> (MmuLevel, usize) {
+    assert!(va_bits <= 48 && va_bits > MmuGranule::SHIFT + BITS_PER_LEVEL);
+
+    let levels = (va_bits - MmuGranule::SHIFT + BITS_PER_LEVEL - 1) / BITS_PER_LEVEL;
+    let level = match MmuLevel::from_index(4 - levels) {
+        Some(level) => level,
+        None => unreachable!(),
+    };
+    (level, level.address_mask() & bit::mask(va_bits))
+}
+
+/// The index mask of the level after the one indexed by `mask`.
+#[inline(always)]
+const fn next_mask(mask: usize) -> usize {
+    (ENTRY_PER_TABLE - 1) << (mask.trailing_zeros() as usize - BITS_PER_LEVEL)
 }
 pub enum EntryType<'a> {
     Invalid,
@@ -123,6 +300,12 @@ register_bitfields! {u64,
         /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
         OUTPUT_ADDR OFFSET(crate::bsp::config::MmuGranule::SHIFT) NUMBITS(48 - crate::bsp::config::MmuGranule::SHIFT) [], // [47:m]
 
+        /// Not global: the translation only applies to the ASID it was cached for.
+        nG       OFFSET(11) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
             False = 0,
@@ -161,18 +344,18 @@ register_bitfields! {u64,
 /// A table descriptor for 64 KiB aperture.
 ///
 /// The output points to the next table.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct TableDescriptor {
     value: u64,
 }
 
-/// A page descriptor with 64 KiB aperture.
+/// A level 3 page descriptor.
 ///
 /// The output points to physical memory.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
-struct PageDescriptor {
+pub struct PageDescriptor {
     value: u64,
 }
 
@@ -196,20 +379,83 @@ pub struct TableSection {
     entries: [TableDescriptor; MmuGranule::SIZE / core::mem::size_of::<TableDescriptor>()],
 }
 
+// The walk indexes tables with `ENTRY_PER_TABLE` and allocates them a granule at a time, both have
+// to agree with the table layout.
+const _: () = assert!(
+    ENTRY_PER_TABLE == MmuGranule::SIZE / core::mem::size_of::<TableDescriptor>(),
+    "ENTRY_PER_TABLE does not match the descriptors per granule"
+);
+const _: () = assert!(
+    core::mem::size_of::<TableSection>() == MmuGranule::SIZE,
+    "A translation table does not fill a granule"
+);
+
+/// Kept out of line, `index_of_addr` runs for every level of every walk.
+#[cold]
+#[inline(never)]
+fn not_an_index_mask(mask: usize) -> ! {
+    panic!("{:#x} is not a table index mask", mask)
+}
+
+/// Allocate and zero a table for the next level and return the descriptor pointing to it.
+///
+/// Out of line so the walk in `map_page` stays compact, only one in `ENTRY_PER_TABLE` pages needs
+/// a new table.
+#[inline(never)]
+fn alloc_next_table<MAPPER: AddrMapper, ALLOC: PageAllocator>() -> Result<TableDescriptor, MmuError>
+{
+    let next_table = ALLOC::alloc_pages(1)?;
+    unsafe { memory::zero_pages::<MAPPER, ALLOC>(&next_table) }
+    let (paddr, _) = next_table.leak();
+    Ok(TableDescriptor::from_next_lvl_table_addr(paddr))
+}
+
 impl TableSection {
+    fn index_of_addr(vaddr: Address<Virtual>, mask: usize) -> usize {
+        let offset = mask.trailing_zeros() as usize;
+        let width = mask.count_ones() as usize;
+        if width > BITS_PER_LEVEL || mask != bit::insert(0, usize::MAX, offset, width) {
+            not_an_index_mask(mask);
+        }
+        bit::extract(vaddr.into_usize(), offset, width)
+    }
+
     pub fn entry_of_addr(&mut self, vaddr: Address<Virtual>, mask: usize) -> &mut TableDescriptor {
-        assert!(
-            (mask / (ENTRY_PER_TABLE - 1)).is_power_of_two(),
-            "{:#x} is not shifted by {:#x}",
-            mask,
-            ENTRY_PER_TABLE - 1
-        );
-        let idx = (vaddr.into_usize() & mask) >> mask.trailing_zeros();
-        &mut self.entries[idx]
+        &mut self.entries[Self::index_of_addr(vaddr, mask)]
+    }
+
+    /// Check if no entry of the table is valid.
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|entry| !entry.is_valid())
     }
 
-    pub unsafe fn from_paddr<MAPPER: AddrMapper>(paddr: Address<Physical>) -> &'static mut Self {
-        &mut *(MAPPER::map_to_vaddr(paddr).into_usize() as *mut _)
+    pub fn entry_of_addr_ref(&self, vaddr: Address<Virtual>, mask: usize) -> &TableDescriptor {
+        &self.entries[Self::index_of_addr(vaddr, mask)]
+    }
+
+    /// Access the table stored at `paddr` through `MAPPER`.
+    ///
+    /// Returns an error instead of a wild reference if `paddr` is null, beyond the physical
+    /// address size, e.g. read from a corrupted descriptor, or if the MMU does not map the address
+    /// `MAPPER` produces for it writable. Walks call it once per table they step into.
+    pub unsafe fn from_paddr<MAPPER: AddrMapper>(
+        paddr: Address<Physical>,
+    ) -> Result<&'static mut Self, MmuError> {
+        if paddr.into_usize() == 0 {
+            return Err(MmuError::NullTable);
+        }
+        let pa_bits = pa_bits();
+        if paddr.into_usize() >> pa_bits != 0 {
+            return Err(MmuError::PhysicalOutOfRange {
+                addr: paddr.into_usize(),
+                pa_bits,
+            });
+        }
+        let vaddr = MAPPER::map_to_vaddr(paddr);
+        if !tlb::is_writable(vaddr) {
+            return Err(MmuError::TableNotMapped);
+        }
+        Ok(&mut *(vaddr.into_usize() as *mut _))
     }
 }
 
@@ -222,201 +468,1294 @@ impl Default for TableSection {
     }
 }
 
+/// `N` translation tables reserved in the image instead of allocated, e.g. for the roots of the
+/// first mappings, made before any allocator is up or while the allocator's own pages still need
+/// mapping.
+///
+/// All entries start out invalid, so a `static` of it costs nothing but `.bss`.
+#[repr(C, align(4096))]
+pub struct StaticTable<const N: usize> {
+    tables: [[TableDescriptor; ENTRY_PER_TABLE]; N],
+}
+
+const _: () = assert!(
+    core::mem::align_of::<StaticTable<1>>() % MmuGranule::SIZE == 0,
+    "A static translation table is not aligned to a granule"
+);
+
+impl<const N: usize> StaticTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            tables: [[TableDescriptor::new_zeroed(); ENTRY_PER_TABLE]; N],
+        }
+    }
+
+    pub fn table(&self, index: usize) -> &TableSection {
+        // `TableSection` is a transparent wrapper of the entries.
+        unsafe { &*(&self.tables[index] as *const _ as *const TableSection) }
+    }
+
+    pub fn table_mut(&mut self, index: usize) -> &mut TableSection {
+        unsafe { &mut *(&mut self.tables[index] as *mut _ as *mut TableSection) }
+    }
+
+    /// Address of table `index` as the running code sees it, the physical one only if the image
+    /// is identity mapped or the MMU is off.
+    pub fn vaddr(&self, index: usize) -> Address<Virtual> {
+        Address::new(self.tables[index].as_ptr() as usize)
+    }
+}
+
 pub trait MmuReigon<MAPPER: AddrMapper, ALLOC: PageAllocator> {
+    /// Width of the region's address space, matching its `TnSZ`.
+    const VA_BITS: usize;
+
+    /// Whether the region translates the TTBR1 half at the top of the address space.
+    const KERNEL_HALF: bool;
+
     fn root(&self) -> Option<&TableSection>;
     fn root_mut(&mut self) -> Option<&mut TableSection>;
     fn root_or_init(&mut self) -> &mut TableSection;
 
+    /// Make the table at `paddr` the root of the region, e.g. one of a `StaticTable`, so
+    /// `root_or_init` does not allocate one.
+    ///
+    /// # Safety
+    ///
+    /// - `paddr` must hold a valid translation table that lives as long as the region is used,
+    ///   and that `MAPPER` maps writable.
+    /// - The previous root is forgotten, not freed, and the TLB is not invalidated.
+    unsafe fn install_root(&mut self, paddr: Address<Physical>);
+
     fn map_range_with(
         &mut self,
         prange: AddressRange<Physical>,
         vrange: AddressRange<Virtual>,
         attribute: AttributeFields,
-    ) -> Result<(), &'static str> {
-        assert_eq!(prange.size(), vrange.size());
-        assert!(
-            prange.addr().is_aligned(MmuGranule::SIZE),
-            "prange = {} not aligned with {:#x}",
-            prange.addr(),
-            MmuGranule::SIZE
-        );
-        assert!(
-            vrange.addr().is_aligned(MmuGranule::SIZE),
-            "vrange = {} not aligned with {:#x}",
-            vrange.addr(),
-            MmuGranule::SIZE
-        );
+    ) -> Result<(), MmuError> {
+        if prange.size() != vrange.size() {
+            return Err(MmuError::SizeMismatch {
+                physical: prange.size(),
+                virtual_: vrange.size(),
+            });
+        }
+        if !prange.addr().is_aligned(MmuGranule::SIZE) {
+            return Err(MmuError::UnalignedPhysical {
+                addr: prange.addr().into_usize(),
+                alignment: MmuGranule::SIZE,
+            });
+        }
+        if !vrange.addr().is_aligned(MmuGranule::SIZE) {
+            return Err(MmuError::UnalignedVirtual {
+                addr: vrange.addr().into_usize(),
+                alignment: MmuGranule::SIZE,
+            });
+        }
         let page_map = prange.pages().zip(vrange.pages());
 
-        for (paddr, vaddr) in page_map {
-            self.map_page(paddr, vaddr, attribute)?;
+        for (mapped, (paddr, vaddr)) in page_map.enumerate() {
+            if let Err(err) = self.map_page(paddr, vaddr, attribute) {
+                self.rollback_range(vrange, mapped);
+                return Err(err);
+            }
         }
         Ok(())
     }
 
+    /// Undo a partially applied `map_range_with`: unmap the first `mapped` pages of `vrange` and
+    /// release the tables that became empty, including ones allocated for the page that failed.
+    #[cold]
+    #[inline(never)]
+    fn rollback_range(&mut self, vrange: AddressRange<Virtual>, mapped: usize) {
+        for vaddr in vrange.pages().take(mapped) {
+            self.unmap_page(vaddr)
+                .expect("Failed to unmap a page mapped by the same call");
+        }
+        for vaddr in vrange.pages().take(mapped + 1) {
+            self.release_empty_tables(vaddr);
+        }
+    }
+
+    /// Free the tables on the walk to `vaddr` that no longer hold any valid descriptor, starting
+    /// from the level 3 table.
+    ///
+    /// A table the allocator refuses to take back stays installed.
+    fn release_empty_tables(&mut self, vaddr: Address<Virtual>) {
+        // Descriptors pointing to the tables below the root, at most three levels of them.
+        let mut walk: [Option<(*mut TableDescriptor, MmuLevel)>; 3] = [None; 3];
+        let (mut level, mut mask) = walk_start(Self::VA_BITS);
+        let mut section = match self.root_mut() {
+            Some(root) => root,
+            None => return,
+        };
+        for slot in walk.iter_mut() {
+            let entry = section.entry_of_addr(vaddr, mask);
+            let entry_ptr: *mut TableDescriptor = entry;
+            match EntryType::from_entry(entry, level) {
+                Some(EntryType::Table(_)) => {
+                    let next_table = unsafe { (*entry_ptr).next_lvl_table_addr() };
+                    section = match unsafe { TableSection::from_paddr::<MAPPER>(next_table) } {
+                        Ok(next) => next,
+                        Err(_) => break,
+                    };
+                    *slot = Some((entry_ptr, level));
+                    level = level.next_lvl().unwrap();
+                }
+                _ => break,
+            }
+            mask = next_mask(mask);
+        }
+
+        for (entry_ptr, entry_level) in walk.iter().rev().flatten() {
+            let entry = unsafe { &mut **entry_ptr };
+            let table_addr = entry.next_lvl_table_addr();
+            match unsafe { TableSection::from_paddr::<MAPPER>(table_addr) } {
+                Ok(table) if table.is_empty() => {}
+                _ => return,
+            }
+
+            let mut table = unsafe { Page::<ALLOC>::from_raw(table_addr, 1) };
+            let freed = unsafe { ALLOC::free_pages(&mut table) }.is_ok();
+            // Ownership went back to the allocator, or the table stays in use.
+            table.into_raw();
+            if !freed {
+                return;
+            }
+            *entry = TableDescriptor::new_zeroed();
+            trace_write(*entry_level, vaddr, entry);
+            tlb::invalidate_all();
+        }
+    }
+
     fn map_range(
         &mut self,
         range: AddressRange<Physical>,
         attribute: AttributeFields,
-    ) -> Result<(), &'static str> {
+    ) -> Result<(), MmuError> {
         let prange = range;
         let vrange = MAPPER::map_to_vrange(prange);
         self.map_range_with(prange, vrange, attribute)
     }
 
+    /// Fail unless `vaddr` lies in the half of the address space the region translates.
+    fn check_half(vaddr: Address<Virtual>) -> Result<(), MmuError> {
+        match (Half::of(vaddr)?, Self::KERNEL_HALF) {
+            (Half::Ttbr0, false) | (Half::Ttbr1, true) => Ok(()),
+            _ => Err(MmuError::WrongHalf {
+                addr: vaddr.into_usize(),
+            }),
+        }
+    }
+
+    /// Install the mappings described by `layout`.
+    ///
+    /// Every page of the layout's address space is queried through
+    /// `KernelVirtualLayout::virt_addr_properties`, so both `Translation::Identity` and
+    /// `Translation::Offset` ranges end up with the output address and attributes they declare.
+    /// Pages whose output addresses continue each other with the same attributes are mapped as one
+    /// `map_range_with` call. On an error, everything mapped so far is unmapped again.
+    fn map_layout<const NUM_SPECIAL_RANGES: usize>(
+        &mut self,
+        layout: &KernelVirtualLayout<NUM_SPECIAL_RANGES>,
+    ) -> Result<(), MmuError> {
+        let max = layout.max_virt_addr_inclusive();
+        Self::check_half(Address::new(0))?;
+        Self::check_half(Address::new(max))?;
+
+        // The layout's address space starts at 0, so this is also the size mapped so far.
+        let mut vaddr = 0;
+        while vaddr <= max {
+            let result = layout_run(layout, vaddr).and_then(|(paddr, size, attributes)| {
+                self.map_range_with(
+                    AddressRange::new_raw(paddr, size),
+                    AddressRange::new_raw(vaddr, size),
+                    attributes,
+                )?;
+                Ok(size)
+            });
+            match result {
+                Ok(size) => vaddr += size,
+                Err(err) => {
+                    if vaddr != 0 {
+                        self.rollback_range(
+                            AddressRange::new_raw(0, vaddr),
+                            vaddr / MmuGranule::SIZE,
+                        );
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn map_page(
         &mut self,
         paddr: Address<Physical>,
         vaddr: Address<Virtual>,
         attributes: AttributeFields,
-    ) -> Result<(), &'static str> {
-        // println!("*Map {} to {}", paddr, vaddr);
-        let mut mask: usize = 0xFF80_0000_0000;
+    ) -> Result<(), MmuError> {
+        attributes.validate()?;
+        let attributes = with_access_flag(attributes);
+        let pa_bits = pa_bits();
+        if paddr.into_usize() >> pa_bits != 0 {
+            return Err(MmuError::PhysicalOutOfRange {
+                addr: paddr.into_usize(),
+                pa_bits,
+            });
+        }
+
+        let (mut level, mut mask) = walk_start(Self::VA_BITS);
         let mut section = self.root_or_init();
-        let mut level = MmuLevel::Level0;
         while mask > MmuGranule::SIZE {
             let entry = section.entry_of_addr(vaddr, mask);
             match EntryType::from_entry(entry, level) {
-                Some(EntryType::Block(_)) => return Err("Address already mapped in a block"),
-                Some(EntryType::Page(_)) => return Err("Address already mapped in a page"),
-                None => return Err("Block descriptor cannot be in level0"),
+                Some(EntryType::Block(_)) => return Err(MmuError::AlreadyMappedInBlock),
+                Some(EntryType::Page(_)) => return Err(MmuError::AlreadyMappedInPage),
+                None => return Err(MmuError::BlockInLevel0),
                 Some(EntryType::Table(table)) => {
                     let next_table = (table.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR)
                         << MmuGranule::SHIFT) as usize;
                     unsafe {
-                        section = TableSection::from_paddr::<MAPPER>(Address::new(next_table));
+                        section = TableSection::from_paddr::<MAPPER>(Address::new(next_table))?;
                         level = level.next_lvl().unwrap();
                     }
                 }
                 Some(EntryType::Invalid) => {
                     if level == MmuLevel::Level3 {
-                        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
-
-                        let shifted = paddr.into_usize() as u64 >> MmuGranule::SHIFT;
-                        val.write(
-                            STAGE1_PAGE_DESCRIPTOR::VALID::True
-                                + STAGE1_PAGE_DESCRIPTOR::AF::True
-                                + attributes.into()
-                                + STAGE1_PAGE_DESCRIPTOR::TYPE::Table
-                                + STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted),
-                        );
-                        entry.value = val.get();
-                        // println!(
-                        //     "Page desc: {:#x} to {:#x}",
-                        //     val.get(),
-                        //     entry as *const _ as usize
-                        // );
+                        *entry = PageDescriptor::from_output_addr(paddr, attributes).into();
+                        trace_write(level, vaddr, entry);
                     } else {
-                        let next_table = ManuallyDrop::new(ALLOC::alloc_pages(1)?);
-                        unsafe { next_table.as_bytes_mut::<MAPPER>().fill(0) }
-                        *entry = TableDescriptor::from_next_lvl_table_addr(next_table.base());
+                        *entry = alloc_next_table::<MAPPER, ALLOC>()?;
+                        trace_write(level, vaddr, entry);
 
                         continue;
                     }
                 }
             }
-            mask >>= MmuGranule::SHIFT - 3;
+            mask = next_mask(mask);
         }
         Ok(())
     }
-}
 
-/// Wraper for TTBR0_EL1
-pub struct MmuReigon0<MAPPER: AddrMapper, ALLOC: PageAllocator> {
-    _alloc: PhantomData<ALLOC>,
-    _mapper: PhantomData<MAPPER>,
-}
+    /// Like `map_page`, but fail instead of installing a fresh root table if the region has none.
+    ///
+    /// For changing the address space that is already live, where a new empty root would unmap
+    /// everything.
+    fn map_page_to_current(
+        &mut self,
+        paddr: Address<Physical>,
+        vaddr: Address<Virtual>,
+        attributes: AttributeFields,
+    ) -> Result<(), MmuError> {
+        // With a root in place, `map_page` never reaches the allocation in `root_or_init`.
+        self.root_mut().ok_or(MmuError::NotInitialized)?;
+        self.map_page(paddr, vaddr, attributes)
+    }
 
-impl<MAPPER: AddrMapper, ALLOC: PageAllocator> MmuReigon<MAPPER, ALLOC>
-    for MmuReigon0<MAPPER, ALLOC>
-{
-    fn root(&self) -> Option<&TableSection> {
-        let paddr = MAPPER::map_to_vaddr(Address::new(TTBR0_EL1.get_baddr() as usize));
-        unsafe { (paddr.into_usize() as *const TableSection).as_ref() }
+    /// Map a fresh read-only page filled with `poison::PATTERN` at `vaddr`, so stray reads return
+    /// the pattern and writes fault.
+    ///
+    /// The page is never freed. Returns its physical address.
+    fn map_poison_page(&mut self, vaddr: Address<Virtual>) -> Result<Address<Physical>, MmuError> {
+        let (paddr, _) = ALLOC::alloc_pages(1)?.leak();
+        let words = MmuGranule::SIZE / core::mem::size_of::<u64>();
+        let ptr = MAPPER::map_to_vaddr(paddr).into_usize() as *mut u64;
+        for i in 0..words {
+            unsafe { ptr.add(i).write_volatile(poison::PATTERN) };
+        }
+
+        let attributes = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadOnly,
+            execute_never: true,
+            allow_wx: false,
+            non_global: false,
+            accessed: true,
+        };
+        self.map_page(paddr, vaddr, attributes)?;
+        poison::register(vaddr, poison::GuardKind::Poison)?;
+        Ok(paddr)
     }
-    fn root_mut(&mut self) -> Option<&mut TableSection> {
-        let paddr = MAPPER::map_to_vaddr(Address::new(TTBR0_EL1.get_baddr() as usize));
-        unsafe { (paddr.into_usize() as *mut TableSection).as_mut() }
+
+    /// Reserve `vaddr` as a guard page: it must stay unmapped, so any access faults.
+    fn map_invalid_guard(&mut self, vaddr: Address<Virtual>) -> Result<(), MmuError> {
+        match self.leaf_descriptor(vaddr) {
+            Some((_, mask)) if mask > MmuGranule::SIZE => Err(MmuError::MappedInBlock),
+            Some(_) => Err(MmuError::AlreadyMappedInPage),
+            None => poison::register(vaddr, poison::GuardKind::Guard),
+        }
     }
-    fn root_or_init(&mut self) -> &mut TableSection {
-        self.root_mut().unwrap_or_else(|| {
-            let lvl0 = ALLOC::alloc_pages(1).expect("get level0 table space");
-            unsafe {
-                lvl0.as_bytes_mut::<MAPPER>().fill(0);
-                TTBR0_EL1.set_baddr(lvl0.base().into_usize() as u64);
-                let (paddr, _) = lvl0.into_raw();
-                &mut *(MAPPER::map_to_vaddr(paddr).into_usize() as *mut _)
-            }
-        })
+
+    /// Walk the translation tables in software to the page or block descriptor mapping `vaddr`.
+    ///
+    /// Returns the descriptor together with the index mask of its level, or `None` if the walk
+    /// hits an invalid descriptor.
+    fn leaf_descriptor(&self, vaddr: Address<Virtual>) -> Option<(u64, usize)> {
+        leaf_descriptor_from::<MAPPER>(self.root()?, Self::VA_BITS, vaddr)
     }
-}
 
-/// Wraper for TTBR1_EL1
-pub struct MmuReigon1<MAPPER: AddrMapper, ALLOC: PageAllocator> {
-    _alloc: PhantomData<ALLOC>,
-    _mapper: PhantomData<MAPPER>,
-}
+    /// Walk the translation tables in software and return the physical address `vaddr` is
+    /// mapped to, or `None` if the walk hits an invalid descriptor.
+    fn translate(&self, vaddr: Address<Virtual>) -> Option<Address<Physical>> {
+        let (desc, mask) = self.leaf_descriptor(vaddr)?;
+        Some(output_address(desc, mask, vaddr))
+    }
 
-impl<MAPPER: AddrMapper, ALLOC: PageAllocator> MmuReigon<MAPPER, ALLOC>
-    for MmuReigon1<MAPPER, ALLOC>
-{
-    fn root(&self) -> Option<&TableSection> {
-        let paddr = MAPPER::map_to_vaddr(Address::new(TTBR1_EL1.get_baddr() as usize));
-        unsafe { (paddr.into_usize() as *const TableSection).as_ref() }
+    /// Decode the attributes `vaddr` is mapped with, or `None` if it is not mapped.
+    fn attributes(&self, vaddr: Address<Virtual>) -> Option<AttributeFields> {
+        let (desc, _) = self.leaf_descriptor(vaddr)?;
+        attributes_of_descriptor(desc)
     }
-    fn root_mut(&mut self) -> Option<&mut TableSection> {
-        let paddr = MAPPER::map_to_vaddr(Address::new(TTBR1_EL1.get_baddr() as usize));
-        unsafe { (paddr.into_usize() as *mut TableSection).as_mut() }
+
+    /// Check if `vaddr` is mapped and EL1 may execute from it.
+    fn is_executable(&self, vaddr: Address<Virtual>) -> bool {
+        match self.leaf_descriptor(vaddr) {
+            Some((desc, _)) => !STAGE1_PAGE_DESCRIPTOR::PXN::True.matches_all(desc),
+            None => false,
+        }
     }
-    fn root_or_init(&mut self) -> &mut TableSection {
-        self.root_mut().unwrap_or_else(|| {
-            let lvl0 = ALLOC::alloc_pages(1).expect("get level0 table space");
-            unsafe {
-                lvl0.as_bytes_mut::<MAPPER>().fill(0);
-                TTBR1_EL1.set_baddr(lvl0.base().into_usize() as u64);
-                let (paddr, _) = lvl0.into_raw();
-                &mut *(MAPPER::map_to_vaddr(paddr).into_usize() as *mut _)
+
+    /// Invalidate the level 3 descriptor of `vaddr` and return the physical page it pointed to.
+    ///
+    /// Intermediate tables are kept, even if they become empty.
+    fn unmap_page(&mut self, vaddr: Address<Virtual>) -> Result<Address<Physical>, MmuError> {
+        let (mut level, mut mask) = walk_start(Self::VA_BITS);
+        let mut section = self.root_mut().ok_or(MmuError::NotInitialized)?;
+        loop {
+            let entry = section.entry_of_addr(vaddr, mask);
+            let entry_ptr: *const TableDescriptor = entry;
+            match EntryType::from_entry(entry, level) {
+                Some(EntryType::Invalid) => return Err(MmuError::NotMapped),
+                Some(EntryType::Block(_)) => return Err(MmuError::MappedInBlock),
+                None => return Err(MmuError::BlockInLevel0),
+                Some(EntryType::Table(table)) => {
+                    let next_table = (table.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR)
+                        << MmuGranule::SHIFT) as usize;
+                    unsafe {
+                        section = TableSection::from_paddr::<MAPPER>(Address::new(next_table))?;
+                        level = level.next_lvl().unwrap();
+                    }
+                }
+                Some(EntryType::Page(page)) => {
+                    let paddr = (page.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR)
+                        << MmuGranule::SHIFT) as usize;
+                    page.set(0);
+                    trace_write(level, vaddr, unsafe { &*entry_ptr });
+                    tlb::invalidate_page(vaddr);
+                    return Ok(Address::new(paddr));
+                }
             }
-        })
+            mask = next_mask(mask);
+        }
     }
-}
 
-pub struct MemoryManagementUnit<ALLOC: PageAllocator> {
-    _alloc: PhantomData<ALLOC>,
-}
-
-impl<ALLOC: PageAllocator> MemoryManagementUnit<ALLOC> {
-    pub unsafe fn new() -> Self {
-        Self {
-            _alloc: PhantomData,
+    /// Change the attributes of the page mapped at `vaddr`, keeping its physical page. Returns the
+    /// attributes it had.
+    ///
+    /// Break-before-make: the descriptor is invalidated and flushed from the TLBs before the new
+    /// one is written, so no core ever sees both. Accesses to the page in between fault, it must
+    /// not hold the running code or stack.
+    fn protect(
+        &mut self,
+        vaddr: Address<Virtual>,
+        attributes: AttributeFields,
+    ) -> Result<AttributeFields, MmuError> {
+        attributes.validate()?;
+        let attributes = with_access_flag(attributes);
+        let (mut level, mut mask) = walk_start(Self::VA_BITS);
+        let mut section = self.root_mut().ok_or(MmuError::NotInitialized)?;
+        loop {
+            let entry = section.entry_of_addr(vaddr, mask);
+            let entry_ptr: *mut TableDescriptor = entry;
+            match EntryType::from_entry(entry, level) {
+                Some(EntryType::Invalid) => return Err(MmuError::NotMapped),
+                Some(EntryType::Block(_)) => return Err(MmuError::MappedInBlock),
+                None => return Err(MmuError::BlockInLevel0),
+                Some(EntryType::Table(table)) => {
+                    let next_table = (table.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR)
+                        << MmuGranule::SHIFT) as usize;
+                    unsafe {
+                        section = TableSection::from_paddr::<MAPPER>(Address::new(next_table))?;
+                        level = level.next_lvl().unwrap();
+                    }
+                }
+                Some(EntryType::Page(page)) => {
+                    let old = PageDescriptor { value: page.get() };
+                    let old_attributes = old
+                        .attributes()
+                        .ok_or(MmuError::Other("Page mapped with unknown attributes"))?;
+                    let entry = unsafe { &mut *entry_ptr };
+                    *entry = TableDescriptor::new_zeroed();
+                    trace_write(level, vaddr, entry);
+                    tlb::invalidate_page(vaddr);
+                    *entry = PageDescriptor::from_output_addr(old.output_addr(), attributes).into();
+                    trace_write(level, vaddr, entry);
+                    return Ok(old_attributes);
+                }
+            }
+            mask = next_mask(mask);
         }
     }
-    pub fn ttbl0<MAPPER: AddrMapper>(&mut self) -> &mut MmuReigon0<MAPPER, ALLOC> {
-        unsafe { &mut *core::ptr::null_mut() }
+
+    /// `protect` every page of `vrange`, e.g. to make a loaded segment read-only.
+    ///
+    /// Every page is checked to be mapped by a page descriptor with known attributes first, so
+    /// either all pages change or, on error, none.
+    fn protect_range(
+        &mut self,
+        vrange: AddressRange<Virtual>,
+        attributes: AttributeFields,
+    ) -> Result<(), MmuError> {
+        if !vrange.addr().is_aligned(MmuGranule::SIZE) {
+            return Err(MmuError::UnalignedVirtual {
+                addr: vrange.addr().into_usize(),
+                alignment: MmuGranule::SIZE,
+            });
+        }
+        attributes.validate()?;
+        for vaddr in vrange.pages() {
+            match self.leaf_descriptor(vaddr) {
+                None => return Err(MmuError::NotMapped),
+                Some((_, mask)) if mask > MmuGranule::SIZE => return Err(MmuError::MappedInBlock),
+                Some((desc, _)) if attributes_of_descriptor(desc).is_none() => {
+                    return Err(MmuError::Other("Page mapped with unknown attributes"))
+                }
+                Some(_) => {}
+            }
+        }
+
+        for vaddr in vrange.pages() {
+            self.protect(vaddr, attributes)?;
+        }
+        Ok(())
     }
-    pub fn ttbl1<MAPPER: AddrMapper>(&mut self) -> &mut MmuReigon1<MAPPER, ALLOC> {
-        unsafe { &mut *core::ptr::null_mut() }
+
+    /// Create the root table of another address space that shares every mapping of this region.
+    ///
+    /// Each valid entry of the current root is copied, so both roots point at the same tables
+    /// below. Mappings added below the root later show up in both, entries added to either root
+    /// do not.
+    fn clone_kernel_half(&self) -> Result<NewRoot<ALLOC>, MmuError> {
+        let root = self.root().ok_or(MmuError::NotInitialized)?;
+        let table = ALLOC::alloc_pages(1)?;
+        unsafe { memory::zero_pages::<MAPPER, ALLOC>(&table) };
+
+        let copy = unsafe { TableSection::from_paddr::<MAPPER>(table.base())? };
+        for (dst, src) in copy.entries.iter_mut().zip(root.entries.iter()) {
+            if src.is_valid() {
+                *dst = *src;
+            }
+        }
+        Ok(NewRoot {
+            table,
+            va_bits: Self::VA_BITS,
+        })
     }
 
-    /// Setup function for the MAIR_EL1 register.
-    fn set_up_mair(&self) {
-        // Define the memory types being mapped.
-        MAIR_EL1.write(
-            // Attribute 1 - Cacheable normal DRAM.
-            MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
-            MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
+    /// Unmap every page of `vrange`, hand the frames behind them back to `ALLOC` and free the
+    /// tables that became empty.
+    ///
+    /// Pages that are not mapped are skipped, so a sparse address space can be torn down with one
+    /// call. Returns the number of frames freed.
+    fn unmap_and_free(&mut self, vrange: AddressRange<Virtual>) -> Result<usize, MmuError> {
+        self.unmap_and_free_where(vrange, |_| true)
+    }
 
-        // Attribute 0 - Device.
-            MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck,
-        );
+    /// Like `unmap_and_free`, but only free the frames whose last mapping went away according to
+    /// `refs`. Every frame of `vrange` must be counted there.
+    fn unmap_and_release(
+        &mut self,
+        vrange: AddressRange<Virtual>,
+        refs: &FrameRefCount,
+    ) -> Result<usize, MmuError> {
+        self.unmap_and_free_where(vrange, |paddr| refs.dec(PhysFrame::from_aligned(paddr)))
     }
 
-    /// Configure various settings of stage 1 of the EL1 translation regime.
-    pub fn enable(&mut self) {
-        let t0sz = (64 - 40) as u64;
-        let t1sz = (64 - 48) as u64;
+    /// Like `unmap_and_free`, freeing only the frames `unmapped` returns `true` for.
+    fn unmap_and_free_where(
+        &mut self,
+        vrange: AddressRange<Virtual>,
+        mut unmapped: impl FnMut(Address<Physical>) -> bool,
+    ) -> Result<usize, MmuError> {
+        if !vrange.addr().is_aligned(MmuGranule::SIZE) {
+            return Err(MmuError::UnalignedVirtual {
+                addr: vrange.addr().into_usize(),
+                alignment: MmuGranule::SIZE,
+            });
+        }
 
-        self.set_up_mair();
+        let mut freed = 0;
+        for vaddr in vrange.pages() {
+            let paddr = match self.unmap_page(vaddr) {
+                Ok(paddr) => paddr,
+                Err(MmuError::NotMapped) => continue,
+                Err(err) => return Err(err),
+            };
+            self.release_empty_tables(vaddr);
+            if !unmapped(paddr) {
+                continue;
+            }
+
+            let mut frame = unsafe { Page::<ALLOC>::from_raw(paddr, 1) };
+            let result = unsafe { ALLOC::free_pages(&mut frame) };
+            // Ownership went back to the allocator, or the frame is leaked.
+            frame.into_raw();
+            result?;
+            freed += 1;
+        }
+        Ok(freed)
+    }
+}
+
+/// Walk the tables below `root`, the root of a `va_bits` wide address space, to the page or block
+/// descriptor mapping `vaddr`.
+///
+/// Returns the descriptor together with the index mask of its level, or `None` if the walk hits
+/// an invalid descriptor.
+fn leaf_descriptor_from<MAPPER: AddrMapper>(
+    root: &TableSection,
+    va_bits: usize,
+    vaddr: Address<Virtual>,
+) -> Option<(u64, usize)> {
+    let (mut level, mut mask) = walk_start(va_bits);
+    let mut section = root;
+    loop {
+        let desc = section.entry_of_addr_ref(vaddr, mask).value;
+        if !STAGE1_TABLE_DESCRIPTOR::VALID::True.matches_all(desc) {
+            return None;
+        }
+
+        let is_table = STAGE1_TABLE_DESCRIPTOR::TYPE::Table.matches_all(desc);
+        match level.next_lvl() {
+            Some(next_lvl) if is_table => {
+                let table = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(desc);
+                let next_table = (table.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR)
+                    << MmuGranule::SHIFT) as usize;
+                unsafe {
+                    section = TableSection::from_paddr::<MAPPER>(Address::new(next_table)).ok()?;
+                }
+                level = next_lvl;
+            }
+            // A block descriptor is not allowed in level 0.
+            _ if level == MmuLevel::Level0 => return None,
+            // A page at level 3 or a block at level 1/2.
+            _ => return Some((desc, mask)),
+        }
+        mask = next_mask(mask);
+    }
+}
+
+/// The physical address `vaddr` translates to through the leaf descriptor `desc`, found at the
+/// level with the index mask `mask`.
+fn output_address(desc: u64, mask: usize, vaddr: Address<Virtual>) -> Address<Physical> {
+    let page = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(desc);
+    let output_addr =
+        (page.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) << MmuGranule::SHIFT) as usize;
+    let offset = bit::extract(vaddr.into_usize(), 0, mask.trailing_zeros() as usize);
+    Address::new(output_addr + offset)
+}
+
+/// A root table that is not installed in a TTBR, e.g. the one of another address space.
+///
+/// Dropping it frees the root page only, the tables below it may be shared.
+pub struct NewRoot<ALLOC: PageAllocator> {
+    table: Page<ALLOC>,
+    va_bits: usize,
+}
+
+impl<ALLOC: PageAllocator> NewRoot<ALLOC> {
+    /// Physical address of the table, the value to program into the TTBR.
+    pub fn paddr(&self) -> Address<Physical> {
+        self.table.base()
+    }
+
+    /// Walk the tables below this root in software, accessing them through `MAPPER`, and return
+    /// the physical address `vaddr` is mapped to.
+    pub fn translate<MAPPER: AddrMapper>(
+        &self,
+        vaddr: Address<Virtual>,
+    ) -> Option<Address<Physical>> {
+        let root = unsafe { TableSection::from_paddr::<MAPPER>(self.paddr()) }.ok()?;
+        let (desc, mask) = leaf_descriptor_from::<MAPPER>(root, self.va_bits, vaddr)?;
+        Some(output_address(desc, mask, vaddr))
+    }
+
+    /// Give up the root table without freeing it.
+    pub fn into_page(self) -> Page<ALLOC> {
+        self.table
+    }
+}
+
+/// Record a descriptor write in the `mmu_trace` log, compiles to nothing without the feature.
+#[inline(always)]
+#[cfg_attr(not(feature = "mmu_trace"), allow(unused_variables))]
+fn trace_write(level: MmuLevel, vaddr: Address<Virtual>, entry: &TableDescriptor) {
+    #[cfg(feature = "mmu_trace")]
+    trace::record(trace::DescriptorWrite {
+        level,
+        vaddr: vaddr.into_usize(),
+        entry: entry as *const _ as usize,
+        desc: entry.value,
+    });
+}
+
+/// Pages set up by `map_poison_page` and `map_invalid_guard`, for the fault handler to name.
+///
+/// The registry is lock free, so the fault handler can look a page up on any core, also while a
+/// page is being registered.
+pub mod poison {
+    use super::MmuError;
+    use crate::{
+        bsp::config::MmuGranule,
+        memory::{Address, Virtual},
+    };
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Content of every word of a poison page.
+    pub const PATTERN: u64 = 0xDEAD_DEAD_DEAD_DEAD;
+
+    /// Number of pages that can be registered.
+    const CAPACITY: usize = 32;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum GuardKind {
+        /// Mapped read-only and filled with `PATTERN`.
+        Poison,
+        /// Left unmapped.
+        Guard,
+    }
+
+    /// A free slot of `PAGES`.
+    const FREE: usize = 0;
+
+    /// Registered pages, each encoded by `encode`.
+    static PAGES: [AtomicUsize; CAPACITY] = {
+        const SLOT: AtomicUsize = AtomicUsize::new(FREE);
+        [SLOT; CAPACITY]
+    };
+
+    /// The page address with the kind in its low bits, which are never `FREE`.
+    fn encode(page: usize, kind: GuardKind) -> usize {
+        page | match kind {
+            GuardKind::Poison => 0b01,
+            GuardKind::Guard => 0b10,
+        }
+    }
+
+    fn decode(slot: usize) -> Option<(usize, GuardKind)> {
+        let page = slot & !(MmuGranule::SIZE - 1);
+        match slot & (MmuGranule::SIZE - 1) {
+            0b01 => Some((page, GuardKind::Poison)),
+            0b10 => Some((page, GuardKind::Guard)),
+            _ => None,
+        }
+    }
+
+    pub(super) fn register(vaddr: Address<Virtual>, kind: GuardKind) -> Result<(), MmuError> {
+        let page = vaddr.align_down(MmuGranule::SIZE).into_usize();
+        let entry = encode(page, kind);
+        // Pages are never removed, so a page registered before is found ahead of any free slot.
+        for slot in PAGES.iter() {
+            match decode(slot.load(Ordering::Acquire)) {
+                Some((registered, _)) if registered == page => {
+                    slot.store(entry, Ordering::Release);
+                    return Ok(());
+                }
+                Some(_) => {}
+                None => {
+                    if slot
+                        .compare_exchange(FREE, entry, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Err(MmuError::Other("Too many poison and guard pages"))
+    }
+
+    /// The kind of the registered page `vaddr` lies in, if any.
+    pub fn lookup(vaddr: usize) -> Option<GuardKind> {
+        let page = Address::<Virtual>::new(vaddr)
+            .align_down(MmuGranule::SIZE)
+            .into_usize();
+        PAGES
+            .iter()
+            .filter_map(|slot| decode(slot.load(Ordering::Acquire)))
+            .find(|(registered, _)| *registered == page)
+            .map(|(_, kind)| kind)
+    }
+}
+
+/// Ring buffer of the latest descriptor writes, to replay how the tables were built after a
+/// fault.
+#[cfg(feature = "mmu_trace")]
+pub mod trace {
+    use super::MmuLevel;
+    use crate::cpu::smp::SingleCoreCell;
+
+    /// Number of writes kept, older ones are overwritten.
+    const CAPACITY: usize = 256;
+
+    #[derive(Copy, Clone)]
+    pub struct DescriptorWrite {
+        pub level: MmuLevel,
+        /// The address whose translation was being changed.
+        pub vaddr: usize,
+        /// Where the descriptor lives, i.e. the table and index written to.
+        pub entry: usize,
+        pub desc: u64,
+    }
+
+    struct Log {
+        writes: [Option<DescriptorWrite>; CAPACITY],
+        /// Total number of writes recorded.
+        count: usize,
+    }
+
+    static LOG: SingleCoreCell<Log> = SingleCoreCell::new(Log {
+        writes: [None; CAPACITY],
+        count: 0,
+    });
+
+    pub(super) fn record(write: DescriptorWrite) {
+        LOG.with(|log| {
+            log.writes[log.count % CAPACITY] = Some(write);
+            log.count += 1;
+        });
+    }
+
+    /// Print the logged writes, oldest first.
+    ///
+    /// Called on the panic path, so it skips the dump instead of panicking again if the log is in
+    /// use or the panic is on another core.
+    pub fn dump() {
+        let dumped = LOG.try_with(|log| {
+            let dropped = log.count.saturating_sub(CAPACITY);
+            println!(
+                "MMU trace: {} descriptor writes, {} dropped",
+                log.count, dropped
+            );
+            for i in dropped..log.count {
+                if let Some(write) = log.writes[i % CAPACITY] {
+                    println!(
+                        "\t{:?} {:#018x}: {:?} at {:#x}",
+                        write.level,
+                        write.vaddr,
+                        super::TableDescriptor { value: write.desc },
+                        write.entry
+                    );
+                }
+            }
+        });
+        if dumped.is_none() {
+            println!("MMU trace: in use, not dumped");
+        }
+    }
+}
+
+/// The run of pages of `layout` from `vaddr` on whose output addresses continue each other with
+/// the same attributes, as its output address, size and attributes.
+fn layout_run<const NUM_SPECIAL_RANGES: usize>(
+    layout: &KernelVirtualLayout<NUM_SPECIAL_RANGES>,
+    vaddr: usize,
+) -> Result<(usize, usize, AttributeFields), MmuError> {
+    let (paddr, attributes) = layout.virt_addr_properties(vaddr)?;
+    let mut size = MmuGranule::SIZE;
+    while size <= layout.max_virt_addr_inclusive() - vaddr {
+        let (next, next_attributes) = layout.virt_addr_properties(vaddr + size)?;
+        if paddr.checked_add(size) != Some(next) || next_attributes != attributes {
+            break;
+        }
+        size += MmuGranule::SIZE;
+    }
+    Ok((paddr, size, attributes))
+}
+
+/// The root table at `baddr`, the base address read from a TTBR, as `MAPPER` makes it accessible.
+///
+/// `None` if the TTBR is not set. That is checked on the base address itself, a mapper with an
+/// offset turns 0 into an address that looks valid.
+fn root_ptr<MAPPER: AddrMapper>(baddr: u64) -> Option<*mut TableSection> {
+    if baddr == 0 {
+        return None;
+    }
+    Some(MAPPER::map_to_vaddr(Address::new(baddr as usize)).into_usize() as *mut TableSection)
+}
+
+/// Wraper for TTBR0_EL1
+pub struct MmuReigon0<MAPPER: AddrMapper, ALLOC: PageAllocator> {
+    _alloc: PhantomData<ALLOC>,
+    _mapper: PhantomData<MAPPER>,
+}
+
+impl<MAPPER: AddrMapper, ALLOC: PageAllocator> MmuReigon<MAPPER, ALLOC>
+    for MmuReigon0<MAPPER, ALLOC>
+{
+    const VA_BITS: usize = config::TTBR0_VA_BITS;
+    const KERNEL_HALF: bool = false;
+
+    fn root(&self) -> Option<&TableSection> {
+        root_ptr::<MAPPER>(TTBR0_EL1.get_baddr()).map(|root| unsafe { &*root })
+    }
+    fn root_mut(&mut self) -> Option<&mut TableSection> {
+        root_ptr::<MAPPER>(TTBR0_EL1.get_baddr()).map(|root| unsafe { &mut *root })
+    }
+    fn root_or_init(&mut self) -> &mut TableSection {
+        self.root_mut().unwrap_or_else(|| {
+            let lvl0 = ALLOC::alloc_pages(1).expect("get level0 table space");
+            unsafe {
+                memory::zero_pages::<MAPPER, ALLOC>(&lvl0);
+                let (paddr, _) = lvl0.leak();
+                TTBR0_EL1.set_baddr(paddr.into_usize() as u64);
+                &mut *(MAPPER::map_to_vaddr(paddr).into_usize() as *mut _)
+            }
+        })
+    }
+    unsafe fn install_root(&mut self, paddr: Address<Physical>) {
+        assert!(paddr.is_aligned(MmuGranule::SIZE), "Root table not aligned");
+        assert!(
+            tlb::is_writable(MAPPER::map_to_vaddr(paddr)),
+            "Root table not mapped writable"
+        );
+        TTBR0_EL1.set_baddr(paddr.into_usize() as u64);
+    }
+}
+
+/// Wraper for TTBR1_EL1
+pub struct MmuReigon1<MAPPER: AddrMapper, ALLOC: PageAllocator> {
+    _alloc: PhantomData<ALLOC>,
+    _mapper: PhantomData<MAPPER>,
+}
+
+impl<MAPPER: AddrMapper, ALLOC: PageAllocator> MmuReigon<MAPPER, ALLOC>
+    for MmuReigon1<MAPPER, ALLOC>
+{
+    const VA_BITS: usize = config::TTBR1_VA_BITS;
+    const KERNEL_HALF: bool = true;
+
+    fn root(&self) -> Option<&TableSection> {
+        root_ptr::<MAPPER>(TTBR1_EL1.get_baddr()).map(|root| unsafe { &*root })
+    }
+    fn root_mut(&mut self) -> Option<&mut TableSection> {
+        root_ptr::<MAPPER>(TTBR1_EL1.get_baddr()).map(|root| unsafe { &mut *root })
+    }
+    fn root_or_init(&mut self) -> &mut TableSection {
+        self.root_mut().unwrap_or_else(|| {
+            let lvl0 = ALLOC::alloc_pages(1).expect("get level0 table space");
+            unsafe {
+                memory::zero_pages::<MAPPER, ALLOC>(&lvl0);
+                let (paddr, _) = lvl0.leak();
+                TTBR1_EL1.set_baddr(paddr.into_usize() as u64);
+                &mut *(MAPPER::map_to_vaddr(paddr).into_usize() as *mut _)
+            }
+        })
+    }
+    unsafe fn install_root(&mut self, paddr: Address<Physical>) {
+        assert!(paddr.is_aligned(MmuGranule::SIZE), "Root table not aligned");
+        assert!(
+            tlb::is_writable(MAPPER::map_to_vaddr(paddr)),
+            "Root table not mapped writable"
+        );
+        TTBR1_EL1.set_baddr(paddr.into_usize() as u64);
+    }
+}
+
+/// Top of the TTBR1 half, nothing else is mapped there.
+#[cfg(feature = "mmu_selftest")]
+const SELF_TEST_VADDR: usize = usize::MAX - MmuGranule::SIZE + 1;
+
+/// One level 0 entry below `SELF_TEST_VADDR`, so none of the tables below the root exist yet even
+/// if the self test could not free its tables.
+#[cfg(all(feature = "mmu_selftest", feature = "alloc_fault_injection"))]
+const ROLLBACK_TEST_VADDR: usize = SELF_TEST_VADDR - MmuLevel::Level0.block_size();
+
+/// The translation table half a virtual address lies in.
+enum Half {
+    Ttbr0,
+    Ttbr1,
+}
+
+impl Half {
+    fn of(vaddr: Address<Virtual>) -> Result<Self, MmuError> {
+        if vaddr.is_kernel_half() {
+            Ok(Self::Ttbr1)
+        } else if vaddr.is_user_half() {
+            Ok(Self::Ttbr0)
+        } else {
+            Err(MmuError::NotInEitherHalf {
+                addr: vaddr.into_usize(),
+            })
+        }
+    }
+}
+
+pub struct MemoryManagementUnit<ALLOC: PageAllocator> {
+    _alloc: PhantomData<ALLOC>,
+}
+
+/// The physical address size `enable()` programs, in the encoding shared by
+/// `ID_AA64MMFR0_EL1.PARange` and `TCR_EL1.IPS`: what the CPU implements, capped at 48 bits.
+fn pa_range() -> usize {
+    let mmfr0 = cpu::memory_model_features() as usize;
+    bit::extract(mmfr0, 0, 4).min(PA_RANGE_48_BITS)
+}
+
+/// Width of the physical addresses the translation tables may output.
+pub fn pa_bits() -> u32 {
+    match pa_range() {
+        0b0000 => 32,
+        0b0001 => 36,
+        0b0010 => 40,
+        0b0011 => 42,
+        0b0100 => 44,
+        _ => 48,
+    }
+}
+
+/// Stop translating the TTBR0 half, i.e. the identity map the bootloader ran on, by disabling its
+/// table walks. Every access below the TTBR1 region faults afterwards, null pointers included.
+///
+/// The tables TTBR0 points to are left alone, they live in the bootloader's page pool.
+///
+/// # Safety
+///
+/// - Code, stacks, data and devices in use must all be mapped in the TTBR1 half.
+/// - Secondary cores started by `smp::wake_all` enable their MMU through the identity map, they
+///   have to be online before.
+pub unsafe fn identity_unmap_low_half() {
+    TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+    barrier::isb();
+    // Walks already cached for TTBR0 would keep translating otherwise.
+    tlb::invalidate_all();
+}
+
+/// `TCR_EL1.HA`: hardware sets the access flag of descriptors on access. Not described by the
+/// register crate.
+const TCR_EL1_HA: u64 = 1 << 39;
+
+/// `TCR_EL1.HD`: hardware manages the dirty state of descriptors that have the DBM bit set.
+const TCR_EL1_HD: u64 = 1 << 40;
+
+/// Who sets the access flag of a descriptor that is mapped without it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessFlagMode {
+    /// The access faults, and the fault handler has to set the flag.
+    Software,
+    /// The hardware sets the flag.
+    Hardware,
+    /// The hardware sets the flag and also manages the dirty state.
+    HardwareDirty,
+}
+
+/// The most the CPU supports, from `ID_AA64MMFR1_EL1.HAFDBS`.
+pub fn access_flag_support() -> AccessFlagMode {
+    let mmfr1 = cpu::memory_model_features1() as usize;
+    match bit::extract(mmfr1, 0, 4) {
+        0b0000 => AccessFlagMode::Software,
+        0b0001 => AccessFlagMode::Hardware,
+        _ => AccessFlagMode::HardwareDirty,
+    }
+}
+
+/// `attributes`, with the access flag set unless the hardware sets it. There is no access flag
+/// fault handler, a page mapped without the flag would fault on every access.
+fn with_access_flag(attributes: AttributeFields) -> AttributeFields {
+    if attributes.accessed || access_flag_mode() != AccessFlagMode::Software {
+        return attributes;
+    }
+    AttributeFields {
+        accessed: true,
+        ..attributes
+    }
+}
+
+/// The mode `TCR_EL1` currently selects.
+pub fn access_flag_mode() -> AccessFlagMode {
+    let tcr = TCR_EL1.get();
+    match (tcr & TCR_EL1_HA != 0, tcr & TCR_EL1_HD != 0) {
+        (false, _) => AccessFlagMode::Software,
+        (true, false) => AccessFlagMode::Hardware,
+        (true, true) => AccessFlagMode::HardwareDirty,
+    }
+}
+
+/// Check that the CPU implements the physical address size and granule `enable()` programs.
+fn check_features() -> Result<(), &'static str> {
+    let ram_last = config::RAM_BASE + config::RAM_SIZE - 1;
+    if ram_last >> pa_bits() != 0 {
+        return Err("Physical address size not supported");
+    }
+
+    // Fields of ID_AA64MMFR0_EL1.
+    let mmfr0 = cpu::memory_model_features() as usize;
+
+    let granule_supported = match MmuGranule::SIZE {
+        Granule4KiB::SIZE => bit::extract(mmfr0, 28, 4) != 0b1111,
+        Granule16KiB::SIZE => bit::extract(mmfr0, 20, 4) != 0b0000,
+        Granule64KiB::SIZE => bit::extract(mmfr0, 24, 4) != 0b1111,
+        _ => false,
+    };
+    if !granule_supported {
+        return Err("Translation granule not supported");
+    }
+    Ok(())
+}
+
+impl<ALLOC: PageAllocator> MemoryManagementUnit<ALLOC> {
+    pub unsafe fn new() -> Self {
+        Self {
+            _alloc: PhantomData,
+        }
+    }
+
+    /// Like `new`, but fail if the CPU can not implement the configured translation regime.
+    pub unsafe fn try_new() -> Result<Self, &'static str> {
+        check_features()?;
+        Ok(Self::new())
+    }
+    // The regions are zero sized, any non-null, aligned pointer is a valid one.
+    pub fn ttbl0<MAPPER: AddrMapper>(&mut self) -> &mut MmuReigon0<MAPPER, ALLOC> {
+        unsafe { core::ptr::NonNull::dangling().as_mut() }
+    }
+    pub fn ttbl1<MAPPER: AddrMapper>(&mut self) -> &mut MmuReigon1<MAPPER, ALLOC> {
+        unsafe { core::ptr::NonNull::dangling().as_mut() }
+    }
+
+    /// Map the page at `paddr` to `vaddr` in the region of the half `vaddr` lies in, see
+    /// `MmuReigon::map_page`.
+    pub fn map<MAPPER: AddrMapper>(
+        &mut self,
+        vaddr: Address<Virtual>,
+        paddr: Address<Physical>,
+        attributes: AttributeFields,
+    ) -> Result<(), MmuError> {
+        match Half::of(vaddr)? {
+            Half::Ttbr0 => self.ttbl0::<MAPPER>().map_page(paddr, vaddr, attributes),
+            Half::Ttbr1 => self.ttbl1::<MAPPER>().map_page(paddr, vaddr, attributes),
+        }
+    }
+
+    /// Unmap the page at `vaddr` in the region of the half it lies in, free the tables that
+    /// became empty and return the physical page it was mapped to. The page itself is not freed.
+    pub fn unmap<MAPPER: AddrMapper>(
+        &mut self,
+        vaddr: Address<Virtual>,
+    ) -> Result<Address<Physical>, MmuError> {
+        fn unmap_in<MAPPER: AddrMapper, ALLOC: PageAllocator>(
+            region: &mut impl MmuReigon<MAPPER, ALLOC>,
+            vaddr: Address<Virtual>,
+        ) -> Result<Address<Physical>, MmuError> {
+            let paddr = region.unmap_page(vaddr)?;
+            region.release_empty_tables(vaddr);
+            Ok(paddr)
+        }
+
+        match Half::of(vaddr)? {
+            Half::Ttbr0 => unmap_in(self.ttbl0::<MAPPER>(), vaddr),
+            Half::Ttbr1 => unmap_in(self.ttbl1::<MAPPER>(), vaddr),
+        }
+    }
+
+    /// Like `MmuReigon::translate`, in the region of the half `vaddr` lies in.
+    pub fn translate<MAPPER: AddrMapper>(
+        &mut self,
+        vaddr: Address<Virtual>,
+    ) -> Option<Address<Physical>> {
+        match Half::of(vaddr).ok()? {
+            Half::Ttbr0 => self.ttbl0::<MAPPER>().translate(vaddr),
+            Half::Ttbr1 => self.ttbl1::<MAPPER>().translate(vaddr),
+        }
+    }
+
+    /// Print the translation table bases and the `TCR_EL1` fields currently in effect.
+    pub fn debug_summary(&self) {
+        println!("MMU:");
+        println!("\tTTBR0_EL1: {:#x}", TTBR0_EL1.get_baddr());
+        println!("\tTTBR1_EL1: {:#x}", TTBR1_EL1.get_baddr());
+        println!("\tTCR_EL1: {:#x}", TCR_EL1.get());
+        println!("\t\t{}", TranslationConfig::current());
+        println!(
+            "\t\tTG1 {:#b}, IPS {:#b}, EPD0 {}, EPD1 {}",
+            TCR_EL1.read(TCR_EL1::TG1),
+            TCR_EL1.read(TCR_EL1::IPS),
+            TCR_EL1.read(TCR_EL1::EPD0),
+            TCR_EL1.read(TCR_EL1::EPD1)
+        );
+        println!(
+            "\tSCTLR_EL1.M: {}",
+            SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
+        );
+    }
+
+    /// Map a fresh page at an unused address, check that it is accessible and then that it is no
+    /// longer after unmapping it.
+    ///
+    /// Exercises the whole map, translate, unmap and TLB invalidation path once the MMU is on.
+    #[cfg(feature = "mmu_selftest")]
+    pub fn self_test<MAPPER: AddrMapper>(&mut self) -> Result<(), MmuError> {
+        use crate::arch::exception;
+
+        const PATTERN: u64 = 0x5354_454c_4c41_5221;
+
+        attribute_round_trip()?;
+        self.clone_test::<MAPPER>()?;
+
+        let vaddr = Address::<Virtual>::new(SELF_TEST_VADDR);
+        let region = self.ttbl1::<MAPPER>();
+        if region.translate(vaddr).is_some() {
+            return Err(MmuError::Other("Self test address already mapped"));
+        }
+
+        let mut page = ALLOC::alloc_pages(1)?;
+        let paddr = page.base();
+        let attributes = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+            allow_wx: false,
+            non_global: false,
+            accessed: true,
+        };
+        let result = (|| -> Result<(), MmuError> {
+            region.map_page(paddr, vaddr, attributes)?;
+            if region.translate(vaddr) != Some(paddr) {
+                return Err(MmuError::Other(
+                    "Self test page translates to the wrong address",
+                ));
+            }
+            if region.attributes(vaddr) != Some(attributes) {
+                return Err(MmuError::Other("Self test page has the wrong attributes"));
+            }
+
+            let words = MmuGranule::SIZE / core::mem::size_of::<u64>();
+            let ptr = vaddr.into_usize() as *mut u64;
+            for i in 0..words {
+                unsafe { ptr.add(i).write_volatile(PATTERN ^ i as u64) };
+            }
+            // The same frame seen through the mapper the tables are accessed with.
+            let alias = MAPPER::map_to_vaddr(paddr).into_usize() as *const u64;
+            for i in 0..words {
+                let value = unsafe { ptr.add(i).read_volatile() };
+                if value != PATTERN ^ i as u64 || unsafe { alias.add(i).read_volatile() } != value {
+                    return Err(MmuError::Other("Self test pattern mismatch"));
+                }
+            }
+
+            if region.unmap_page(vaddr)? != paddr {
+                return Err(MmuError::Other("Self test unmapped the wrong page"));
+            }
+            if !exception::read_faults(vaddr.into_usize()) {
+                return Err(MmuError::Other(
+                    "Self test page still accessible after unmap",
+                ));
+            }
+            region.release_empty_tables(vaddr);
+            Ok(())
+        })();
+
+        // The page is either back with the allocator or stays leaked for allocators that can not
+        // take it back.
+        let _ = unsafe { ALLOC::free_pages(&mut page) };
+        page.into_raw();
+        result
+    }
+
+    /// Clone the TTBR1 root twice and check that kernel addresses translate the same through both
+    /// clones as through the installed root.
+    #[cfg(feature = "mmu_selftest")]
+    fn clone_test<MAPPER: AddrMapper>(&mut self) -> Result<(), MmuError> {
+        let region = self.ttbl1::<MAPPER>();
+        let first = region.clone_kernel_half()?;
+        let second = region.clone_kernel_half()?;
+
+        let vaddrs = [
+            config::UART0_VBASE,
+            config::PHYS_MAP_OFFSET + config::RAM_BASE,
+        ];
+        let result = vaddrs.iter().try_for_each(|&vaddr| {
+            let vaddr = Address::<Virtual>::new(vaddr);
+            let expected = region.translate(vaddr);
+            if expected.is_none() {
+                Err(MmuError::Other("Clone test address not mapped"))
+            } else if first.translate::<MAPPER>(vaddr) != expected
+                || second.translate::<MAPPER>(vaddr) != expected
+            {
+                Err(MmuError::Other("Cloned root translates differently"))
+            } else {
+                Ok(())
+            }
+        });
+
+        for root in core::array::IntoIter::new([first, second]) {
+            // Back with the allocator, or leaked for allocators that can not take it back.
+            let mut page = root.into_page();
+            let _ = unsafe { ALLOC::free_pages(&mut page) };
+            page.into_raw();
+        }
+        result
+    }
+
+    /// Fail the second table allocation of a `map_range_with` and check that the rollback hands
+    /// the table allocated before the failure back and leaves nothing mapped.
+    #[cfg(all(feature = "mmu_selftest", feature = "alloc_fault_injection"))]
+    pub fn rollback_test<MAPPER: AddrMapper>(&mut self) -> Result<(), MmuError> {
+        use crate::memory::fault_inject::FaultingAllocator;
+
+        let vrange = AddressRange::<Virtual>::new_raw(ROLLBACK_TEST_VADDR, MmuGranule::SIZE);
+        let mut page = ALLOC::alloc_pages(1)?;
+        let attributes = AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+            allow_wx: false,
+            non_global: false,
+            accessed: true,
+        };
+
+        let mut faulting = unsafe { MemoryManagementUnit::<FaultingAllocator<ALLOC>>::new() };
+        let region = faulting.ttbl1::<MAPPER>();
+        let outstanding = FaultingAllocator::<ALLOC>::outstanding_pages();
+        FaultingAllocator::<ALLOC>::fail_after(1);
+        let mapped = region.map_range_with(page.range(), vrange, attributes);
+        FaultingAllocator::<ALLOC>::disarm();
+
+        let result = match mapped {
+            Err(MmuError::Other("injected")) => {
+                if region.translate(vrange.addr()).is_some() {
+                    Err(MmuError::Other("Rollback left the page mapped"))
+                } else if FaultingAllocator::<ALLOC>::outstanding_pages() != outstanding {
+                    Err(MmuError::Other("Rollback kept a translation table"))
+                } else {
+                    Ok(())
+                }
+            }
+            Err(err) => Err(err),
+            Ok(()) => {
+                region.unmap_page(vrange.addr())?;
+                region.release_empty_tables(vrange.addr());
+                Err(MmuError::Other(
+                    "Rollback test did not hit the injected fault",
+                ))
+            }
+        };
+
+        let _ = unsafe { ALLOC::free_pages(&mut page) };
+        page.into_raw();
+        result
+    }
+
+    /// Setup function for the MAIR_EL1 register.
+    fn set_up_mair(&self) {
+        // Define the memory types being mapped.
+        MAIR_EL1.set(mair::MAIR.value());
+    }
+
+    /// Configure various settings of stage 1 of the EL1 translation regime.
+    pub fn enable(&mut self) {
+        self.set_up_mair();
 
         TCR_EL1.write(
             TCR_EL1::TBI0::Used
@@ -431,28 +1770,150 @@ impl<ALLOC: PageAllocator> MemoryManagementUnit<ALLOC> {
                 + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::EPD0::EnableTTBR0Walks
                 + TCR_EL1::EPD1::EnableTTBR1Walks
-                + TCR_EL1::IPS::Bits_40
+                + TCR_EL1::IPS.val(pa_range() as u64)
                 + TCR_EL1::A1::TTBR0
-                + TCR_EL1::T0SZ.val(t0sz)
-                + TCR_EL1::T1SZ.val(t1sz),
+                + TCR_EL1::T0SZ.val(T0SZ as u64)
+                + TCR_EL1::T1SZ.val(T1SZ as u64),
         );
 
+        // Let the hardware set the access flag where it can, pages mapped without it depend on
+        // that. Dirty state is left alone, as nothing writes the DBM bit of a descriptor.
+        if access_flag_support() != AccessFlagMode::Software {
+            TCR_EL1.set(TCR_EL1.get() | TCR_EL1_HA);
+        }
+
         // Enable MMU
-        unsafe {
-            cortex_a::barrier::isb(cortex_a::barrier::SY);
+        barrier::isb();
 
-            SCTLR_EL1.write(SCTLR_EL1::M::Enable);
+        SCTLR_EL1.write(SCTLR_EL1::M::Enable);
+
+        barrier::isb();
+    }
+}
+
+/// Translation parameters programmed into `TCR_EL1`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct TranslationConfig {
+    pub granule: usize,
+    pub t0sz: u8,
+    pub t1sz: u8,
+}
+
+impl TranslationConfig {
+    /// The parameters this binary was built for and `enable()` programs.
+    pub const fn expected() -> Self {
+        Self {
+            granule: MmuGranule::SIZE,
+            t0sz: T0SZ,
+            t1sz: T1SZ,
+        }
+    }
+
+    /// Read back the parameters currently in effect.
+    pub fn current() -> Self {
+        let granule = match TCR_EL1.read(TCR_EL1::TG0) {
+            0b00 => Granule4KiB::SIZE,
+            0b01 => Granule64KiB::SIZE,
+            0b10 => Granule16KiB::SIZE,
+            _ => 0,
+        };
 
-            cortex_a::barrier::isb(cortex_a::barrier::SY);
+        Self {
+            granule,
+            t0sz: TCR_EL1.read(TCR_EL1::T0SZ) as u8,
+            t1sz: TCR_EL1.read(TCR_EL1::T1SZ) as u8,
         }
     }
 }
 
-/// Constants for indexing the MAIR_EL1.
-#[allow(dead_code)]
+impl core::fmt::Display for TranslationConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "granule {:#x}, T0SZ {}, T1SZ {}",
+            self.granule, self.t0sz, self.t1sz
+        )
+    }
+}
+
+/// The memory types programmed into MAIR_EL1 and the indices descriptors refer to them by.
 pub mod mair {
-    pub const DEVICE: u64 = 0;
-    pub const NORMAL: u64 = 1;
+    use crate::memory::MemAttributes;
+
+    /// Device-nGnRE.
+    pub const ATTR_DEVICE: u8 = 0b0000_0100;
+    /// Normal memory, inner and outer write-back non-transient with read and write allocation.
+    pub const ATTR_NORMAL: u8 = 0b1111_1111;
+    /// Normal memory, inner and outer non-cacheable.
+    pub const ATTR_NORMAL_NON_CACHEABLE: u8 = 0b0100_0100;
+
+    /// Attribute definitions in MAIR_EL1 order, an index is handed out per registration.
+    #[derive(Copy, Clone)]
+    pub struct Mair {
+        /// `MemAttributes` discriminant of each registered definition.
+        kinds: [u8; 8],
+        attrs: [u8; 8],
+        len: usize,
+    }
+
+    impl Mair {
+        pub const fn new() -> Self {
+            Self {
+                kinds: [0; 8],
+                attrs: [0; 8],
+                len: 0,
+            }
+        }
+
+        /// Add the definition of `kind` at the next free index.
+        pub const fn register(mut self, kind: MemAttributes, attr: u8) -> Self {
+            assert!(self.len < 8, "MAIR_EL1 holds eight attributes");
+            let mut i = 0;
+            while i < self.len {
+                assert!(self.kinds[i] != kind as u8, "Memory type registered twice");
+                i += 1;
+            }
+            self.kinds[self.len] = kind as u8;
+            self.attrs[self.len] = attr;
+            self.len += 1;
+            self
+        }
+
+        /// The `AttrIndx` of `kind`.
+        pub const fn index(&self, kind: MemAttributes) -> u64 {
+            let mut i = 0;
+            while i < self.len {
+                if self.kinds[i] == kind as u8 {
+                    return i as u64;
+                }
+                i += 1;
+            }
+            panic!("Memory type not registered in MAIR")
+        }
+
+        /// The value to program into MAIR_EL1.
+        pub const fn value(&self) -> u64 {
+            let mut value = 0;
+            let mut i = 0;
+            while i < self.len {
+                value |= (self.attrs[i] as u64) << (i * 8);
+                i += 1;
+            }
+            value
+        }
+    }
+
+    /// The layout programmed by `set_up_mair`. Adding a memory type only takes a registration.
+    pub const MAIR: Mair = Mair::new()
+        .register(MemAttributes::Device, ATTR_DEVICE)
+        .register(MemAttributes::CacheableDRAM, ATTR_NORMAL)
+        .register(MemAttributes::NonCacheable, ATTR_NORMAL_NON_CACHEABLE);
+
+    // Evaluated at compile time, so a missing registration fails the build.
+    pub const DEVICE: u64 = MAIR.index(MemAttributes::Device);
+    pub const NORMAL: u64 = MAIR.index(MemAttributes::CacheableDRAM);
+    pub const NORMAL_NON_CACHEABLE: u64 = MAIR.index(MemAttributes::NonCacheable);
 }
 
 // const NUM_LVL2_TABLES: usize = KernelAddrSpaceSize::SIZE >> Granule512MiB::SHIFT;
@@ -469,7 +1930,7 @@ impl TableDescriptor {
     pub fn from_next_lvl_table_addr(next_lvl_table_addr: Address<Physical>) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
 
-        let shifted = next_lvl_table_addr.into_usize() >> MmuGranule::SHIFT;
+        let shifted = bit::align_shift(next_lvl_table_addr.into_usize(), MmuGranule::SHIFT);
         val.write(
             STAGE1_TABLE_DESCRIPTOR::VALID::True
                 + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
@@ -478,6 +1939,123 @@ impl TableDescriptor {
 
         TableDescriptor { value: val.get() }
     }
+
+    /// Address of the next level table this descriptor points to.
+    pub fn next_lvl_table_addr(&self) -> Address<Physical> {
+        let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(self.value);
+        let shifted = val.read(STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR) as usize;
+
+        Address::new(shifted << MmuGranule::SHIFT)
+    }
+
+    /// Check if the descriptor is valid.
+    pub fn is_valid(&self) -> bool {
+        STAGE1_TABLE_DESCRIPTOR::VALID::True.matches_all(self.value)
+    }
+}
+
+impl PageDescriptor {
+    /// Create an instance.
+    ///
+    /// Descriptor is invalid by default.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance mapping the page at `output_addr` with `attribute_fields`.
+    pub fn from_output_addr(
+        output_addr: Address<Physical>,
+        attribute_fields: AttributeFields,
+    ) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
+
+        let shifted = bit::align_shift(output_addr.into_usize(), MmuGranule::SHIFT) as u64;
+        val.write(
+            STAGE1_PAGE_DESCRIPTOR::VALID::True
+                + attribute_fields.into()
+                + STAGE1_PAGE_DESCRIPTOR::TYPE::Table
+                + STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted),
+        );
+
+        Self { value: val.get() }
+    }
+
+    /// Address of the page this descriptor maps.
+    pub fn output_addr(&self) -> Address<Physical> {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+        let shifted = val.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) as usize;
+
+        Address::new(shifted << MmuGranule::SHIFT)
+    }
+
+    /// The attributes the page is mapped with, see `attributes_of_descriptor`.
+    pub fn attributes(&self) -> Option<AttributeFields> {
+        attributes_of_descriptor(self.value)
+    }
+}
+
+impl convert::From<PageDescriptor> for TableDescriptor {
+    /// The entry of a level 3 table, which holds both kinds of descriptor.
+    fn from(page: PageDescriptor) -> Self {
+        Self { value: page.value }
+    }
+}
+
+/// Decode the fields of a descriptor, naming the attributes of pages and blocks too, as the type
+/// alone does not tell them apart from tables.
+fn fmt_descriptor(f: &mut core::fmt::Formatter<'_>, name: &str, value: u64) -> core::fmt::Result {
+    let desc = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(value);
+    if !desc.is_set(STAGE1_PAGE_DESCRIPTOR::VALID) {
+        return write!(f, "{}({:#018x}, invalid)", name, value);
+    }
+    let ap = match desc.read_as_enum(STAGE1_PAGE_DESCRIPTOR::AP) {
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1) => "RW_EL1",
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1_EL0) => "RW_EL1_EL0",
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RO_EL1) => "RO_EL1",
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RO_EL1_EL0) => "RO_EL1_EL0",
+        None => "?",
+    };
+    write!(
+        f,
+        "{}({:#018x}, {}, output {:#x}, AP {}, AttrIndx {}{}{}{})",
+        name,
+        value,
+        if desc.is_set(STAGE1_PAGE_DESCRIPTOR::TYPE) {
+            "Table"
+        } else {
+            "Block"
+        },
+        desc.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) << MmuGranule::SHIFT,
+        ap,
+        desc.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx),
+        if desc.is_set(STAGE1_PAGE_DESCRIPTOR::PXN) {
+            ", PXN"
+        } else {
+            ""
+        },
+        if desc.is_set(STAGE1_PAGE_DESCRIPTOR::UXN) {
+            ", UXN"
+        } else {
+            ""
+        },
+        if desc.is_set(STAGE1_PAGE_DESCRIPTOR::nG) {
+            ", nG"
+        } else {
+            ""
+        },
+    )
+}
+
+impl core::fmt::Debug for TableDescriptor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmt_descriptor(f, "TableDescriptor", self.value)
+    }
+}
+
+impl core::fmt::Debug for PageDescriptor {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        fmt_descriptor(f, "PageDescriptor", self.value)
+    }
 }
 
 // /// Convert the kernel's generic memory attributes to HW-specific attributes of the MMU.
@@ -491,6 +2069,10 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
             }
+            MemAttributes::NonCacheable => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL_NON_CACHEABLE)
+            }
             MemAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
@@ -513,34 +2095,525 @@ impl convert::From<AttributeFields>
         // Always set unprivileged exectue-never as long as userspace is not implemented yet.
         desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
 
+        desc += if attribute_fields.non_global {
+            STAGE1_PAGE_DESCRIPTOR::nG::True
+        } else {
+            STAGE1_PAGE_DESCRIPTOR::nG::False
+        };
+
+        desc += if attribute_fields.accessed {
+            STAGE1_PAGE_DESCRIPTOR::AF::True
+        } else {
+            STAGE1_PAGE_DESCRIPTOR::AF::False
+        };
+
         desc
     }
 }
 
-// impl PageDescriptor {
-//     /// Create an instance.
-//     ///
-//     /// Descriptor is invalid by default.
-//     pub const fn new_zeroed() -> Self {
-//         Self { value: 0 }
-//     }
+/// Decode the attributes of a page or block descriptor, the inverse of the conversion above.
+///
+/// Returns `None` for `AttrIndx` and `AP` values the kernel never writes.
+pub fn attributes_of_descriptor(desc: u64) -> Option<AttributeFields> {
+    let page = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(desc);
+    let mem_attributes = match page.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx) {
+        mair::NORMAL => MemAttributes::CacheableDRAM,
+        mair::NORMAL_NON_CACHEABLE => MemAttributes::NonCacheable,
+        mair::DEVICE => MemAttributes::Device,
+        _ => return None,
+    };
+    let acc_perms = match page.read_as_enum(STAGE1_PAGE_DESCRIPTOR::AP) {
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RO_EL1) => AccessPermissions::ReadOnly,
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1) => AccessPermissions::ReadWrite,
+        _ => return None,
+    };
+
+    let execute_never = page.is_set(STAGE1_PAGE_DESCRIPTOR::PXN);
+    Some(AttributeFields {
+        mem_attributes,
+        acc_perms,
+        execute_never,
+        // A writable and executable descriptor can only have been mapped with the exemption.
+        allow_wx: acc_perms == AccessPermissions::ReadWrite && !execute_never,
+        non_global: page.is_set(STAGE1_PAGE_DESCRIPTOR::nG),
+        accessed: page.is_set(STAGE1_PAGE_DESCRIPTOR::AF),
+    })
+}
 
-//     /// Create an instance.
-//     pub fn from_output_addr(output_addr: usize, attribute_fields: AttributeFields) -> Self {
-//         let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
-
-//         let shifted = output_addr as u64 >> Granule64KiB::SHIFT;
-//         val.write(
-//             STAGE1_PAGE_DESCRIPTOR::VALID::True
-//                 + STAGE1_PAGE_DESCRIPTOR::AF::True
-//                 + attribute_fields.into()
-//                 + STAGE1_PAGE_DESCRIPTOR::TYPE::Table
-//                 + STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted),
-//         );
-
-//         Self { value: val.get() }
-//     }
-// }
+/// Check that every `AttributeFields` decodes back from the descriptor it is encoded into.
+#[cfg(feature = "mmu_selftest")]
+fn attribute_round_trip() -> Result<(), MmuError> {
+    let mem_attributes = [
+        MemAttributes::CacheableDRAM,
+        MemAttributes::NonCacheable,
+        MemAttributes::Device,
+    ];
+    let acc_perms = [AccessPermissions::ReadOnly, AccessPermissions::ReadWrite];
+    for &mem_attributes in mem_attributes.iter() {
+        for &acc_perms in acc_perms.iter() {
+            for &(execute_never, non_global) in
+                [(false, false), (false, true), (true, false), (true, true)].iter()
+            {
+                let attributes = AttributeFields {
+                    mem_attributes,
+                    acc_perms,
+                    execute_never,
+                    allow_wx: acc_perms == AccessPermissions::ReadWrite && !execute_never,
+                    non_global,
+                    accessed: true,
+                };
+                let desc: register::FieldValue<u64, STAGE1_PAGE_DESCRIPTOR::Register> =
+                    attributes.into();
+                if attributes_of_descriptor(desc.value) != Some(attributes) {
+                    return Err(MmuError::Other(
+                        "Attributes do not survive a descriptor round trip",
+                    ));
+                }
+
+                let paddr = Address::new(config::RAM_BASE);
+                let page = PageDescriptor::from_output_addr(paddr, attributes);
+                if page.output_addr() != paddr || page.attributes() != Some(attributes) {
+                    return Err(MmuError::Other(
+                        "Page descriptor does not decode to what it was built from",
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, not(target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::host::{exclusive_ttbrs, HostPageAllocator},
+        memory::{IdentMapper, OffsetMapper},
+        mmu::{Translation, TranslationDescriptor},
+    };
+    use core::ops::RangeInclusive;
+    use std::collections::HashMap;
+
+    /// The TTBR0 region, with its tables in host memory.
+    fn region0() -> MmuReigon0<IdentMapper, HostPageAllocator> {
+        MmuReigon0 {
+            _alloc: PhantomData,
+            _mapper: PhantomData,
+        }
+    }
+
+    /// xorshift64, seeded by the test so a failure reproduces.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            let mut x = self.0;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            self.0 = x;
+            x
+        }
+
+        /// A granule aligned address below `1 << bits`.
+        fn page(&mut self, bits: u32) -> usize {
+            bit::extract(self.next() as usize, 0, bits as usize) & !(MmuGranule::SIZE - 1)
+        }
+    }
+
+    /// Number of pages the round trip maps.
+    const ROUND_TRIP_PAGES: usize = 512;
+
+    #[test]
+    fn map_translate_round_trip() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = region0();
+        let mut rng = Rng(0x5354_454c_4c41_5221);
+        let attributes = AttributeFields::default();
+
+        let mut mapped = HashMap::new();
+        while mapped.len() < ROUND_TRIP_PAGES {
+            let vaddr = rng.page(config::TTBR0_VA_BITS as u32);
+            let paddr = rng.page(pa_bits());
+            if mapped.contains_key(&vaddr) {
+                continue;
+            }
+            region
+                .map_page(Address::new(paddr), Address::new(vaddr), attributes)
+                .unwrap();
+            mapped.insert(vaddr, paddr);
+        }
+
+        for (&vaddr, &paddr) in mapped.iter() {
+            let offset = rng.next() as usize % MmuGranule::SIZE;
+            assert_eq!(
+                region.translate(Address::new(vaddr + offset)),
+                Some(Address::new(paddr + offset)),
+                "{:#x} + {:#x}",
+                vaddr,
+                offset
+            );
+            assert_eq!(region.attributes(Address::new(vaddr)), Some(attributes));
+        }
+
+        for (&vaddr, &paddr) in mapped.iter() {
+            assert_eq!(
+                region.unmap_page(Address::new(vaddr)),
+                Ok(Address::new(paddr))
+            );
+            assert_eq!(region.translate(Address::new(vaddr)), None);
+        }
+    }
+
+    #[test]
+    fn mapping_twice_fails() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = region0();
+        let mut rng = Rng(0x6d61_705f_7477_6963);
+        let attributes = AttributeFields::default();
+
+        for _ in 0..64 {
+            let vaddr = Address::new(rng.page(config::TTBR0_VA_BITS as u32));
+            let paddr = Address::new(rng.page(pa_bits()));
+            let other = Address::new(rng.page(pa_bits()));
+            if region.translate(vaddr).is_some() {
+                continue;
+            }
+            region.map_page(paddr, vaddr, attributes).unwrap();
+            assert_eq!(
+                region.map_page(other, vaddr, attributes),
+                Err(MmuError::AlreadyMappedInPage)
+            );
+            assert_eq!(region.translate(vaddr), Some(paddr));
+        }
+    }
+
+    static MEM_ATTRIBUTES: [MemAttributes; 3] = [
+        MemAttributes::CacheableDRAM,
+        MemAttributes::NonCacheable,
+        MemAttributes::Device,
+    ];
+    static ACC_PERMS: [AccessPermissions; 2] =
+        [AccessPermissions::ReadOnly, AccessPermissions::ReadWrite];
+
+    #[test]
+    fn unset_root_with_offset_mapper() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = MmuReigon0::<OffsetMapper<0x1000_0000>, HostPageAllocator> {
+            _alloc: PhantomData,
+            _mapper: PhantomData,
+        };
+        assert!(region.root().is_none());
+        assert!(region.root_mut().is_none());
+        assert_eq!(
+            region.map_page_to_current(
+                Address::new(config::RAM_BASE),
+                Address::new(0x4000_0000),
+                AttributeFields::default()
+            ),
+            Err(MmuError::NotInitialized)
+        );
+        assert_eq!(TTBR0_EL1.get_baddr(), 0);
+    }
+
+    #[test]
+    fn map_through_the_ttbrs() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut mmu = unsafe { MemoryManagementUnit::<HostPageAllocator>::new() };
+        let attributes = AttributeFields::default();
+        let low = Address::new(0x4000_0000);
+        let high = Address::new(0xffff_0000_4000_0000);
+        let paddr = Address::new(config::RAM_BASE);
+
+        mmu.map::<IdentMapper>(low, paddr, attributes).unwrap();
+        assert_ne!(TTBR0_EL1.get_baddr(), 0);
+        assert_eq!(TTBR1_EL1.get_baddr(), 0);
+        assert_eq!(mmu.translate::<IdentMapper>(high), None);
+
+        mmu.map::<IdentMapper>(high, paddr + MmuGranule::SIZE, attributes)
+            .unwrap();
+        assert_ne!(TTBR1_EL1.get_baddr(), 0);
+        assert_ne!(TTBR1_EL1.get_baddr(), TTBR0_EL1.get_baddr());
+        assert_eq!(mmu.translate::<IdentMapper>(low), Some(paddr));
+        assert_eq!(
+            mmu.translate::<IdentMapper>(high),
+            Some(paddr + MmuGranule::SIZE)
+        );
+
+        let gap = Address::new(1 << config::TTBR0_VA_BITS);
+        assert_eq!(
+            mmu.map::<IdentMapper>(gap, paddr, attributes),
+            Err(MmuError::NotInEitherHalf {
+                addr: gap.into_usize()
+            })
+        );
+        assert_eq!(mmu.translate::<IdentMapper>(gap), None);
+
+        assert_eq!(mmu.unmap::<IdentMapper>(low), Ok(paddr));
+        assert_eq!(mmu.unmap::<IdentMapper>(high), Ok(paddr + MmuGranule::SIZE));
+        assert_eq!(mmu.translate::<IdentMapper>(low), None);
+        assert_eq!(mmu.translate::<IdentMapper>(high), None);
+        // The tables below the roots were released, the roots stay installed.
+        assert!(mmu.ttbl0::<IdentMapper>().root().unwrap().is_empty());
+        assert!(mmu.ttbl1::<IdentMapper>().root().unwrap().is_empty());
+    }
+
+    /// Every `AttributeFields` there is, W^X violations included.
+    fn all_attributes() -> impl Iterator<Item = AttributeFields> {
+        MEM_ATTRIBUTES.iter().flat_map(|&mem_attributes| {
+            ACC_PERMS.iter().flat_map(move |&acc_perms| {
+                (0..16).map(move |flags| AttributeFields {
+                    mem_attributes,
+                    acc_perms,
+                    execute_never: flags & 1 != 0,
+                    allow_wx: flags & 2 != 0,
+                    non_global: flags & 4 != 0,
+                    accessed: flags & 8 != 0,
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn attributes_survive_descriptor_round_trip() {
+        for attributes in all_attributes() {
+            // Only a writable and executable mapping tells the exemption apart.
+            let expected = AttributeFields {
+                allow_wx: attributes.acc_perms == AccessPermissions::ReadWrite
+                    && !attributes.execute_never,
+                ..attributes
+            };
+            let desc: register::FieldValue<u64, STAGE1_PAGE_DESCRIPTOR::Register> =
+                attributes.into();
+            assert_eq!(
+                attributes_of_descriptor(desc.value),
+                Some(expected),
+                "{:?}",
+                attributes
+            );
+
+            let paddr = Address::new(config::RAM_BASE);
+            let page = PageDescriptor::from_output_addr(paddr, attributes);
+            assert_eq!(page.output_addr(), paddr, "{:?}", attributes);
+            assert_eq!(page.attributes(), Some(expected), "{:?}", attributes);
+        }
+    }
+
+    #[test]
+    fn access_flag_round_trip() {
+        let paddr = Address::new(config::RAM_BASE);
+        for &accessed in [false, true].iter() {
+            let attributes = AttributeFields {
+                accessed,
+                ..AttributeFields::default()
+            };
+            let page = PageDescriptor::from_output_addr(paddr, attributes);
+            assert_eq!(
+                STAGE1_PAGE_DESCRIPTOR::AF::True.matches_all(page.value),
+                accessed
+            );
+            assert_eq!(page.attributes(), Some(attributes));
+        }
+    }
+
+    #[test]
+    fn mapped_attributes_round_trip() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = region0();
+        let mut vaddr = Address::new(0x4000_0000);
+        // Mapping without the access flag depends on `TCR_EL1`, which the host does not have.
+        let mapped = all_attributes()
+            .filter(|attributes| attributes.validate().is_ok() && attributes.accessed);
+        for attributes in mapped {
+            region
+                .map_page(Address::new(config::RAM_BASE), vaddr, attributes)
+                .unwrap();
+            let decoded = region.attributes(vaddr).unwrap();
+            assert_eq!(decoded.mem_attributes, attributes.mem_attributes);
+            assert_eq!(decoded.acc_perms, attributes.acc_perms);
+            assert_eq!(decoded.execute_never, attributes.execute_never);
+            assert_eq!(decoded.non_global, attributes.non_global);
+            assert!(decoded.validate().is_ok());
+            vaddr = vaddr + MmuGranule::SIZE;
+        }
+    }
+
+    #[test]
+    fn unmapped_translates_to_none() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = region0();
+        let mut rng = Rng(0x756e_6d61_7070_6564);
+
+        // No root table yet.
+        let vaddr = Address::new(rng.page(config::TTBR0_VA_BITS as u32));
+        assert_eq!(region.translate(vaddr), None);
+        assert_eq!(region.unmap_page(vaddr), Err(MmuError::NotInitialized));
+
+        let paddr = Address::new(rng.page(pa_bits()));
+        region
+            .map_page(paddr, vaddr, AttributeFields::default())
+            .unwrap();
+        for _ in 0..256 {
+            let other = Address::new(rng.page(config::TTBR0_VA_BITS as u32));
+            if other != vaddr {
+                assert_eq!(region.translate(other), None);
+                assert_eq!(region.unmap_page(other), Err(MmuError::NotMapped));
+            }
+        }
+        // The neighbours share the level 3 table.
+        if vaddr.into_usize() != 0 {
+            assert_eq!(region.translate(vaddr - MmuGranule::SIZE), None);
+        }
+        assert_eq!(region.translate(vaddr + MmuGranule::SIZE), None);
+    }
+
+    /// Device pages of `two_range_layout`, identity mapped.
+    fn layout_device() -> RangeInclusive<usize> {
+        0x4000..=0x5fff
+    }
+
+    /// Read-only pages of `two_range_layout`, mapped at `offset`.
+    fn layout_read_only() -> RangeInclusive<usize> {
+        0x8000..=0x9fff
+    }
+
+    const LAYOUT_DEVICE: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::Device,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+        allow_wx: false,
+        non_global: false,
+        accessed: true,
+    };
+
+    const LAYOUT_READ_ONLY: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: AccessPermissions::ReadOnly,
+        execute_never: false,
+        allow_wx: false,
+        non_global: false,
+        accessed: true,
+    };
+
+    /// 16 pages from 0 on, two of them special.
+    fn two_range_layout(offset: usize) -> KernelVirtualLayout<2> {
+        KernelVirtualLayout::new(
+            0xffff,
+            [
+                TranslationDescriptor {
+                    name: "Device",
+                    virtual_range: layout_device,
+                    physical_range_translation: Translation::Identity,
+                    attribute_fields: LAYOUT_DEVICE,
+                },
+                TranslationDescriptor {
+                    name: "Read-only",
+                    virtual_range: layout_read_only,
+                    physical_range_translation: Translation::Offset(offset),
+                    attribute_fields: LAYOUT_READ_ONLY,
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn map_layout_identity_and_offset() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = region0();
+        let offset = 0x10_0000;
+        region.map_layout(&two_range_layout(offset)).unwrap();
+
+        for vaddr in (0..=0xffff).step_by(MmuGranule::SIZE) {
+            let (paddr, expected) = match vaddr {
+                0x4000..=0x5fff => (vaddr, LAYOUT_DEVICE),
+                0x8000..=0x9fff => (offset + vaddr - 0x8000, LAYOUT_READ_ONLY),
+                _ => (vaddr, AttributeFields::default()),
+            };
+            let vaddr = Address::new(vaddr);
+            assert_eq!(region.translate(vaddr), Some(Address::new(paddr)));
+            let attributes = region.attributes(vaddr).unwrap();
+            assert_eq!(attributes.mem_attributes, expected.mem_attributes);
+            assert_eq!(attributes.acc_perms, expected.acc_perms);
+            assert_eq!(attributes.execute_never, expected.execute_never);
+        }
+        assert_eq!(region.translate(Address::new(0x10000)), None);
+    }
+
+    #[test]
+    fn map_layout_rolls_back() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = region0();
+        // The read-only range lands beyond the physical address size.
+        let offset = 1 << pa_bits();
+        assert_eq!(
+            region.map_layout(&two_range_layout(offset)),
+            Err(MmuError::PhysicalOutOfRange {
+                addr: offset,
+                pa_bits: pa_bits(),
+            })
+        );
+        for vaddr in (0..=0xffff).step_by(MmuGranule::SIZE) {
+            assert_eq!(region.translate(Address::new(vaddr)), None);
+        }
+    }
+
+    #[test]
+    fn map_layout_in_the_wrong_half() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut region = MmuReigon1::<IdentMapper, HostPageAllocator> {
+            _alloc: PhantomData,
+            _mapper: PhantomData,
+        };
+        assert_eq!(
+            region.map_layout(&two_range_layout(0)),
+            Err(MmuError::WrongHalf { addr: 0 })
+        );
+        assert_eq!(TTBR1_EL1.get_baddr(), 0);
+    }
+
+    #[test]
+    fn table_beyond_physical_address_size() {
+        let paddr = Address::new(1 << pa_bits());
+        assert_eq!(
+            unsafe { TableSection::from_paddr::<IdentMapper>(paddr) }.err(),
+            Some(MmuError::PhysicalOutOfRange {
+                addr: paddr.into_usize(),
+                pa_bits: pa_bits(),
+            })
+        );
+        assert_eq!(
+            unsafe { TableSection::from_paddr::<IdentMapper>(Address::new(0)) }.err(),
+            Some(MmuError::NullTable)
+        );
+    }
+
+    #[test]
+    fn poison_registry() {
+        let page = Address::<Virtual>::new(0xffff_7000_0000_0000);
+        let guard = Address::<Virtual>::new(0);
+
+        assert_eq!(poison::lookup(page.into_usize()), None);
+        poison::register(page, poison::GuardKind::Poison).unwrap();
+        poison::register(guard, poison::GuardKind::Guard).unwrap();
+        assert_eq!(
+            poison::lookup(page.into_usize() + 8),
+            Some(poison::GuardKind::Poison)
+        );
+        assert_eq!(poison::lookup(0x10), Some(poison::GuardKind::Guard));
+        assert_eq!(poison::lookup(page.into_usize() + MmuGranule::SIZE), None);
+
+        // Registering a page again changes its kind.
+        poison::register(page, poison::GuardKind::Guard).unwrap();
+        assert_eq!(
+            poison::lookup(page.into_usize()),
+            Some(poison::GuardKind::Guard)
+        );
+    }
+}
 
 // //--------------------------------------------------------------------------------------------------
 // // Public Code
@@ -1,4 +1,5 @@
 pub mod cpu;
 pub mod exception;
+mod mem;
 pub mod mmu;
 pub mod reg;
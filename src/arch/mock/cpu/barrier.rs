@@ -0,0 +1,16 @@
+//! Barriers are not needed for the host stand-ins, they all do nothing.
+
+#[inline(always)]
+pub fn dsb_sy() {}
+
+#[inline(always)]
+pub fn dsb_ish() {}
+
+#[inline(always)]
+pub fn dsb_ishst() {}
+
+#[inline(always)]
+pub fn dmb_ish() {}
+
+#[inline(always)]
+pub fn isb() {}
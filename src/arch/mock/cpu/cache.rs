@@ -0,0 +1,30 @@
+//! Host stand-in for cache maintenance, only what the arch independent code uses.
+
+use crate::memory::{AddressRange, Virtual};
+
+/// A common line size, there is no cache maintenance on the host.
+pub fn dcache_line_size() -> usize {
+    64
+}
+
+/// A common line size, there is no cache maintenance on the host.
+pub fn icache_line_size() -> usize {
+    64
+}
+
+/// `dc zva` does not exist on the host.
+pub fn zero_block_size() -> Option<usize> {
+    None
+}
+
+/// Never called, as `zero_block_size` returns `None`.
+///
+/// # Safety
+///
+/// - Must not be called.
+pub unsafe fn zero_block(_addr: usize) {
+    unreachable!()
+}
+
+/// There is no cache maintenance on the host.
+pub fn clean_and_invalidate_dcache_range(_range: AddressRange<Virtual>) {}
@@ -0,0 +1,20 @@
+//! Host stand-in for switching between threads, there is only the one thread of the host.
+
+/// Same interface as on aarch64, nothing is ever saved.
+#[derive(Copy, Clone, Debug)]
+pub struct Context;
+
+impl Context {
+    pub const fn zeroed() -> Self {
+        Self
+    }
+
+    pub fn new(_entry: extern "C" fn() -> !, _stack_end: usize) -> Self {
+        Self
+    }
+}
+
+/// There are no other threads to switch to on the host.
+pub unsafe fn switch(_from: *mut Context, _to: *const Context) {
+    panic!("cpu::context::switch called on the host")
+}
@@ -0,0 +1,103 @@
+//! Host stand-in for the architectural processor code.
+
+pub mod barrier;
+pub mod cache;
+pub mod context;
+mod interrupt;
+pub mod psci;
+pub mod smp;
+pub mod timer;
+pub mod tlb;
+
+pub use interrupt::{enable_irq, without_interrupts, InterruptGuard};
+
+/// Does nothing.
+#[inline(always)]
+pub fn nop() {}
+
+/// Return an address on the current stack.
+#[inline(always)]
+pub fn stack_pointer() -> usize {
+    let marker = 0u8;
+    &marker as *const _ as usize
+}
+
+/// Host code runs like EL1 code.
+#[inline(always)]
+pub fn current_el() -> u8 {
+    1
+}
+
+/// Panic with the actual exception level if it is not `expected`.
+#[track_caller]
+pub fn assert_el(expected: u8) {
+    let el = current_el();
+    assert!(
+        el == expected,
+        "Running at EL{}, expected EL{}",
+        el,
+        expected
+    );
+}
+
+/// Host code never traps FP/SIMD.
+#[inline(always)]
+pub fn enable_fp_simd() {}
+
+#[inline(always)]
+pub fn trap_fp_simd_el0() {}
+
+/// Report a 48 bit physical address size and support for the 4 KiB, 16 KiB and 64 KiB granules.
+#[inline(always)]
+pub fn memory_model_features() -> u64 {
+    0x0010_0005
+}
+
+/// Report no hardware management of the access flag.
+#[inline(always)]
+pub fn memory_model_features1() -> u64 {
+    0
+}
+
+/// No other core waits for events on the host.
+#[inline(always)]
+pub fn send_event() {}
+
+/// There are no events to wait for on the host, spin instead.
+#[inline(always)]
+pub fn wait_for_event() {
+    core::hint::spin_loop()
+}
+
+#[inline(always)]
+pub fn spin_hint() {
+    core::hint::spin_loop()
+}
+
+/// Spin forever.
+#[inline(always)]
+pub fn wait_forever() -> ! {
+    loop {
+        core::hint::spin_loop()
+    }
+}
+
+/// There is no machine to reset on the host.
+pub fn reset() -> ! {
+    panic!("cpu::reset called on the host")
+}
+
+/// There is no machine to power off on the host.
+pub fn poweroff() -> ! {
+    panic!("cpu::poweroff called on the host")
+}
+
+/// There is no QEMU to exit on the host.
+pub fn qemu_exit_failure() -> ! {
+    panic!("cpu::qemu_exit_failure called on the host")
+}
+
+/// There is no QEMU to exit on the host.
+pub fn qemu_exit_success() -> ! {
+    panic!("cpu::qemu_exit_success called on the host")
+}
@@ -0,0 +1,25 @@
+//! Host stand-in for the PSCI calls.
+
+/// Instruction used to reach the PSCI implementation.
+#[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PsciConduit {
+    Smc,
+    Hvc,
+}
+
+pub const CPU_ON: u32 = 0xC400_0003;
+pub const SYSTEM_OFF: u32 = 0x8400_0008;
+pub const SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// PSCI `NOT_SUPPORTED`.
+const NOT_SUPPORTED: i64 = -1;
+
+/// There is no firmware on the host, every call fails.
+///
+/// # Safety
+///
+/// - Safe on the host, kept `unsafe` to match the aarch64 signature.
+pub unsafe fn call(_function: u32, _arg0: u64, _arg1: u64, _arg2: u64) -> i64 {
+    NOT_SUPPORTED
+}
@@ -0,0 +1,83 @@
+//! Host stand-in for symmetric multiprocessing, the host is treated as a single boot core.
+
+use crate::bsp;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Interior mutability for statics that only the boot core may touch.
+///
+/// Same interface as on aarch64, without the core check.
+pub struct SingleCoreCell<T> {
+    value: UnsafeCell<T>,
+    borrowed: AtomicBool,
+}
+
+unsafe impl<T: Send> Sync for SingleCoreCell<T> {}
+
+/// Return the executing core's id.
+#[inline(always)]
+pub fn core_id() -> usize {
+    bsp::cpu::BOOT_CORE_ID
+}
+
+/// Check if the executing core is the one the BSP boots on.
+#[inline(always)]
+pub fn is_boot_core() -> bool {
+    true
+}
+
+impl<T> SingleCoreCell<T> {
+    /// Create an instance.
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            borrowed: AtomicBool::new(false),
+        }
+    }
+
+    /// Run `f` with exclusive access to the value.
+    ///
+    /// Panics if `with` is already running for the same cell.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        match self.try_with(f) {
+            Some(result) => result,
+            None => panic!("Single core state accessed again while in use"),
+        }
+    }
+
+    /// Like `with`, but return `None` instead of running `f` if `with` is already running, e.g.
+    /// on the panic path.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        if self.borrowed.swap(true, Ordering::Acquire) {
+            return None;
+        }
+        let _borrow = Borrow(&self.borrowed);
+        Some(f(unsafe { &mut *self.value.get() }))
+    }
+
+    /// Get a mutable reference to the value.
+    ///
+    /// Panics if `with` is running.
+    ///
+    /// # Safety
+    ///
+    /// - No other reference to the value may be alive at the same time.
+    pub unsafe fn get_mut(&self) -> &mut T {
+        assert!(
+            !self.borrowed.load(Ordering::Acquire),
+            "Single core state accessed while in use"
+        );
+        &mut *self.value.get()
+    }
+}
+
+/// Clears the borrow flag of a `SingleCoreCell` when `with` returns or unwinds.
+struct Borrow<'a>(&'a AtomicBool);
+
+impl Drop for Borrow<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
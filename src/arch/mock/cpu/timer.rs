@@ -0,0 +1,24 @@
+//! Host stand-in for the generic timer, the counter never advances and nothing fires.
+
+use core::time::Duration;
+
+/// The 62.5 MHz QEMU reports.
+#[inline(always)]
+pub fn frequency() -> u64 {
+    62_500_000
+}
+
+#[inline(always)]
+pub fn counter() -> u64 {
+    0
+}
+
+/// Convert `duration` into counter ticks, saturating.
+pub fn ticks(duration: Duration) -> u64 {
+    let ticks = duration.as_nanos() * frequency() as u128 / 1_000_000_000;
+    ticks.min(u64::MAX as u128) as u64
+}
+
+pub fn set_deadline(_ticks: u64) {}
+
+pub fn disable() {}
@@ -0,0 +1,19 @@
+//! There is no TLB on the host, translation tables are only walked in software.
+
+use crate::memory::{Address, Physical, Virtual};
+
+#[inline(always)]
+pub fn invalidate_page(_vaddr: Address<Virtual>) {}
+
+#[inline(always)]
+pub fn invalidate_all() {}
+
+/// Table pages are ordinary host memory, so every address is considered writable.
+pub fn is_writable(_vaddr: Address<Virtual>) -> bool {
+    true
+}
+
+/// Host memory is not translated, every address maps flat.
+pub fn translate(vaddr: Address<Virtual>) -> Option<Address<Physical>> {
+    Some(Address::new(vaddr.into_usize()))
+}
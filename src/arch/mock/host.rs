@@ -0,0 +1,55 @@
+//! Test support on top of the mock: host memory standing in for physical pages, and exclusive use
+//! of the translation table base registers.
+
+use super::regs::{TTBR0_EL1, TTBR1_EL1};
+use crate::{
+    bsp::config::MmuGranule,
+    memory::{Address, Page, PageAllocator},
+};
+use std::{
+    alloc::{self, Layout},
+    sync::{Mutex, MutexGuard},
+};
+
+/// Hands out zeroed, granule aligned host memory. Its address doubles as the physical one, so the
+/// tables built in it are accessed through `IdentMapper`.
+pub struct HostPageAllocator;
+
+fn layout(num: usize) -> Layout {
+    Layout::from_size_align(num * MmuGranule::SIZE, MmuGranule::SIZE).unwrap()
+}
+
+impl PageAllocator for HostPageAllocator {
+    fn alloc_pages(num: usize) -> Result<Page<Self>, &'static str> {
+        if num == 0 {
+            return Err("No pages requested");
+        }
+        let ptr = unsafe { alloc::alloc_zeroed(layout(num)) };
+        if ptr.is_null() {
+            return Err("Host out of memory");
+        }
+        Ok(unsafe { Page::from_raw(Address::new(ptr as usize), num) })
+    }
+
+    unsafe fn free_pages(pages: &mut Page<Self>) -> Result<(), &'static str> {
+        alloc::dealloc(
+            pages.base().into_usize() as *mut u8,
+            layout(pages.page_num()),
+        );
+        Ok(())
+    }
+}
+
+static TTBR_LOCK: Mutex<()> = Mutex::new(());
+
+/// Serialize the tests that use the translation table base registers, which are shared by all
+/// test threads, and start each of them with no table installed.
+pub fn exclusive_ttbrs() -> MutexGuard<'static, ()> {
+    // A failed test does not leave the registers in a state the next one depends on.
+    let guard = TTBR_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    TTBR0_EL1.set_baddr(0);
+    TTBR1_EL1.set_baddr(0);
+    guard
+}
@@ -0,0 +1,19 @@
+//! Host stand-in for the architecture code, so the arch independent logic builds and runs under
+//! `cargo test` off-target.
+//!
+//! Barriers and TLB maintenance do nothing and the translation table base registers are plain
+//! memory. The translation table code itself is shared with aarch64, its tests build tables in
+//! host memory with `host::HostPageAllocator`.
+//!
+//! `./test.sh` runs the tests, i.e. `cargo test --lib` with the host triple as `--target`, as the
+//! default target is the kernel's.
+
+pub mod cpu;
+#[cfg(test)]
+pub mod host;
+#[path = "../aarch64/mmu.rs"]
+pub mod mmu;
+mod regs;
+
+pub use cortex_a::regs::*;
+pub use regs::{TTBR0_EL1, TTBR1_EL1};
@@ -0,0 +1,27 @@
+//! In-memory translation table base registers.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A translation table base register that only remembers the base address.
+pub struct Ttbr {
+    baddr: AtomicU64,
+}
+
+impl Ttbr {
+    const fn new() -> Self {
+        Self {
+            baddr: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get_baddr(&self) -> u64 {
+        self.baddr.load(Ordering::SeqCst)
+    }
+
+    pub fn set_baddr(&self, addr: u64) {
+        self.baddr.store(addr, Ordering::SeqCst)
+    }
+}
+
+pub static TTBR0_EL1: Ttbr = Ttbr::new();
+pub static TTBR1_EL1: Ttbr = Ttbr::new();
@@ -3,3 +3,9 @@ mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::*;
+
+#[cfg(not(target_arch = "aarch64"))]
+mod mock;
+
+#[cfg(not(target_arch = "aarch64"))]
+pub use mock::*;
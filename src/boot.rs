@@ -1,14 +1,228 @@
-use crate::memory::{AddressRange, Physical};
+use crate::{
+    bsp::config::{self, MmuGranule},
+    common::align_up,
+    memory::{Address, AddressRange, Physical},
+    mmu::mmu::TranslationConfig,
+};
 
+/// Value of `BootInfo::magic` written by the bootloader, "STELLAR1" in ASCII.
+pub const BOOT_INFO_MAGIC: u64 = 0x5354_454c_4c41_5231;
+
+/// Value of `BootInfo::version`, bumped on every change to the layout or meaning of the fields.
+pub const BOOT_INFO_VERSION: u32 = 1;
+
+/// Name of the symbol holding `BOOT_INFO_VERSION` in the kernel ELF.
+pub const BOOT_INFO_ABI_SYMBOL: &str = "__boot_info_abi_version";
+
+/// `BOOT_INFO_VERSION` of the kernel, which the bootloader looks up in the ELF symbol table to
+/// refuse a kernel built against another layout before jumping to it.
+///
+/// Kept by the linker script even though nothing references it.
+#[no_mangle]
+#[used]
+#[link_section = ".rodata.boot_info_abi"]
+#[allow(non_upper_case_globals)]
+pub static __boot_info_abi_version: u32 = BOOT_INFO_VERSION;
+
+/// Name of the function the bootloader jumps to. The ELF entry is the direct boot stub, which a
+/// loader has to enter with the MMU off.
+pub const KERNEL_ENTRY_SYMBOL: &str = "runtime_init";
+
+/// First word of a flattened device tree, stored big endian.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// What the bootloader hands over to the kernel, below the top of the kernel stack.
+///
+/// Bootloader and kernel are built separately, so the layout is fixed to this C struct and checked
+/// at compile time:
+///
+/// ```c
+/// struct range { uint64_t addr; uint64_t size; };
+///
+/// struct boot_info {                  // size 80, align 16
+///     uint64_t magic;                 // 0
+///     uint32_t version;               // 8
+///     struct range used_pages;        // 16
+///     struct {
+///         uint64_t granule;
+///         uint8_t t0sz;
+///         uint8_t t1sz;
+///     } translation;                  // 32
+///     struct range ram;               // 48
+///     uint64_t phys_offset;           // 64
+/// };
+/// ```
 #[derive(Debug)]
 #[repr(C, align(16))]
 pub struct BootInfo {
+    pub magic: u64,
+    pub version: u32,
     pub used_pages: AddressRange<Physical>,
-    pub _fill: usize,
+    /// Translation parameters the bootloader programmed before jumping to the kernel.
+    pub translation: TranslationConfig,
+    /// Physical RAM, all of it mapped at `phys_offset`.
+    pub ram: AddressRange<Physical>,
+    /// Offset of the linear map of physical memory in the TTBR1 half.
+    pub phys_offset: usize,
+}
+
+// Sizes and alignments are checked here, the offsets documented on `BootInfo` by the tests below.
+const _: () = assert!(core::mem::size_of::<AddressRange<Physical>>() == 16);
+const _: () = assert!(core::mem::align_of::<AddressRange<Physical>>() == 8);
+const _: () = assert!(core::mem::size_of::<TranslationConfig>() == 16);
+const _: () = assert!(core::mem::align_of::<TranslationConfig>() == 8);
+const _: () = assert!(core::mem::size_of::<BootInfo>() == 80);
+const _: () = assert!(core::mem::align_of::<BootInfo>() == 16);
+
+impl BootInfo {
+    /// Check if the bootloader filled in this structure, in the layout this kernel expects.
+    pub fn is_valid(&self) -> bool {
+        self.magic == BOOT_INFO_MAGIC && self.version == BOOT_INFO_VERSION
+    }
+
+    /// Synthesize the boot info for a kernel started without the bootloader, e.g. with QEMU
+    /// `-kernel`, from what the direct boot stub in `cpu::boot` set up.
+    ///
+    /// The stub maps RAM at `config::PHYS_MAP_OFFSET` like the bootloader and uses RAM from its
+    /// start, where QEMU places the device tree, up to `used_end` for the image, its tables and the
+    /// stack. `handover` is what the loader passed in `x0`. If it is a device tree in RAM, that is
+    /// reserved too.
+    ///
+    /// # Safety
+    ///
+    /// - RAM must be mapped at `config::PHYS_MAP_OFFSET`.
+    pub unsafe fn from_direct_boot(handover: usize, used_end: usize) -> Self {
+        let ram = AddressRange::<Physical>::new_raw(config::RAM_BASE, config::RAM_SIZE);
+        let mut used_end = used_end;
+        if ram.contains(Address::new(handover)) {
+            let header = (config::PHYS_MAP_OFFSET + handover) as *const u32;
+            if u32::from_be(header.read_volatile()) == FDT_MAGIC {
+                let total_size = u32::from_be(header.add(1).read_volatile()) as usize;
+                used_end = used_end.max(align_up(handover + total_size, MmuGranule::SIZE));
+            }
+        }
+
+        Self {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            used_pages: AddressRange::new_range(ram.addr(), Address::new(used_end)),
+            translation: TranslationConfig::current(),
+            ram,
+            phys_offset: config::PHYS_MAP_OFFSET,
+        }
+    }
 }
 
 impl core::fmt::Display for BootInfo {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "Used pages: {}", self.used_pages)
+        write!(
+            f,
+            "Used pages: {}, translation: {}, RAM: {} at offset {:#x}",
+            self.used_pages, self.translation, self.ram, self.phys_offset
+        )
+    }
+}
+
+#[cfg(all(test, not(target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use core::{convert::TryInto, mem::size_of};
+
+    /// The bytes of a `BootInfo` as they lie below the top of the kernel stack.
+    #[repr(C, align(16))]
+    struct Raw([u8; size_of::<BootInfo>()]);
+
+    impl Raw {
+        fn u64_at(&self, offset: usize) -> u64 {
+            u64::from_ne_bytes(self.0[offset..offset + 8].try_into().unwrap())
+        }
+
+        fn u32_at(&self, offset: usize) -> u32 {
+            u32::from_ne_bytes(self.0[offset..offset + 4].try_into().unwrap())
+        }
+
+        fn set_u64(&mut self, offset: usize, value: u64) {
+            self.0[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+
+    // Every field holds a different value, so two swapped fields of the same size show.
+    const USED_PAGES: (usize, usize) = (0x4000_0000, 0x2_0000);
+    const GRANULE: usize = MmuGranule::SIZE;
+    const T0SZ: u8 = 16;
+    const T1SZ: u8 = 17;
+    const RAM: (usize, usize) = (0x4010_0000, 0x3ff0_0000);
+    const PHYS_OFFSET: usize = 0xffff_8000_0000_0000;
+
+    #[test]
+    fn written_like_the_bootloader() {
+        let mut raw = Raw([0; size_of::<BootInfo>()]);
+        // bigbang writes the whole struct at once, like this.
+        unsafe {
+            (raw.0.as_mut_ptr() as *mut BootInfo).write(BootInfo {
+                magic: BOOT_INFO_MAGIC,
+                version: BOOT_INFO_VERSION,
+                used_pages: AddressRange::new_raw(USED_PAGES.0, USED_PAGES.1),
+                translation: TranslationConfig {
+                    granule: GRANULE,
+                    t0sz: T0SZ,
+                    t1sz: T1SZ,
+                },
+                ram: AddressRange::new_raw(RAM.0, RAM.1),
+                phys_offset: PHYS_OFFSET,
+            })
+        };
+
+        assert_eq!(raw.u64_at(0), BOOT_INFO_MAGIC);
+        assert_eq!(raw.u32_at(8), BOOT_INFO_VERSION);
+        assert_eq!(raw.u64_at(16), USED_PAGES.0 as u64);
+        assert_eq!(raw.u64_at(24), USED_PAGES.1 as u64);
+        assert_eq!(raw.u64_at(32), GRANULE as u64);
+        assert_eq!(raw.0[40], T0SZ);
+        assert_eq!(raw.0[41], T1SZ);
+        assert_eq!(raw.u64_at(48), RAM.0 as u64);
+        assert_eq!(raw.u64_at(56), RAM.1 as u64);
+        assert_eq!(raw.u64_at(64), PHYS_OFFSET as u64);
+    }
+
+    #[test]
+    fn read_like_the_kernel() {
+        let mut raw = Raw([0; size_of::<BootInfo>()]);
+        raw.set_u64(0, BOOT_INFO_MAGIC);
+        raw.0[8..12].copy_from_slice(&BOOT_INFO_VERSION.to_ne_bytes());
+        raw.set_u64(16, USED_PAGES.0 as u64);
+        raw.set_u64(24, USED_PAGES.1 as u64);
+        raw.set_u64(32, GRANULE as u64);
+        raw.0[40] = T0SZ;
+        raw.0[41] = T1SZ;
+        raw.set_u64(48, RAM.0 as u64);
+        raw.set_u64(56, RAM.1 as u64);
+        raw.set_u64(64, PHYS_OFFSET as u64);
+
+        let boot_info = unsafe { &*(raw.0.as_ptr() as *const BootInfo) };
+        assert!(boot_info.is_valid());
+        assert_eq!(
+            boot_info.used_pages,
+            AddressRange::new_raw(USED_PAGES.0, USED_PAGES.1)
+        );
+        assert_eq!(
+            boot_info.translation,
+            TranslationConfig {
+                granule: GRANULE,
+                t0sz: T0SZ,
+                t1sz: T1SZ,
+            }
+        );
+        assert_eq!(boot_info.ram, AddressRange::new_raw(RAM.0, RAM.1));
+        assert_eq!(boot_info.phys_offset, PHYS_OFFSET);
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let mut raw = Raw([0; size_of::<BootInfo>()]);
+        raw.set_u64(0, BOOT_INFO_MAGIC);
+        raw.0[8..12].copy_from_slice(&(BOOT_INFO_VERSION + 1).to_ne_bytes());
+        let boot_info = unsafe { &*(raw.0.as_ptr() as *const BootInfo) };
+        assert!(!boot_info.is_valid());
     }
 }
@@ -1,5 +1,4 @@
-#[cfg(target_arch = "aarch64")]
+// The host build with `arch::mock` uses the virt board description as well.
 mod virt;
 
-#[cfg(target_arch = "aarch64")]
 pub use virt::*;
@@ -1,3 +1,85 @@
-use crate::mmu::mmu::Granule4KiB;
+use crate::{cpu::psci::PsciConduit, mmu::mmu::Granule4KiB};
+use core::time::Duration;
 
-pub type MmuGranule = Granule4KiB;
\ No newline at end of file
+pub type MmuGranule = Granule4KiB;
+
+/// Width of the TTBR0 (low half) address space.
+pub const TTBR0_VA_BITS: usize = 48;
+
+/// Width of the TTBR1 (high half) address space.
+pub const TTBR1_VA_BITS: usize = 48;
+
+/// Virtual address at which the kernel maps all of physical memory.
+pub const PHYS_MAP_OFFSET: usize = 0xFFFF_8000_0000_0000;
+
+/// Virtual base of the kernel stack the bootloader sets up, with an unmapped guard page below.
+///
+/// Passed to `exception.s` as `KERNEL_STACK_BOTTOM`.
+pub const KERNEL_STACK_VBASE: usize = 0xFFFF_1000_0000_0000;
+
+/// Size of the kernel stack in pages.
+pub const KERNEL_STACK_PAGES: usize = 512;
+
+/// Virtual window in the TTBR1 half reserved for `DmaBuffer` mappings, apart from the linear map.
+pub const DMA_WINDOW_VBASE: usize = 0xFFFF_2000_0000_0000;
+
+/// Size of the DMA window.
+pub const DMA_WINDOW_SIZE: usize = 1 << 30;
+
+/// Number of pages the kernel's early page allocator hands out before the frame allocator is up.
+pub const EARLY_PAGES: usize = 256;
+
+/// Physical start of RAM.
+pub const RAM_BASE: usize = 0x4000_0000;
+
+/// Size of RAM, as QEMU is started with `-m 1024M`.
+pub const RAM_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Physical address of the PL011 UART.
+pub const UART0_PADDR: usize = 0x0900_0000;
+
+/// Virtual address of the UART in the TTBR1 half, mapped by the bootloader for the kernel.
+pub const UART0_VBASE: usize = PHYS_MAP_OFFSET + UART0_PADDR;
+
+/// Physical address of the PL031 real time clock.
+pub const RTC_PADDR: usize = 0x0901_0000;
+
+/// Virtual address of the real time clock in the TTBR1 half.
+pub const RTC_VBASE: usize = PHYS_MAP_OFFSET + RTC_PADDR;
+
+/// Physical address of the GICv2 distributor.
+pub const GIC_DIST_PADDR: usize = 0x0800_0000;
+
+/// Virtual address of the GIC distributor in the TTBR1 half.
+pub const GIC_DIST_VBASE: usize = PHYS_MAP_OFFSET + GIC_DIST_PADDR;
+
+/// Physical address of the GICv2 CPU interface.
+pub const GIC_CPU_PADDR: usize = 0x0801_0000;
+
+/// Virtual address of the GIC CPU interface in the TTBR1 half.
+pub const GIC_CPU_VBASE: usize = PHYS_MAP_OFFSET + GIC_CPU_PADDR;
+
+/// Interrupt ID of the EL1 physical timer, PPI 14.
+pub const TIMER_IRQ: u32 = 30;
+
+/// Time the kernel gets between two `watchdog::kick` calls before the machine is reset.
+pub const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Physical address of the first virtio-mmio transport.
+pub const VIRTIO_MMIO_PADDR: usize = 0x0a00_0000;
+
+/// Number of virtio-mmio transports.
+pub const VIRTIO_MMIO_COUNT: usize = 32;
+
+/// Distance between two virtio-mmio transports.
+pub const VIRTIO_MMIO_STRIDE: usize = 0x200;
+
+/// Virtual address of the virtio-mmio transports in the TTBR1 half.
+pub const VIRTIO_MMIO_VBASE: usize = PHYS_MAP_OFFSET + VIRTIO_MMIO_PADDR;
+
+/// Cache line size to step cache maintenance by instead of the smallest sizes `CTR_EL0` reports,
+/// `None` to use those. Larger than the actual line size, maintenance skips lines.
+pub const CACHE_LINE_SIZE_OVERRIDE: Option<usize> = None;
+
+/// QEMU virt emulates PSCI behind `hvc` unless it also emulates EL2 or EL3.
+pub const PSCI_CONDUIT: PsciConduit = PsciConduit::Hvc;
@@ -1,26 +1,214 @@
-use core::fmt::Write;
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use super::config;
+use crate::{common::volatile::MmioBlock, cpu};
+
+/// Address the UART is currently accessed at, identity mapped until the kernel switches over.
+static UART0: AtomicUsize = AtomicUsize::new(config::UART0_PADDR);
+
+/// Access the UART through `vaddr` from now on.
+///
+/// # Safety
+///
+/// - `vaddr` must map the UART as device memory.
+pub unsafe fn set_uart_base(vaddr: usize) {
+    UART0.store(vaddr, Ordering::Relaxed);
+}
+
+/// The PL011 registers at the address set last.
+#[inline(always)]
+fn uart() -> MmioBlock {
+    unsafe { MmioBlock::new(UART0.load(Ordering::Relaxed)) }
+}
 
 #[inline(always)]
 pub unsafe fn raw_print(s: &str) {
-    const UART0: *mut u8 = 0x09000000 as *mut u8;
-    for byte in s.as_bytes() {
-        core::ptr::write_volatile(UART0, *byte);
+    raw_write(s.as_bytes())
+}
+
+#[inline(always)]
+unsafe fn raw_write(bytes: &[u8]) {
+    let uartdr = uart().reg::<u8>(UARTDR);
+    for byte in bytes {
+        uartdr.write(*byte);
+    }
+}
+
+/// Offset of the PL011 data register.
+const UARTDR: usize = 0x0;
+/// Offset of the PL011 flag register.
+const UARTFR: usize = 0x18;
+/// UARTFR: the UART is busy transmitting data.
+const UARTFR_BUSY: u32 = 1 << 3;
+/// UARTFR: the receive FIFO is empty.
+const UARTFR_RXFE: u32 = 1 << 4;
+/// UARTFR: the transmit FIFO is empty.
+const UARTFR_TXFE: u32 = 1 << 7;
+
+/// Spin until every byte written so far has left the UART.
+///
+/// Call before halting or exiting QEMU, otherwise the tail of the output can be lost.
+pub fn flush() {
+    let uartfr = uart().reg::<u32>(UARTFR);
+    loop {
+        let flags = uartfr.read();
+        if flags & UARTFR_TXFE != 0 && flags & UARTFR_BUSY == 0 {
+            break;
+        }
+        cpu::spin_hint();
+    }
+}
+
+/// Take the next byte received by the UART, or `None` if there is none.
+pub fn read_byte() -> Option<u8> {
+    let uart = uart();
+    if uart.reg::<u32>(UARTFR).read() & UARTFR_RXFE != 0 {
+        return None;
+    }
+    // The bits above the data are the receive error flags.
+    Some(uart.reg::<u32>(UARTDR).read() as u8)
+}
+
+/// Wait for a line of input into `buf` and return it without the line ending.
+///
+/// Input is echoed and can be edited with backspace. Only printable ASCII is accepted, characters
+/// beyond the length of `buf` are dropped.
+pub fn read_line(buf: &mut [u8]) -> &str {
+    let mut len = 0;
+    loop {
+        let byte = match read_byte() {
+            Some(byte) => byte,
+            None => {
+                // Waiting for input is idle time, not a hang.
+                #[cfg(feature = "watchdog")]
+                crate::watchdog::kick();
+                cpu::spin_hint();
+                continue;
+            }
+        };
+        match byte {
+            b'\r' | b'\n' => {
+                print!("\n");
+                break;
+            }
+            // Backspace or delete, depending on the terminal.
+            0x08 | 0x7f if len > 0 => {
+                len -= 1;
+                print!("\x08 \x08");
+            }
+            b' '..=b'~' if len < buf.len() => {
+                buf[len] = byte;
+                len += 1;
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+    core::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// Held while a message is written to the UART, so messages of different cores do not
+/// interleave.
+static OUTPUT_LOCK: AtomicBool = AtomicBool::new(false);
+
+/// Release the output lock whoever holds it, e.g. a core that panicked while printing.
+///
+/// # Safety
+///
+/// - The holder's output may interleave with the next message.
+pub unsafe fn break_output_lock() {
+    OUTPUT_LOCK.store(false, Ordering::Release);
+    cpu::send_event();
+}
+
+/// Size of the stack buffer a message is collected in. Longer messages are written in chunks.
+const LINE_BUFFER_SIZE: usize = 256;
+
+/// Collects the pieces of one formatted message, so it reaches the UART in one go.
+struct LineBuffer {
+    bytes: [u8; LINE_BUFFER_SIZE],
+    len: usize,
+}
+
+impl LineBuffer {
+    fn flush(&mut self) {
+        unsafe { raw_write(&self.bytes[..self.len]) };
+        self.len = 0;
+    }
+}
+
+impl Write for LineBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(LINE_BUFFER_SIZE) {
+            if self.len + chunk.len() > LINE_BUFFER_SIZE {
+                self.flush();
+            }
+            self.bytes[self.len..self.len + chunk.len()].copy_from_slice(chunk);
+            self.len += chunk.len();
+        }
+        Ok(())
     }
 }
 
 pub struct Console;
 
 impl Write for Console {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        unsafe {
-            raw_print(s);
-        }
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_fmt(format_args!("{}", s))
+    }
+
+    /// Format the whole message into a buffer on the stack, then write it out holding the output
+    /// lock with interrupts masked.
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+        cpu::without_interrupts(|| {
+            // Sleep while the lock is held, the holder sends an event when it lets go.
+            while OUTPUT_LOCK
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                while OUTPUT_LOCK.load(Ordering::Relaxed) {
+                    cpu::wait_for_event();
+                }
+            }
+
+            let mut line = LineBuffer {
+                bytes: [0; LINE_BUFFER_SIZE],
+                len: 0,
+            };
+            let result = line.write_fmt(args);
+            line.flush();
+
+            OUTPUT_LOCK.store(false, Ordering::Release);
+            cpu::send_event();
+            result
+        })
+    }
+}
+
+/// Writes straight to the UART, for the panic handler only.
+///
+/// Neither takes the output lock nor buffers, so it works whatever state the panicking code left
+/// the console in, at the price of interleaving with other output.
+pub struct PanicConsole;
+
+impl Write for PanicConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        unsafe { raw_print(s) };
         Ok(())
     }
 }
 
-static mut CONSOLE: Console = Console;
+/// The console of the panic handler, see `PanicConsole`.
+pub fn panic_console() -> PanicConsole {
+    PanicConsole
+}
+
+/// The console every core prints through. It holds no state, `OUTPUT_LOCK` serializes the
+/// messages.
 #[inline(always)]
-pub fn console() -> &'static mut dyn Write {
-    unsafe { &mut CONSOLE }
+pub fn console() -> Console {
+    Console
 }
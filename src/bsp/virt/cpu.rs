@@ -10,3 +10,6 @@
 
 /// Used by `arch` code to find the early boot core.
 pub const BOOT_CORE_ID: usize = 0;
+
+/// Most cores the machine can have, the limit of the GICv2 on QEMU's virt board.
+pub const MAX_CORES: usize = 8;
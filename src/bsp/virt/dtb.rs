@@ -1,5 +1,167 @@
-use dtb::Reader;
+use crate::memory::{AddrMapper, Address, AddressRange, Physical};
+use core::convert::TryInto;
+use dtb::{Reader, StructItem};
 
-pub fn get_dtb(addr: &[u8]) -> Result<Reader, dtb::Error> {
-    unsafe { Reader::read_from_address(addr.as_ptr() as usize) }
+/// Magic number at the start of every flattened device tree, big endian.
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// Size of the header the reader parses, no valid blob is smaller.
+const FDT_HEADER_SIZE: usize = 40;
+
+/// Why a device tree was rejected.
+#[derive(Debug)]
+pub enum DtbError {
+    /// The address is not in RAM.
+    OutOfRange,
+    /// The blob starts with this instead of `FDT_MAGIC`.
+    BadMagic(u32),
+    /// The header's total size is smaller than the header or reaches beyond the memory given.
+    BadSize { total_size: usize, available: usize },
+    /// The header looked sane, but the reader failed on the blob.
+    Reader(dtb::Error),
+}
+
+impl core::fmt::Display for DtbError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DtbError::OutOfRange => write!(f, "Device tree not in RAM"),
+            DtbError::BadMagic(magic) => write!(
+                f,
+                "Device tree magic is {:#010x}, expected {:#010x}",
+                magic, FDT_MAGIC
+            ),
+            DtbError::BadSize {
+                total_size,
+                available,
+            } => write!(
+                f,
+                "Device tree size {:#x} does not fit in {:#x} bytes",
+                total_size, available
+            ),
+            DtbError::Reader(err) => write!(f, "Malformed device tree: {:?}", err),
+        }
+    }
+}
+
+/// A big endian `u32` at `offset` of `bytes`, which must be long enough.
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Check the magic and the total size in the header at the start of `bytes`, and return the total
+/// size.
+fn check_header(bytes: &[u8]) -> Result<usize, DtbError> {
+    let available = bytes.len();
+    if available < FDT_HEADER_SIZE {
+        return Err(DtbError::BadSize {
+            total_size: FDT_HEADER_SIZE,
+            available,
+        });
+    }
+    let magic = be32(bytes, 0);
+    if magic != FDT_MAGIC {
+        return Err(DtbError::BadMagic(magic));
+    }
+    let total_size = be32(bytes, 4) as usize;
+    if total_size < FDT_HEADER_SIZE || total_size > available {
+        return Err(DtbError::BadSize {
+            total_size,
+            available,
+        });
+    }
+    Ok(total_size)
+}
+
+/// Read the device tree at the start of `addr`, after checking that its header is sane and the
+/// whole blob lies within `addr`, so the reader can not run past it.
+pub fn get_dtb(addr: &[u8]) -> Result<Reader, DtbError> {
+    let total_size = check_header(addr)?;
+    unsafe { Reader::read_from_address(addr[..total_size].as_ptr() as usize) }
+        .map_err(DtbError::Reader)
+}
+
+/// Read the device tree at `paddr`, e.g. as the bootloader passed it on, checking it with
+/// `get_dtb` against the rest of `ram` first.
+///
+/// # Safety
+///
+/// - `MAPPER` must map all of `ram` readable.
+/// - Nothing may write the blob as long as the reader lives.
+pub unsafe fn try_read_from_address<MAPPER: AddrMapper>(
+    paddr: Address<Physical>,
+    ram: AddressRange<Physical>,
+) -> Result<Reader<'static>, DtbError> {
+    let last = match ram.last() {
+        Some(last) if ram.contains(paddr) => last,
+        _ => return Err(DtbError::OutOfRange),
+    };
+    let available = last.into_usize() - paddr.into_usize() + 1;
+    let vaddr = MAPPER::map_to_vaddr(paddr).into_usize();
+    get_dtb(core::slice::from_raw_parts(vaddr as *const u8, available))
+}
+
+/// The properties of a `cpu` node under `/cpus` needed to start the core.
+pub struct CpuNode<'a> {
+    /// The core's MPIDR affinity fields.
+    pub reg: u64,
+    pub enable_method: &'a [u8],
+    pub release_addr: Option<&'a [u8]>,
+}
+
+/// A big endian value of one or two cells.
+fn cells(value: &[u8]) -> Option<u64> {
+    match value.len() {
+        4 => Some(u32::from_be_bytes(value.try_into().ok()?) as u64),
+        8 => Some(u64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Call `f` with every `cpu@` node under `/cpus` that has a `reg` and an `enable-method`.
+pub fn for_each_cpu<'a>(reader: &Reader<'a>, mut f: impl FnMut(CpuNode<'a>)) {
+    // The root node is at depth 1, `/cpus` at 2 and its `cpu@` nodes at 3.
+    let mut depth = 0;
+    let mut in_cpus = false;
+    let mut in_cpu = false;
+    let (mut reg, mut enable_method, mut release_addr) = (None, None, None);
+    for item in reader.struct_items() {
+        match item {
+            StructItem::BeginNode { name } => {
+                depth += 1;
+                match depth {
+                    2 => in_cpus = name == "cpus",
+                    3 if in_cpus => {
+                        in_cpu = name.starts_with("cpu@");
+                        reg = None;
+                        enable_method = None;
+                        release_addr = None;
+                    }
+                    _ => {}
+                }
+            }
+            StructItem::Property { name, value } if in_cpu && depth == 3 => match name {
+                "reg" => reg = cells(value),
+                "enable-method" => enable_method = Some(value),
+                "cpu-release-addr" => release_addr = Some(value),
+                _ => {}
+            },
+            StructItem::Property { .. } => {}
+            StructItem::EndNode => {
+                if in_cpu && depth == 3 {
+                    in_cpu = false;
+                    if let (Some(reg), Some(enable_method)) = (reg, enable_method) {
+                        f(CpuNode {
+                            reg,
+                            enable_method,
+                            release_addr,
+                        });
+                    }
+                }
+                if depth == 2 {
+                    in_cpus = false;
+                }
+                depth -= 1;
+            }
+        }
+    }
 }
@@ -0,0 +1,79 @@
+//! GICv2 distributor and CPU interface, as far as routing private peripheral interrupts needs.
+
+use register::{
+    mmio::{ReadOnly, ReadWrite, WriteOnly},
+    register_structs,
+};
+
+use super::config;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    DistributorBlock {
+        (0x000 => CTLR: ReadWrite<u32>),
+        (0x004 => _reserved0),
+        (0x100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x200 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    CpuInterfaceBlock {
+        (0x00 => CTLR: ReadWrite<u32>),
+        (0x04 => PMR: ReadWrite<u32>),
+        (0x08 => BPR: ReadWrite<u32>),
+        (0x0c => IAR: ReadOnly<u32>),
+        (0x10 => EOIR: WriteOnly<u32>),
+        (0x14 => @END),
+    }
+}
+
+/// Interrupt IDs from this one up are special, e.g. 1023 for "nothing pending".
+const SPECIAL_INTID: u32 = 1020;
+
+#[inline(always)]
+fn distributor() -> &'static DistributorBlock {
+    // Mapped as device memory by the bootloader.
+    unsafe { &*(config::GIC_DIST_VBASE as *const DistributorBlock) }
+}
+
+#[inline(always)]
+fn cpu_interface() -> &'static CpuInterfaceBlock {
+    unsafe { &*(config::GIC_CPU_VBASE as *const CpuInterfaceBlock) }
+}
+
+/// Enable the distributor and let interrupts of every priority through to the executing core.
+pub fn init() {
+    distributor().CTLR.set(1);
+    cpu_interface().PMR.set(0xff);
+    cpu_interface().CTLR.set(1);
+}
+
+/// Forward interrupt `intid` to the CPU interfaces.
+pub fn enable(intid: u32) {
+    distributor().ISENABLER[intid as usize / 32].set(1 << (intid % 32));
+}
+
+/// Stop forwarding interrupt `intid`.
+pub fn disable(intid: u32) {
+    distributor().ICENABLER[intid as usize / 32].set(1 << (intid % 32));
+}
+
+/// Take the highest priority pending interrupt, `None` if there is none.
+///
+/// Every returned ID must be passed to `end_of_interrupt` once handled.
+pub fn acknowledge() -> Option<u32> {
+    let intid = cpu_interface().IAR.get() & 0x3ff;
+    if intid >= SPECIAL_INTID {
+        None
+    } else {
+        Some(intid)
+    }
+}
+
+/// Finish handling interrupt `intid`.
+pub fn end_of_interrupt(intid: u32) {
+    cpu_interface().EOIR.set(intid);
+}
@@ -1,13 +1,13 @@
 use core::cell::UnsafeCell;
 use core::ops::RangeInclusive;
 
-use crate::memory::{Address, Physical, Virtual};
+use super::config;
+use crate::memory::{Address, AddressRange, Physical};
 
 // Symbols from the linker script.
 extern "Rust" {
     static __bss_start: UnsafeCell<u64>;
     static __bss_end_inclusive: UnsafeCell<u64>;
-    static __ro_start: UnsafeCell<()>;
     static __ro_size: UnsafeCell<()>;
     static __data_size: UnsafeCell<()>;
 }
@@ -16,13 +16,9 @@ extern "Rust" {
 pub(super) mod map {
     use super::*;
 
-    pub const BOOT_CORE_STACK_SIZE: usize = 0x1_0000;
-
     /// Physical devices.
     #[warn(unused_variables)]
     pub mod mmio {
-        use crate::memory::AddressRange;
-
         use super::*;
 
         // https://github.com/qemu/qemu/blob/master/hw/arm/virt.c
@@ -77,6 +73,26 @@ pub(super) mod map {
         pub const FLASH: AddressRange<Physical> = AddressRange::new_raw(0, 0x08000000);
         pub const UART: AddressRange<Physical> = AddressRange::new_raw(0x09000000, 0x00001000);
         pub const GPIO: AddressRange<Physical> = AddressRange::new_raw(0x09030000, 0x00001000);
+        pub const GIC_DIST: AddressRange<Physical> =
+            AddressRange::new_raw(config::GIC_DIST_PADDR, 0x00010000);
+        pub const GIC_CPU: AddressRange<Physical> =
+            AddressRange::new_raw(config::GIC_CPU_PADDR, 0x00010000);
+        pub const RTC: AddressRange<Physical> =
+            AddressRange::new_raw(config::RTC_PADDR, 0x00001000);
+        pub const VIRTIO_MMIO: AddressRange<Physical> = AddressRange::new_raw(
+            config::VIRTIO_MMIO_PADDR,
+            config::VIRTIO_MMIO_COUNT * config::VIRTIO_MMIO_STRIDE,
+        );
+
+        /// Every device register range above. The flash is memory, not a device.
+        pub const REGIONS: [(&str, AddressRange<Physical>); 6] = [
+            ("GIC distributor", GIC_DIST),
+            ("GIC CPU interface", GIC_CPU),
+            ("UART", UART),
+            ("RTC", RTC),
+            ("GPIO", GPIO),
+            ("virtio-mmio", VIRTIO_MMIO),
+        ];
 
         pub const END: Address<Physical> = Address::new(0x4001_0000);
     }
@@ -84,34 +100,9 @@ pub(super) mod map {
     pub const END: Address<Physical> = mmio::END;
 }
 
-/// Start address of the Read-Only (RO) range.
-///
-/// # Safety
-///
-/// - Value is provided by the linker script and must be trusted as-is.
-#[inline(always)]
-fn virt_ro_start() -> Address<Virtual> {
-    Address::new(unsafe { __ro_start.get() as usize })
-}
-
-/// Start address of the boot core's stack.
-#[inline(always)]
-fn virt_boot_core_stack_start() -> Address<Virtual> {
-    virt_ro_start() - map::BOOT_CORE_STACK_SIZE
-}
-
-/// Size of the boot core's stack.
-#[inline(always)]
-fn boot_core_stack_size() -> usize {
-    map::BOOT_CORE_STACK_SIZE
-}
-
-/// Exclusive end address of the boot core's stack.
-#[inline(always)]
-pub fn phys_boot_core_stack_end() -> Address<Physical> {
-    // The binary is still identity mapped, so we don't need to convert here.
-    let end = virt_boot_core_stack_start().into_usize() + boot_core_stack_size();
-    Address::new(end)
+/// The device register ranges of the board, labeled with the device name.
+pub fn mmio_regions() -> impl Iterator<Item = (&'static str, AddressRange<Physical>)> {
+    map::mmio::REGIONS.iter().copied()
 }
 
 /// Return the inclusive range spanning the .bss section.
@@ -2,4 +2,9 @@ pub mod config;
 pub mod console;
 pub mod cpu;
 pub mod dtb;
+pub mod gic;
 pub mod memory;
+pub mod rtc;
+pub mod virtio_mmio;
+
+pub use console::panic_console;
@@ -0,0 +1,34 @@
+//! PL031 real time clock.
+
+use register::{
+    mmio::{ReadOnly, ReadWrite, WriteOnly},
+    register_structs,
+};
+
+use super::config;
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => DR: ReadOnly<u32>),
+        (0x04 => MR: ReadWrite<u32>),
+        (0x08 => LR: ReadWrite<u32>),
+        (0x0c => CR: ReadWrite<u32>),
+        (0x10 => IMSC: ReadWrite<u32>),
+        (0x14 => RIS: ReadOnly<u32>),
+        (0x18 => MIS: ReadOnly<u32>),
+        (0x1c => ICR: WriteOnly<u32>),
+        (0x20 => @END),
+    }
+}
+
+#[inline(always)]
+fn registers() -> &'static RegisterBlock {
+    // Mapped as device memory by the bootloader.
+    unsafe { &*(config::RTC_VBASE as *const RegisterBlock) }
+}
+
+/// Seconds since the Unix epoch.
+pub fn wall_clock_secs() -> u32 {
+    registers().DR.get()
+}
@@ -0,0 +1,246 @@
+//! Virtio over MMIO transport, as exposed by the QEMU virt machine.
+//!
+//! Supports both the legacy (version 1) and the modern (version 2) register layout. Virtqueues use
+//! the legacy contiguous layout in either case, so one physically contiguous buffer holds a queue.
+
+use core::ops::Deref;
+
+use register::{
+    mmio::{ReadOnly, ReadWrite, WriteOnly},
+    register_structs,
+};
+
+use super::config;
+use crate::{
+    common::align_up,
+    memory::{dma::DmaBuffer, PageAllocator},
+};
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => MagicValue: ReadOnly<u32>),
+        (0x004 => Version: ReadOnly<u32>),
+        (0x008 => DeviceID: ReadOnly<u32>),
+        (0x00c => VendorID: ReadOnly<u32>),
+        (0x010 => DeviceFeatures: ReadOnly<u32>),
+        (0x014 => DeviceFeaturesSel: WriteOnly<u32>),
+        (0x018 => _reserved0),
+        (0x020 => DriverFeatures: WriteOnly<u32>),
+        (0x024 => DriverFeaturesSel: WriteOnly<u32>),
+        (0x028 => GuestPageSize: WriteOnly<u32>),
+        (0x02c => _reserved1),
+        (0x030 => QueueSel: WriteOnly<u32>),
+        (0x034 => QueueNumMax: ReadOnly<u32>),
+        (0x038 => QueueNum: WriteOnly<u32>),
+        (0x03c => QueueAlign: WriteOnly<u32>),
+        (0x040 => QueuePFN: ReadWrite<u32>),
+        (0x044 => QueueReady: ReadWrite<u32>),
+        (0x048 => _reserved2),
+        (0x050 => QueueNotify: WriteOnly<u32>),
+        (0x054 => _reserved3),
+        (0x060 => InterruptStatus: ReadOnly<u32>),
+        (0x064 => InterruptACK: WriteOnly<u32>),
+        (0x068 => _reserved4),
+        (0x070 => Status: ReadWrite<u32>),
+        (0x074 => _reserved5),
+        (0x080 => QueueDescLow: WriteOnly<u32>),
+        (0x084 => QueueDescHigh: WriteOnly<u32>),
+        (0x088 => _reserved6),
+        (0x090 => QueueDriverLow: WriteOnly<u32>),
+        (0x094 => QueueDriverHigh: WriteOnly<u32>),
+        (0x098 => _reserved7),
+        (0x0a0 => QueueDeviceLow: WriteOnly<u32>),
+        (0x0a4 => QueueDeviceHigh: WriteOnly<u32>),
+        (0x0a8 => _reserved8),
+        (0x0fc => ConfigGeneration: ReadOnly<u32>),
+        (0x100 => @END),
+    }
+}
+
+/// "virt" in little endian.
+const MAGIC: u32 = 0x7472_6976;
+
+/// Alignment of the used ring in the legacy queue layout.
+const QUEUE_ALIGN: usize = 4096;
+
+/// Largest queue size the driver sets up.
+const MAX_QUEUE_SIZE: u32 = 256;
+
+/// Device status bits.
+mod status {
+    pub const ACKNOWLEDGE: u32 = 1;
+    pub const DRIVER: u32 = 2;
+    pub const DRIVER_OK: u32 = 4;
+    pub const FEATURES_OK: u32 = 8;
+    pub const FAILED: u32 = 128;
+}
+
+/// Feature bit a modern device requires the driver to accept.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+/// One virtio-mmio transport.
+pub struct VirtioMmio {
+    registers: &'static RegisterBlock,
+}
+
+impl Deref for VirtioMmio {
+    type Target = RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        self.registers
+    }
+}
+
+/// Bytes needed by a queue with `size` entries in the legacy contiguous layout.
+const fn queue_bytes(size: usize) -> usize {
+    // Descriptor table followed by the available ring, then the used ring on its own alignment.
+    let desc_and_avail = 16 * size + 6 + 2 * size;
+    align_up(desc_and_avail, QUEUE_ALIGN) + align_up(6 + 8 * size, QUEUE_ALIGN)
+}
+
+impl VirtioMmio {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - `vaddr` must map a virtio-mmio transport as device memory.
+    pub unsafe fn new(vaddr: usize) -> Self {
+        Self {
+            registers: &*(vaddr as *const RegisterBlock),
+        }
+    }
+
+    /// The device id if a device is attached to the transport, 0 means the slot is empty.
+    pub fn probe(&self) -> Option<u32> {
+        if self.MagicValue.get() != MAGIC {
+            return None;
+        }
+        match (self.Version.get(), self.DeviceID.get()) {
+            (1..=2, 0) => None,
+            (1..=2, id) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Whether the transport uses the legacy register layout.
+    pub fn is_legacy(&self) -> bool {
+        self.Version.get() == 1
+    }
+
+    fn device_features(&self) -> u64 {
+        self.DeviceFeaturesSel.set(0);
+        let low = self.DeviceFeatures.get() as u64;
+        self.DeviceFeaturesSel.set(1);
+        let high = self.DeviceFeatures.get() as u64;
+        (high << 32) | low
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.DriverFeaturesSel.set(0);
+        self.DriverFeatures.set(features as u32);
+        self.DriverFeaturesSel.set(1);
+        self.DriverFeatures.set((features >> 32) as u32);
+    }
+
+    /// Reset the device and accept the subset of `supported` it offers.
+    ///
+    /// Returns the negotiated features.
+    pub fn negotiate(&self, supported: u64) -> Result<u64, &'static str> {
+        self.Status.set(0);
+        self.Status.set(status::ACKNOWLEDGE | status::DRIVER);
+
+        let mut supported = supported;
+        if !self.is_legacy() {
+            supported |= VIRTIO_F_VERSION_1;
+        }
+        let features = self.device_features() & supported;
+        self.set_driver_features(features);
+
+        if self.is_legacy() {
+            self.GuestPageSize.set(QUEUE_ALIGN as u32);
+            return Ok(features);
+        }
+
+        self.Status
+            .set(status::ACKNOWLEDGE | status::DRIVER | status::FEATURES_OK);
+        if self.Status.get() & status::FEATURES_OK == 0 {
+            self.Status.set(status::FAILED);
+            return Err("Device rejected the negotiated features");
+        }
+        Ok(features)
+    }
+
+    /// Place queue `index` in `buffer`, using as many entries as fit.
+    ///
+    /// Returns the queue size.
+    pub fn setup_queue<ALLOC: PageAllocator>(
+        &self,
+        index: u32,
+        buffer: &DmaBuffer<ALLOC>,
+    ) -> Result<u32, &'static str> {
+        self.QueueSel.set(index);
+        let mut size = self.QueueNumMax.get().min(MAX_QUEUE_SIZE);
+        if size == 0 {
+            return Err("Queue not available");
+        }
+        while queue_bytes(size as usize) > buffer.size() {
+            size /= 2;
+            if size == 0 {
+                return Err("DMA buffer too small for a queue");
+            }
+        }
+        self.QueueNum.set(size);
+
+        let desc = buffer.paddr().into_usize();
+        if self.is_legacy() {
+            self.QueueAlign.set(QUEUE_ALIGN as u32);
+            self.QueuePFN.set((desc / QUEUE_ALIGN) as u32);
+        } else {
+            let driver = desc + 16 * size as usize;
+            let device = desc + align_up(18 * size as usize + 6, QUEUE_ALIGN);
+            self.QueueDescLow.set(desc as u32);
+            self.QueueDescHigh.set((desc >> 32) as u32);
+            self.QueueDriverLow.set(driver as u32);
+            self.QueueDriverHigh.set((driver >> 32) as u32);
+            self.QueueDeviceLow.set(device as u32);
+            self.QueueDeviceHigh.set((device >> 32) as u32);
+            self.QueueReady.set(1);
+        }
+        Ok(size)
+    }
+
+    /// Tell the device the driver is ready.
+    pub fn driver_ok(&self) {
+        self.Status.set(self.Status.get() | status::DRIVER_OK);
+    }
+
+    /// Notify the device about new buffers in queue `index`.
+    pub fn notify(&self, index: u32) {
+        self.QueueNotify.set(index);
+    }
+
+    /// Acknowledge all pending interrupts, returning them.
+    pub fn ack_interrupts(&self) -> u32 {
+        let pending = self.InterruptStatus.get();
+        self.InterruptACK.set(pending);
+        pending
+    }
+}
+
+/// Print the devices attached to the virtio-mmio transports.
+pub fn probe_all() {
+    for slot in 0..config::VIRTIO_MMIO_COUNT {
+        let transport = unsafe {
+            VirtioMmio::new(config::VIRTIO_MMIO_VBASE + slot * config::VIRTIO_MMIO_STRIDE)
+        };
+        if let Some(id) = transport.probe() {
+            println!(
+                "virtio-mmio slot {}: device id {}, version {}",
+                slot,
+                id,
+                transport.Version.get()
+            );
+        }
+    }
+}
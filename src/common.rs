@@ -4,6 +4,10 @@
 
 //! General purpose code.
 
+pub mod bit;
+pub mod checksum;
+pub mod volatile;
+
 /// Check if a value is aligned to a given size.
 #[inline(always)]
 pub const fn is_aligned(value: usize, alignment: usize) -> bool {
@@ -27,3 +31,55 @@ pub const fn align_up(value: usize, alignment: usize) -> usize {
 
     (value + alignment - 1) & !(alignment - 1)
 }
+
+/// The smaller of `a` and `b`, usable in `const fn`s unlike `Ord::min`.
+#[inline(always)]
+pub const fn min(a: usize, b: usize) -> usize {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+/// The larger of `a` and `b`, usable in `const fn`s unlike `Ord::max`.
+#[inline(always)]
+pub const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Restrict `value` to `lower..=upper`.
+#[inline(always)]
+pub const fn clamp(value: usize, lower: usize, upper: usize) -> usize {
+    assert!(lower <= upper);
+
+    max(lower, min(value, upper))
+}
+
+/// Sort `items` in place without a heap.
+///
+/// Insertion sort, meant for the handful of elements kept in fixed arrays during boot.
+pub fn insertion_sort<T: Ord>(items: &mut [T]) {
+    for i in 1..items.len() {
+        let mut j = i;
+        while j > 0 && items[j - 1] > items[j] {
+            items.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+/// Like `insertion_sort`, ordering the items by `key`.
+pub fn insertion_sort_by_key<T, K: Ord>(items: &mut [T], key: impl Fn(&T) -> K) {
+    for i in 1..items.len() {
+        let mut j = i;
+        while j > 0 && key(&items[j - 1]) > key(&items[j]) {
+            items.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
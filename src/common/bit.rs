@@ -0,0 +1,36 @@
+//! Bit field helpers, matching the `offset`/`width` notation of the ARM ARM field definitions.
+
+/// A mask of the lowest `width` bits.
+#[inline(always)]
+pub const fn mask(width: usize) -> usize {
+    if width >= usize::BITS as usize {
+        usize::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+/// Read the `width` bits wide field starting at bit `offset` of `value`.
+#[inline(always)]
+pub const fn extract(value: usize, offset: usize, width: usize) -> usize {
+    (value >> offset) & mask(width)
+}
+
+/// Replace the `width` bits wide field starting at bit `offset` of `value` with `field`.
+///
+/// Bits of `field` that do not fit into the field are dropped.
+#[inline(always)]
+pub const fn insert(value: usize, field: usize, offset: usize, width: usize) -> usize {
+    let field_mask = mask(width) << offset;
+    (value & !field_mask) | ((field << offset) & field_mask)
+}
+
+/// Shift out the lowest `shift` bits of `value`, which must all be zero.
+///
+/// E.g. turn a page aligned address into the output address field of a descriptor.
+#[inline(always)]
+pub const fn align_shift(value: usize, shift: usize) -> usize {
+    assert!(extract(value, 0, shift) == 0);
+
+    value >> shift
+}
@@ -0,0 +1,38 @@
+//! Table driven CRC-32 (IEEE 802.3), usable in const context.
+
+/// Reversed representation of the CRC-32 polynomial.
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Remainders for every possible byte value.
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Calculate the CRC-32 of `data`.
+pub const fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    let mut i = 0;
+    while i < data.len() {
+        crc = TABLE[((crc ^ data[i] as u32) & 0xFF) as usize] ^ (crc >> 8);
+        i += 1;
+    }
+    !crc
+}
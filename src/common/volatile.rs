@@ -0,0 +1,66 @@
+//! Volatile access to memory mapped device registers.
+//!
+//! Devices with a fixed register layout are better described with `register_structs!`, these are
+//! for the few places that poke single registers by offset.
+
+/// A device register of type `T`. Every access is a single volatile load or store of `T`'s width,
+/// so the compiler can neither elide, merge nor reorder it against other register accesses.
+#[derive(Copy, Clone)]
+pub struct Mmio<T: Copy> {
+    ptr: *mut T,
+}
+
+impl<T: Copy> Mmio<T> {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - `addr` must be aligned to `T` and map the register as device memory for as long as the
+    ///   instance is used.
+    pub const unsafe fn new(addr: usize) -> Self {
+        Self {
+            ptr: addr as *mut T,
+        }
+    }
+
+    #[inline(always)]
+    pub fn read(&self) -> T {
+        unsafe { self.ptr.read_volatile() }
+    }
+
+    #[inline(always)]
+    pub fn write(&self, value: T) {
+        unsafe { self.ptr.write_volatile(value) }
+    }
+}
+
+/// The base address of a device's register block, the registers are addressed by byte offset.
+#[derive(Copy, Clone)]
+pub struct MmioBlock {
+    base: usize,
+}
+
+impl MmioBlock {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - `base` must map the whole register block as device memory for as long as the instance
+    ///   and the registers taken from it are used.
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// The register of type `T` at `offset` bytes from the base.
+    #[inline(always)]
+    pub fn reg<T: Copy>(&self, offset: usize) -> Mmio<T> {
+        let addr = self.base + offset;
+        debug_assert!(
+            super::is_aligned(addr, core::mem::align_of::<T>()),
+            "Register {:#x} is not aligned",
+            addr
+        );
+
+        unsafe { Mmio::new(addr) }
+    }
+}
@@ -1,3 +1,39 @@
 pub use crate::arch::cpu::*;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 mod boot;
+
+/// Set by `halt_others`, checked by `check_halt`.
+static HALT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask every other core to stop at its next `check_halt`, e.g. because the executing one
+/// panicked, and wake the cores waiting for an event.
+pub fn halt_others() {
+    HALT_REQUESTED.store(true, Ordering::SeqCst);
+    send_event();
+}
+
+#[cfg(all(feature = "panic_reset", feature = "panic_qemu_exit"))]
+compile_error!("Features `panic_reset` and `panic_qemu_exit` exclude each other");
+
+/// End a panic that has been reported, as the `panic_*` feature selects: reset the machine, exit
+/// QEMU with a failure or, by default, spin so the state can be inspected with a debugger.
+pub fn end_panic() -> ! {
+    #[cfg(feature = "panic_reset")]
+    reset();
+    #[cfg(feature = "panic_qemu_exit")]
+    qemu_exit_failure();
+    #[allow(unreachable_code)]
+    loop {}
+}
+
+/// Park the executing core for good if another core called `halt_others`.
+///
+/// Called where cores pass regularly: on interrupts and when a task yields.
+#[inline(always)]
+pub fn check_halt() {
+    if HALT_REQUESTED.load(Ordering::SeqCst) {
+        wait_forever()
+    }
+}
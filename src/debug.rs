@@ -1,4 +1,4 @@
-use core::fmt;
+use core::fmt::{self, Write};
 
 use crate::bsp::console::console;
 
@@ -25,3 +25,49 @@ macro_rules! println {
         $crate::debug::_print(format_args_nl!($($arg)*));
     })
 }
+
+/// Bytes per line of a `hexdump`.
+pub const HEXDUMP_ROW: usize = 16;
+
+/// One line of a `hexdump`, formatted as a whole so it reaches the console in one piece.
+struct HexdumpRow<'a> {
+    addr: usize,
+    bytes: &'a [u8],
+}
+
+impl fmt::Display for HexdumpRow<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}:", self.addr)?;
+        for byte in self.bytes {
+            write!(f, " {:02x}", byte)?;
+        }
+        // Keep the ASCII column aligned on a short last line.
+        for _ in self.bytes.len()..HEXDUMP_ROW {
+            f.write_str("   ")?;
+        }
+        f.write_str("  |")?;
+        for &byte in self.bytes {
+            let c = if (b' '..=b'~').contains(&byte) {
+                byte as char
+            } else {
+                '.'
+            };
+            write!(f, "{}", c)?;
+        }
+        f.write_str("|")
+    }
+}
+
+/// Print `bytes` as lines of `HEXDUMP_ROW` bytes in hex and as ASCII, each line prefixed with its
+/// address counted from `base`. Bytes outside of printable ASCII show as `.`.
+pub fn hexdump(bytes: &[u8], base: usize) {
+    for (row, chunk) in bytes.chunks(HEXDUMP_ROW).enumerate() {
+        println!(
+            "{}",
+            HexdumpRow {
+                addr: base + row * HEXDUMP_ROW,
+                bytes: chunk,
+            }
+        );
+    }
+}
@@ -1,4 +1,5 @@
-#![no_std]
+// The unit tests run on the host, with `arch::mock` and the standard test harness.
+#![cfg_attr(not(test), no_std)]
 #![feature(asm)]
 #![feature(global_asm)]
 #![feature(const_panic)]
@@ -15,5 +16,9 @@ pub mod common;
 pub mod cpu;
 pub mod memory;
 pub mod mmu;
+pub mod sched;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 pub mod boot;
+#[cfg(target_arch = "aarch64")]
 mod runtime_init;
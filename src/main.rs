@@ -20,11 +20,18 @@ mod common;
 mod cpu;
 mod memory;
 mod mmu;
+#[cfg(feature = "monitor")]
+mod monitor;
 mod panic;
 mod runtime_init;
+mod sched;
+mod status;
+#[cfg(feature = "watchdog")]
+mod watchdog;
 
 use arch::exception::handling_init;
-use stellaros::boot::BootInfo;
+use boot::BootInfo;
+use memory::PhysOffsetMapper;
 
 /// Early init code.
 ///
@@ -38,9 +45,42 @@ use stellaros::boot::BootInfo;
 ///         work on the RPi SoCs.
 #[no_mangle]
 unsafe extern "C" fn kernel_init(boot_info: &BootInfo) -> ! {
+    cpu::assert_el(1);
     handling_init();
+    #[cfg(feature = "vector_selftest")]
+    arch::exception::vector_self_test().expect("Exception vectors not wired");
+    PhysOffsetMapper::init(boot_info.phys_offset);
     println!("StellarOS started!");
+    println!("Wall clock: {}s since epoch", bsp::rtc::wall_clock_secs());
     println!("Boot Info:\n\t{}", boot_info);
+    let mut memory_map = memory::memory_map::MemoryMap::from_boot_info(boot_info)
+        .expect("Memory map too fragmented");
+    println!(
+        "Memory: {} KiB free of {} KiB",
+        memory_map.free_ram() >> 10,
+        memory_map.total_ram() >> 10
+    );
+    #[cfg(feature = "memtest")]
+    memory::selftest::<PhysOffsetMapper>(&memory_map).expect("RAM is faulty");
+    let frame_refs =
+        memory::frame_refcount::FrameRefCount::from_memory_map::<PhysOffsetMapper>(&mut memory_map)
+            .expect("Failed to set up the frame reference counts");
+    println!("Reference counts for {} frames", frame_refs.frame_count());
+    let early_pages = memory::stack_allocator::EarlyPageAllocator::init_from_memory_map(
+        &mut memory_map,
+        bsp::config::EARLY_PAGES,
+    )
+    .expect("Failed to set up the early page allocator");
+    println!("Early page allocator: {}", early_pages);
+    status::dump(boot_info, &memory_map);
+    bsp::virtio_mmio::probe_all();
+    #[cfg(feature = "sched_selftest")]
+    sched::self_test().expect("Scheduler self test failed");
+    #[cfg(feature = "watchdog")]
+    watchdog::arm(bsp::config::WATCHDOG_TIMEOUT);
+    #[cfg(feature = "monitor")]
+    monitor::run(boot_info);
     use cpu::qemu_exit_success;
+    bsp::console::flush();
     qemu_exit_success()
 }
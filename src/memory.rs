@@ -5,12 +5,30 @@
 //! Memory Management.
 
 // pub mod mmu;
+pub mod dma;
+#[cfg(feature = "alloc_fault_injection")]
+pub mod fault_inject;
+pub mod frame_refcount;
+pub mod memory_map;
+#[cfg(feature = "memtest")]
+pub mod memtest;
+pub mod range_set;
+pub mod stack_allocator;
 
 use crate::common;
-use crate::{bsp::config::MmuGranule, common::is_aligned};
-use core::{marker::PhantomData, ops::RangeInclusive};
+use crate::{bsp::config::MmuGranule, common::is_aligned, cpu::cache, mmu::mmu::MmuReigon};
+use core::{
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 pub use crate::bsp::memory::*;
+pub use crate::mmu::mmu::StaticTable;
+#[cfg(feature = "memtest")]
+pub use memtest::selftest;
+pub use stack_allocator::{StackMetadataCell, StackPageAllocator, StackPageAllocatorMetadata};
 
 pub trait AddrMapper {
     fn map_to_vaddr(paddr: Address<Physical>) -> Address<Virtual>;
@@ -27,6 +45,43 @@ impl AddrMapper for IdentMapper {
     }
 }
 
+/// Maps physical memory at a fixed virtual offset, e.g. the kernel's linear map of RAM.
+///
+/// Unlike `IdentMapper`, this keeps working once the tables are no longer accessible at their
+/// physical address.
+pub struct OffsetMapper<const OFFSET: usize>;
+
+impl<const OFFSET: usize> AddrMapper for OffsetMapper<OFFSET> {
+    fn map_to_vaddr(paddr: Address<Physical>) -> Address<Virtual> {
+        Address::new(paddr.into_usize() + OFFSET)
+    }
+}
+
+/// Offset of the linear map of physical memory, handed over in `BootInfo`.
+static PHYS_OFFSET: AtomicUsize = AtomicUsize::new(0);
+
+/// Maps physical memory through the linear map the bootloader set up in the TTBR1 half.
+pub struct PhysOffsetMapper;
+
+impl PhysOffsetMapper {
+    /// Set the offset of the linear map.
+    ///
+    /// # Safety
+    ///
+    /// - All RAM must be mapped at `offset` in the current translation tables.
+    pub unsafe fn init(offset: usize) {
+        PHYS_OFFSET.store(offset, Ordering::Relaxed);
+    }
+}
+
+impl AddrMapper for PhysOffsetMapper {
+    fn map_to_vaddr(paddr: Address<Physical>) -> Address<Virtual> {
+        let offset = PHYS_OFFSET.load(Ordering::Relaxed);
+        debug_assert_ne!(offset, 0, "Linear map offset is not initialized");
+        Address::new(paddr.into_usize() + offset)
+    }
+}
+
 /// Metadata trait for marking the type of an address.
 pub trait AddressType: Copy + Clone + PartialOrd + PartialEq {}
 
@@ -40,6 +95,7 @@ pub enum Virtual {}
 
 /// Generic address type.
 #[derive(Copy, Clone, PartialOrd, PartialEq, Debug)]
+#[repr(C)]
 pub struct Address<ATYPE: AddressType> {
     value: usize,
     _address_type: PhantomData<fn() -> ATYPE>,
@@ -47,6 +103,7 @@ pub struct Address<ATYPE: AddressType> {
 
 /// Generic address range type.
 #[derive(Copy, Clone, Debug)]
+#[repr(C)]
 pub struct AddressRange<ATYPE: AddressType> {
     addr: Address<ATYPE>,
     size: usize,
@@ -54,15 +111,16 @@ pub struct AddressRange<ATYPE: AddressType> {
 
 /// Architecture agnostic memory attributes.
 #[allow(missing_docs)]
-#[derive(Copy, Clone, PartialOrd, PartialEq)]
+#[derive(Copy, Clone, PartialOrd, PartialEq, Debug)]
 pub enum MemAttributes {
     CacheableDRAM,
+    NonCacheable,
     Device,
 }
 
 /// Architecture agnostic access permissions.
 #[allow(missing_docs)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum AccessPermissions {
     ReadOnly,
     ReadWrite,
@@ -70,13 +128,53 @@ pub enum AccessPermissions {
 
 /// Collection of memory attributes.
 #[allow(missing_docs)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct AttributeFields {
     pub mem_attributes: MemAttributes,
     pub acc_perms: AccessPermissions,
     pub execute_never: bool,
+    /// Permit a writable and executable mapping, e.g. for code generated at runtime.
+    pub allow_wx: bool,
+    /// Tag the translation with the current ASID instead of applying it to every address space,
+    /// for EL0 mappings.
+    pub non_global: bool,
+    /// Map with the access flag set. Cleared, the hardware sets it on the first access, e.g. to
+    /// track the working set. Without hardware access flag management the flag is set anyway, see
+    /// `mmu::access_flag_mode`.
+    pub accessed: bool,
+}
+
+impl Default for AttributeFields {
+    fn default() -> AttributeFields {
+        AttributeFields {
+            mem_attributes: MemAttributes::CacheableDRAM,
+            acc_perms: AccessPermissions::ReadWrite,
+            execute_never: true,
+            allow_wx: false,
+            non_global: false,
+            accessed: true,
+        }
+    }
 }
 
+impl AttributeFields {
+    /// Enforce W^X: a mapping must not be writable and executable at once unless `allow_wx` is
+    /// set.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let writable = matches!(self.acc_perms, AccessPermissions::ReadWrite);
+        if writable && !self.execute_never && !self.allow_wx {
+            return Err("Mapping is writable and executable");
+        }
+        Ok(())
+    }
+}
+
+/// A single physical frame of `MmuGranule` size, identified by its start address.
+///
+/// Unlike `Page`, a frame does not own the memory and is freely copied.
+#[derive(Copy, Clone, PartialOrd, PartialEq, Debug)]
+pub struct PhysFrame(Address<Physical>);
+
 pub trait PageAllocator {
     fn alloc_pages(num: usize) -> Result<Page<Self>, &'static str>;
     unsafe fn free_pages(pages: &mut Page<Self>) -> Result<(), &'static str>;
@@ -90,10 +188,7 @@ pub struct Page<ALLOC: PageAllocator + ?Sized> {
 
 impl<ALLOC: PageAllocator + ?Sized> Page<ALLOC> {
     pub unsafe fn from_raw(base: Address<Physical>, num: usize) -> Self {
-        assert!(
-            base.value.trailing_zeros() as usize >= MmuGranule::SHIFT,
-            "Page not aligned"
-        );
+        assert!(base.is_aligned(MmuGranule::SIZE), "Page not aligned");
         Self {
             base,
             num,
@@ -101,6 +196,15 @@ impl<ALLOC: PageAllocator + ?Sized> Page<ALLOC> {
         }
     }
 
+    /// Like `from_raw`, but return an error for a `base` that is not granule aligned instead of
+    /// panicking, e.g. for allocator output that may be corrupt.
+    pub unsafe fn try_from_raw(base: Address<Physical>, num: usize) -> Result<Self, &'static str> {
+        if !base.is_aligned(MmuGranule::SIZE) {
+            return Err("Page not aligned");
+        }
+        Ok(Self::from_raw(base, num))
+    }
+
     #[inline(always)]
     pub const fn base(&self) -> Address<Physical> {
         self.base
@@ -121,12 +225,57 @@ impl<ALLOC: PageAllocator + ?Sized> Page<ALLOC> {
         AddressRange::new_raw(self.base.value, self.size())
     }
 
+    /// Check if the pages share at least one frame with `other`.
+    #[inline(always)]
+    pub fn phys_range_overlaps(&self, other: &AddressRange<Physical>) -> bool {
+        self.range().overlaps(other)
+    }
+
+    /// The frames of the pages, without their ownership.
+    pub fn frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.range().frames()
+    }
+
     pub fn into_raw(self) -> (Address<Physical>, usize) {
         let raw = (self.base, self.num);
         core::mem::forget(self);
         raw
     }
 
+    /// Keep the pages allocated for good, e.g. for translation tables and stacks.
+    ///
+    /// Same as `into_raw`, but states that nobody is going to free them later.
+    pub fn leak(self) -> (Address<Physical>, usize) {
+        self.into_raw()
+    }
+
+    /// Wrap `range`, which `ALLOC` does not own, e.g. memory reserved by the linker script.
+    ///
+    /// The handle is never dropped, so the range is never handed to `ALLOC::free_pages`.
+    ///
+    /// # Safety
+    ///
+    /// - `range` must be memory nothing else uses as pages of `ALLOC`.
+    pub unsafe fn from_static(range: AddressRange<Physical>) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(Self::from_raw(range.addr(), range.page_count()))
+    }
+
+    /// Check that `region` maps the pages at `MAPPER`'s address of `base` onto exactly the
+    /// physical frames of this `Page`, in order.
+    pub fn phys_contiguous<MAPPER: AddrMapper, REGION: MmuReigon<MAPPER, ALLOC>>(
+        &self,
+        region: &REGION,
+    ) -> bool
+    where
+        ALLOC: Sized,
+    {
+        let vrange = MAPPER::map_to_vrange(self.range());
+        self.range()
+            .pages()
+            .zip(vrange.pages())
+            .all(|(paddr, vaddr)| region.translate(vaddr) == Some(paddr))
+    }
+
     pub unsafe fn ref_as<MAPPER: AddrMapper, T>(&self) -> &T {
         assert!(core::mem::size_of::<T>() <= self.size());
         let vaddr = MAPPER::map_to_vaddr(self.base);
@@ -150,6 +299,68 @@ impl<ALLOC: PageAllocator + ?Sized> Page<ALLOC> {
     }
 }
 
+impl PhysFrame {
+    /// The frame starting at `addr`, which must be granule aligned.
+    pub fn from_aligned(addr: Address<Physical>) -> Self {
+        assert!(
+            addr.is_aligned(MmuGranule::SIZE),
+            "Frame address {} not aligned",
+            addr
+        );
+        Self(addr)
+    }
+
+    /// The frame `addr` lies in.
+    #[inline(always)]
+    pub const fn containing(addr: Address<Physical>) -> Self {
+        Self(addr.align_down(MmuGranule::SIZE))
+    }
+
+    #[inline(always)]
+    pub const fn start_address(&self) -> Address<Physical> {
+        self.0
+    }
+
+    #[inline(always)]
+    pub const fn range(&self) -> AddressRange<Physical> {
+        AddressRange::new(self.0, MmuGranule::SIZE)
+    }
+
+    /// The frame directly above this one.
+    #[inline(always)]
+    pub fn next(&self) -> Self {
+        Self(self.0 + MmuGranule::SIZE)
+    }
+
+    /// The frame directly below this one.
+    #[inline(always)]
+    pub fn prev(&self) -> Self {
+        Self(self.0 - MmuGranule::SIZE)
+    }
+}
+
+impl core::fmt::Display for PhysFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "frame {}", self.0)
+    }
+}
+
+/// Human-readable output of a Page, e.g. `0x40100000 + 4 pages (0x4000)`.
+impl<ALLOC: PageAllocator + ?Sized> core::fmt::Display for Page<ALLOC> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} + {} pages ({:#x})", self.base, self.num, self.size())
+    }
+}
+
+impl<ALLOC: PageAllocator + ?Sized> core::fmt::Debug for Page<ALLOC> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Page")
+            .field("base", &self.base)
+            .field("num", &self.num)
+            .finish()
+    }
+}
+
 impl<ALLOC: PageAllocator + ?Sized> Drop for Page<ALLOC> {
     fn drop(&mut self) {
         unsafe { ALLOC::free_pages(self).expect("Failed to drop pages") }
@@ -202,6 +413,20 @@ impl<ATYPE: AddressType> Address<ATYPE> {
     }
 }
 
+impl Address<Virtual> {
+    /// Check if the address lies in the TTBR1 half, i.e. all bits above
+    /// `config::TTBR1_VA_BITS` are set.
+    pub const fn is_kernel_half(&self) -> bool {
+        self.value >= !0 << crate::bsp::config::TTBR1_VA_BITS
+    }
+
+    /// Check if the address lies in the TTBR0 half, i.e. all bits above
+    /// `config::TTBR0_VA_BITS` are clear.
+    pub const fn is_user_half(&self) -> bool {
+        self.value >> crate::bsp::config::TTBR0_VA_BITS == 0
+    }
+}
+
 impl<ATYPE: AddressType> core::ops::Add<usize> for Address<ATYPE> {
     type Output = Self;
 
@@ -230,6 +455,13 @@ impl<ATYPE: AddressType> core::fmt::Display for Address<ATYPE> {
     }
 }
 
+/// Formats the address like a `usize`, so width and padding apply, e.g. `{:#018x}`.
+impl<ATYPE: AddressType> core::fmt::LowerHex for Address<ATYPE> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::LowerHex::fmt(&self.value, f)
+    }
+}
+
 impl<T, ATYPE: AddressType> core::convert::From<*const T> for Address<ATYPE> {
     fn from(cell: *const T) -> Self {
         Self::new(cell as usize)
@@ -243,26 +475,51 @@ impl<T, ATYPE: AddressType> core::convert::From<*mut T> for Address<ATYPE> {
 }
 
 impl<ATYPE: AddressType> AddressRange<ATYPE> {
+    /// The `size` bytes from `addr` on.
+    ///
+    /// A range is kept as its start and size and may reach the top of the address space, its last
+    /// address being `usize::MAX` and its end `2^64`, which `end` reports as `None`. It must not
+    /// wrap around to the bottom.
     #[inline(always)]
     pub const fn new(addr: Address<ATYPE>, size: usize) -> Self {
+        assert!(
+            size == 0 || size - 1 <= usize::MAX - addr.value,
+            "Range wraps around the address space"
+        );
         Self { addr, size }
     }
     #[inline(always)]
     pub const fn new_range(start: Address<ATYPE>, end: Address<ATYPE>) -> Self {
-        assert!(start.value <= end.value);
+        assert!(start.value <= end.value, "Range ends before it starts");
         Self {
             addr: start,
             size: end.value - start.value,
         }
     }
+    /// The range from `start` up to and including `last`, which can reach the top of the address
+    /// space unlike `new_range`. It must not cover the whole address space.
     #[inline(always)]
-    pub const fn new_raw(addr: usize, size: usize) -> Self {
+    pub const fn new_inclusive(start: Address<ATYPE>, last: Address<ATYPE>) -> Self {
+        assert!(start.value <= last.value, "Range ends before it starts");
+        assert!(
+            last.value - start.value < usize::MAX,
+            "Range covers the whole address space"
+        );
         Self {
-            addr: Address::new(addr),
-            size,
+            addr: start,
+            size: last.value - start.value + 1,
         }
     }
     #[inline(always)]
+    pub const fn new_raw(addr: usize, size: usize) -> Self {
+        Self::new(Address::new(addr), size)
+    }
+    /// The range of `pages` granule pages starting at `start`.
+    #[inline(always)]
+    pub const fn from_page_count(start: Address<ATYPE>, pages: usize) -> Self {
+        Self::new(start, pages * MmuGranule::SIZE)
+    }
+    #[inline(always)]
     pub fn addr(&self) -> Address<ATYPE> {
         self.addr
     }
@@ -270,22 +527,145 @@ impl<ATYPE: AddressType> AddressRange<ATYPE> {
     pub fn size(&self) -> usize {
         self.size
     }
+    /// The first address past the range, `None` if the range reaches the top of the address
+    /// space.
+    #[inline(always)]
+    pub const fn end(&self) -> Option<Address<ATYPE>> {
+        match self.addr.value.checked_add(self.size) {
+            Some(end) => Some(Address::new(end)),
+            None => None,
+        }
+    }
+    /// The last address in the range, `None` if the range is empty.
     #[inline(always)]
-    pub fn end(&self) -> Address<ATYPE> {
-        self.addr + self.size
+    pub const fn last(&self) -> Option<Address<ATYPE>> {
+        match self.size {
+            0 => None,
+            size => Some(Address::new(self.addr.value + (size - 1))),
+        }
     }
+    /// Number of granule pages in the range, which must be granule aligned.
+    pub fn page_count(&self) -> usize {
+        assert!(
+            self.addr.is_aligned(MmuGranule::SIZE) && is_aligned(self.size, MmuGranule::SIZE),
+            "Range {} is not page aligned",
+            self
+        );
+        self.size >> MmuGranule::SHIFT
+    }
+    /// Check if `addr` lies within the range.
+    #[inline(always)]
+    pub fn contains(&self, addr: Address<ATYPE>) -> bool {
+        match self.last() {
+            Some(last) => self.addr.value <= addr.value && addr.value <= last.value,
+            None => false,
+        }
+    }
+
+    /// Check if the two ranges share at least one address, which an empty range never does.
+    #[inline(always)]
+    pub fn overlaps(&self, other: &Self) -> bool {
+        match (self.last(), other.last()) {
+            (Some(last), Some(other_last)) => {
+                self.addr.value <= other_last.value && other.addr.value <= last.value
+            }
+            _ => false,
+        }
+    }
+
+    /// The addresses in both ranges, `None` if they do not overlap.
+    pub const fn intersection(&self, other: &Self) -> Option<Self> {
+        let (last, other_last) = match (self.last(), other.last()) {
+            (Some(last), Some(other_last)) => (last.value, other_last.value),
+            _ => return None,
+        };
+        let start = common::max(self.addr.value, other.addr.value);
+        let last = common::min(last, other_last);
+        if start <= last {
+            Some(Self::new_raw(start, last - start + 1))
+        } else {
+            None
+        }
+    }
+
+    /// The smallest `alignment` aligned range covering this one, which may end at the top of the
+    /// address space.
+    pub fn align_outward(&self, alignment: usize) -> Self {
+        let start = self.addr.align_down(alignment);
+        match self.last() {
+            Some(last) => Self::new(
+                start,
+                last.align_down(alignment).value - start.value + alignment,
+            ),
+            None if self.addr.is_aligned(alignment) => Self::new(start, 0),
+            None => Self::new(start, alignment),
+        }
+    }
+
+    /// The largest `alignment` aligned range within this one, `None` if there is none.
+    pub fn align_inward(&self, alignment: usize) -> Option<Self> {
+        assert!(alignment.is_power_of_two());
+        // The distance to the next aligned address, which does not overflow near the top.
+        let offset = self.addr.value.wrapping_neg() & (alignment - 1);
+        let size = common::align_down(self.size.checked_sub(offset)?, alignment);
+        if size != 0 {
+            Some(Self::new_raw(self.addr.value + offset, size))
+        } else {
+            None
+        }
+    }
+
+    /// The `T`s from the first to the last one in the range, which must hold at least one.
     pub fn range<T>(&self) -> RangeInclusive<*mut T> {
+        assert!(
+            self.size >= core::mem::size_of::<T>(),
+            "Range {} too small",
+            self
+        );
         RangeInclusive::new(
             self.addr.value as *mut T,
-            (self.addr.value + self.size) as *mut T,
+            (self.addr.value + (self.size - core::mem::size_of::<T>())) as *mut T,
         )
     }
 
+    /// The start addresses of the granule pages covering the range, from `addr` on.
     pub fn pages(&self) -> impl Iterator<Item = Address<ATYPE>> {
         let base = self.addr.into_usize();
-        (base..base + self.size)
-            .step_by(MmuGranule::SIZE)
-            .map(Address::new)
+        let count = (self.size >> MmuGranule::SHIFT) + (self.size % MmuGranule::SIZE != 0) as usize;
+        (0..count).map(move |page| Address::new(base + (page << MmuGranule::SHIFT)))
+    }
+}
+
+impl AddressRange<Physical> {
+    /// The frames covering the range, which must be granule aligned.
+    pub fn frames(&self) -> impl Iterator<Item = PhysFrame> {
+        assert!(
+            self.addr.is_aligned(MmuGranule::SIZE) && is_aligned(self.size, MmuGranule::SIZE),
+            "Range {} is not frame aligned",
+            self
+        );
+        self.pages().map(PhysFrame)
+    }
+}
+
+impl<ATYPE: AddressType> PartialEq for AddressRange<ATYPE> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr.value == other.addr.value && self.size == other.size
+    }
+}
+
+impl<ATYPE: AddressType> Eq for AddressRange<ATYPE> {}
+
+impl<ATYPE: AddressType> PartialOrd for AddressRange<ATYPE> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Ranges are ordered by start address, then by size.
+impl<ATYPE: AddressType> Ord for AddressRange<ATYPE> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (self.addr.value, self.size).cmp(&(other.addr.value, other.size))
     }
 }
 
@@ -314,39 +694,210 @@ where
     }
 }
 
-// //--------------------------------------------------------------------------------------------------
-// // Testing
-// //--------------------------------------------------------------------------------------------------
+/// Zero all bytes of `page`, accessed through `MAPPER`.
+///
+/// Uses `dc zva` where the CPU allows it and the widest aligned stores otherwise. Unlike
+/// `zero_volatile`, this is meant for whole pages, e.g. freshly allocated translation tables.
+///
+/// # Safety
+///
+/// - `MAPPER` must map `page` to writable normal memory.
+pub unsafe fn zero_pages<MAPPER: AddrMapper, ALLOC: PageAllocator + ?Sized>(page: &Page<ALLOC>) {
+    let vrange = MAPPER::map_to_vrange(page.range());
+    let start = vrange.addr().into_usize();
+
+    // Pages are aligned to the granule, which is larger than any zeroing block. Counting from the
+    // start keeps pages at the top of the address space working.
+    if let Some(block_size) = cache::zero_block_size() {
+        for block in 0..vrange.size() / block_size {
+            cache::zero_block(start + block * block_size);
+        }
+        return;
+    }
+
+    let ptr = start as *mut u128;
+    for offset in 0..vrange.size() / core::mem::size_of::<u128>() {
+        core::ptr::write_volatile(ptr.add(offset), 0);
+    }
+}
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use test_macros::kernel_test;
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
 
-//     /// Check `zero_volatile()`.
-//     #[kernel_test]
-//     fn zero_volatile_works() {
-//         let mut x: [usize; 3] = [10, 11, 12];
-//         let x_range = x.as_mut_ptr_range();
-//         let x_range_inclusive =
-//             RangeInclusive::new(x_range.start, unsafe { x_range.end.offset(-1) });
+#[cfg(all(test, not(target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use crate::common::{align_down, align_up};
+
+    const PAGE: usize = MmuGranule::SIZE;
+
+    fn range(addr: usize, size: usize) -> AddressRange<Virtual> {
+        AddressRange::new_raw(addr, size)
+    }
+
+    #[test]
+    fn align_helpers() {
+        assert_eq!(align_up(0, 8), 0);
+        assert_eq!(align_up(1, 8), 8);
+        assert_eq!(align_up(8, 8), 8);
+        assert_eq!(align_down(15, 8), 8);
+        assert_eq!(align_down(7, 8), 0);
+        assert_eq!(align_up(13, 1), 13);
+        assert_eq!(align_down(usize::MAX, PAGE), usize::MAX - PAGE + 1);
+        assert_eq!(align_up(usize::MAX - PAGE + 1, PAGE), usize::MAX - PAGE + 1);
+
+        assert!(is_aligned(0, PAGE));
+        assert!(is_aligned(PAGE, PAGE));
+        assert!(!is_aligned(PAGE + 8, PAGE));
+        assert!(is_aligned(usize::MAX - PAGE + 1, PAGE));
+        assert!(!is_aligned(usize::MAX, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn alignment_must_be_a_power_of_two() {
+        align_up(1, 3);
+    }
+
+    #[test]
+    fn address_alignment() {
+        let addr = Address::<Virtual>::new(0x1234);
+        assert_eq!(addr.align_down(PAGE).into_usize(), 0x1000);
+        assert_eq!(addr.align_up(PAGE).into_usize(), 0x2000);
+        assert!(!addr.is_aligned(PAGE));
+        assert!(addr.align_up(PAGE).is_aligned(PAGE));
+    }
+
+    #[test]
+    fn pages() {
+        assert_eq!(range(PAGE, 0).pages().count(), 0);
+        let pages: [usize; 3] = [PAGE, 2 * PAGE, 3 * PAGE];
+        assert!(range(PAGE, 3 * PAGE)
+            .pages()
+            .map(Address::into_usize)
+            .eq(pages.iter().copied()));
+        // A partial page at the end is still visited.
+        assert_eq!(range(0, PAGE + 1).pages().count(), 2);
+
+        let top = usize::MAX - 2 * PAGE + 1;
+        assert!(range(top, 2 * PAGE - 1)
+            .pages()
+            .map(Address::into_usize)
+            .eq([top, top + PAGE].iter().copied()));
+        assert!(range(top, 2 * PAGE)
+            .pages()
+            .map(Address::into_usize)
+            .eq([top, top + PAGE].iter().copied()));
+    }
+
+    #[test]
+    fn contains() {
+        let r = range(PAGE, PAGE);
+        assert!(r.contains(Address::new(PAGE)));
+        assert!(r.contains(Address::new(2 * PAGE - 1)));
+        assert!(!r.contains(r.end().unwrap()));
+        assert!(!r.contains(Address::new(PAGE - 1)));
+
+        let empty = range(PAGE, 0);
+        assert!(!empty.contains(Address::new(PAGE)));
+
+        let top = range(usize::MAX - PAGE, PAGE);
+        assert_eq!(top.end().map(Address::into_usize), Some(usize::MAX));
+        assert!(top.contains(Address::new(usize::MAX - 1)));
+        assert!(!top.contains(Address::new(usize::MAX)));
+        assert!(!top.contains(Address::new(0)));
+
+        let last_page = range(usize::MAX - PAGE + 1, PAGE);
+        assert!(last_page.contains(Address::new(usize::MAX)));
+        assert!(!last_page.contains(Address::new(usize::MAX - PAGE)));
+        assert!(!last_page.contains(Address::new(0)));
+    }
+
+    #[test]
+    fn overlaps() {
+        let r = range(2 * PAGE, 2 * PAGE);
+        assert!(r.overlaps(&r));
+        assert!(r.overlaps(&range(3 * PAGE, 4 * PAGE)));
+        assert!(range(3 * PAGE, 4 * PAGE).overlaps(&r));
+        assert!(r.overlaps(&range(0, 8 * PAGE)));
+        assert!(range(0, 8 * PAGE).overlaps(&r));
+
+        // Touching ranges share no address.
+        assert!(!r.overlaps(&range(4 * PAGE, PAGE)));
+        assert!(!range(4 * PAGE, PAGE).overlaps(&r));
+        assert!(!r.overlaps(&range(PAGE, PAGE)));
+
+        // Neither does an empty range, even within another one.
+        assert!(!r.overlaps(&range(3 * PAGE, 0)));
+        assert!(!range(3 * PAGE, 0).overlaps(&r));
+        assert!(!range(3 * PAGE, 0).overlaps(&range(3 * PAGE, 0)));
+
+        let top = range(usize::MAX - PAGE, PAGE);
+        assert!(top.overlaps(&range(usize::MAX - 1, 1)));
+        assert!(!top.overlaps(&range(usize::MAX - 2 * PAGE, PAGE)));
+        assert!(!top.overlaps(&range(0, PAGE)));
+
+        let last_page = range(usize::MAX - PAGE + 1, PAGE);
+        assert!(last_page.overlaps(&range(usize::MAX, 1)));
+        assert!(!last_page.overlaps(&top));
+        assert!(!last_page.overlaps(&range(0, PAGE)));
+    }
+
+    #[test]
+    fn intersection() {
+        let r = range(2 * PAGE, 2 * PAGE);
+        assert_eq!(
+            r.intersection(&range(3 * PAGE, 4 * PAGE)),
+            Some(range(3 * PAGE, PAGE))
+        );
+        assert_eq!(r.intersection(&range(4 * PAGE, PAGE)), None);
+        assert_eq!(r.intersection(&range(3 * PAGE, 0)), None);
 
-//         unsafe { zero_volatile(x_range_inclusive) };
+        let last_pages = range(usize::MAX - 2 * PAGE + 1, 2 * PAGE);
+        assert_eq!(
+            last_pages.intersection(&range(usize::MAX - PAGE + 1, PAGE)),
+            Some(range(usize::MAX - PAGE + 1, PAGE))
+        );
+        assert_eq!(last_pages.intersection(&range(0, PAGE)), None);
+    }
 
-//         assert_eq!(x, [0, 0, 0]);
-//     }
+    #[test]
+    fn alignment_near_the_top() {
+        let last_page = usize::MAX - PAGE + 1;
+        assert_eq!(
+            range(last_page + 8, 8).align_outward(PAGE),
+            range(last_page, PAGE)
+        );
+        assert_eq!(
+            range(last_page - 8, 16).align_outward(PAGE),
+            range(last_page - PAGE, 2 * PAGE)
+        );
+        assert_eq!(
+            range(last_page - 8, PAGE + 8).align_inward(PAGE),
+            Some(range(last_page, PAGE))
+        );
+        assert_eq!(range(last_page + 8, PAGE - 8).align_inward(PAGE), None);
+    }
 
-//     /// Check `bss` section layout.
-//     #[kernel_test]
-//     fn bss_section_is_sane() {
-//         use crate::bsp::memory::bss_range_inclusive;
-//         use core::mem;
+    #[test]
+    fn end_and_last_near_the_top() {
+        let empty = range(usize::MAX, 0);
+        assert_eq!(empty.end().map(Address::into_usize), Some(usize::MAX));
+        assert_eq!(empty.last(), None);
 
-//         let start = *bss_range_inclusive().start() as usize;
-//         let end = *bss_range_inclusive().end() as usize;
+        let last_page = range(usize::MAX - PAGE + 1, PAGE);
+        assert_eq!(last_page.end(), None);
+        assert_eq!(last_page.last().map(Address::into_usize), Some(usize::MAX));
+        assert_eq!(
+            AddressRange::new_inclusive(last_page.addr(), Address::new(usize::MAX)),
+            last_page
+        );
+    }
 
-//         assert_eq!(start % mem::size_of::<usize>(), 0);
-//         assert_eq!(end % mem::size_of::<usize>(), 0);
-//         assert!(end >= start);
-//     }
-// }
+    #[test]
+    #[should_panic]
+    fn range_must_not_wrap() {
+        range(usize::MAX - PAGE + 2, PAGE);
+    }
+}
@@ -0,0 +1,160 @@
+//! Buffers for devices that access memory directly.
+
+use crate::{
+    bsp::config::MmuGranule,
+    cpu::cache,
+    memory::{
+        self, AccessPermissions, AddrMapper, Address, AddressRange, AttributeFields, MemAttributes,
+        Page, PageAllocator, Physical, Virtual,
+    },
+    mmu::mmu::{MmuError, MmuReigon},
+};
+
+/// Physically contiguous pages mapped non-cacheable, so the CPU and a device observe the same
+/// content without cache maintenance.
+///
+/// The pages stay mapped cacheable in the linear map of `MAPPER` as well. That alias must not be
+/// used while the buffer lives: its lines are cleaned and invalidated before the buffer is handed
+/// out and again before the pages are freed, so none of them is written back over what the device
+/// wrote, or read stale by the next owner of the pages.
+pub struct DmaBuffer<ALLOC: PageAllocator> {
+    pages: Page<ALLOC>,
+    vrange: AddressRange<Virtual>,
+}
+
+impl<ALLOC: PageAllocator> DmaBuffer<ALLOC> {
+    const ATTRIBUTES: AttributeFields = AttributeFields {
+        mem_attributes: MemAttributes::NonCacheable,
+        acc_perms: AccessPermissions::ReadWrite,
+        execute_never: true,
+        allow_wx: false,
+        non_global: false,
+        accessed: true,
+    };
+
+    /// Allocate contiguous pages for `vrange` and map them there into `region`.
+    ///
+    /// `vrange` is granule aligned and lies in a window the caller reserves for DMA buffers, e.g.
+    /// `config::DMA_WINDOW_VBASE`, never in the linear map.
+    pub fn new<MAPPER: AddrMapper, REGION: MmuReigon<MAPPER, ALLOC>>(
+        region: &mut REGION,
+        vrange: AddressRange<Virtual>,
+    ) -> Result<Self, MmuError> {
+        let pages = ALLOC::alloc_pages(vrange.size() / MmuGranule::SIZE)?;
+        unsafe { memory::zero_pages::<MAPPER, ALLOC>(&pages) };
+        cache::clean_and_invalidate_dcache_range(MAPPER::map_to_vrange(pages.range()));
+
+        region.map_range_with(pages.range(), vrange, Self::ATTRIBUTES)?;
+        assert!(
+            pages.phys_contiguous(region),
+            "DMA buffer {} is not physically contiguous",
+            pages.range()
+        );
+        Ok(Self { pages, vrange })
+    }
+
+    /// The address to program into the device.
+    #[inline(always)]
+    pub fn paddr(&self) -> Address<Physical> {
+        self.pages.base()
+    }
+
+    /// The address the CPU accesses the buffer at.
+    #[inline(always)]
+    pub fn vaddr(&self) -> Address<Virtual> {
+        self.vrange.addr()
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> usize {
+        self.pages.size()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.vaddr().into_usize() as *const u8, self.size()) }
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self.vaddr().into_usize() as *mut u8, self.size())
+        }
+    }
+
+    /// Remove the mapping from `region` and hand the pages back to the allocator.
+    pub fn unmap<MAPPER: AddrMapper, REGION: MmuReigon<MAPPER, ALLOC>>(
+        self,
+        region: &mut REGION,
+    ) -> Result<(), MmuError> {
+        for vaddr in self.vrange.pages() {
+            region.unmap_page(vaddr)?;
+        }
+        // Lines the linear map may have fetched speculatively since `new`.
+        cache::clean_and_invalidate_dcache_range(MAPPER::map_to_vrange(self.pages.range()));
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, not(target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+    use crate::{
+        arch::host::{exclusive_ttbrs, HostPageAllocator},
+        bsp::config,
+        memory::IdentMapper,
+        mmu::mmu::MemoryManagementUnit,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Pages `CountingAllocator` handed out and did not get back yet.
+    static HELD_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+    /// `HostPageAllocator`, counting the pages it holds out.
+    struct CountingAllocator;
+
+    impl PageAllocator for CountingAllocator {
+        fn alloc_pages(num: usize) -> Result<Page<Self>, &'static str> {
+            let (base, num) = HostPageAllocator::alloc_pages(num)?.leak();
+            HELD_PAGES.fetch_add(num, Ordering::SeqCst);
+            Ok(unsafe { Page::from_raw(base, num) })
+        }
+
+        unsafe fn free_pages(pages: &mut Page<Self>) -> Result<(), &'static str> {
+            HELD_PAGES.fetch_sub(pages.page_num(), Ordering::SeqCst);
+            drop(Page::<HostPageAllocator>::from_raw(
+                pages.base(),
+                pages.page_num(),
+            ));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn map_and_unmap() {
+        let _ttbrs = exclusive_ttbrs();
+        let mut mmu = unsafe { MemoryManagementUnit::<CountingAllocator>::new() };
+        let region = mmu.ttbl1::<IdentMapper>();
+        let vrange = AddressRange::from_page_count(Address::new(config::DMA_WINDOW_VBASE), 2);
+
+        let buffer = DmaBuffer::new(region, vrange).unwrap();
+        assert_eq!(buffer.vaddr(), vrange.addr());
+        assert_eq!(buffer.size(), vrange.size());
+        for (offset, vaddr) in (0..).step_by(MmuGranule::SIZE).zip(vrange.pages()) {
+            assert_eq!(region.translate(vaddr), Some(buffer.paddr() + offset));
+            assert_eq!(
+                region.attributes(vaddr).unwrap().mem_attributes,
+                MemAttributes::NonCacheable
+            );
+        }
+
+        let held = HELD_PAGES.load(Ordering::SeqCst);
+        buffer.unmap(region).unwrap();
+        assert_eq!(HELD_PAGES.load(Ordering::SeqCst), held - 2);
+        for vaddr in vrange.pages() {
+            assert_eq!(region.translate(vaddr), None);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Page allocator wrapper that fails on demand, to reach the error paths of its users.
+
+use crate::memory::{Page, PageAllocator};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Allocations left before the next one fails, `usize::MAX` while disarmed.
+///
+/// Shared by every `FaultingAllocator`, a static can not depend on the inner allocator.
+static REMAINING: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Pages handed out through a `FaultingAllocator` and not passed back to `free_pages` yet.
+static OUTSTANDING_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Forwards to `A`, except for the allocation `fail_after` picked, which returns
+/// `Err("injected")`.
+pub struct FaultingAllocator<A: PageAllocator> {
+    _inner: PhantomData<A>,
+}
+
+impl<A: PageAllocator> FaultingAllocator<A> {
+    /// Let `allocations` more allocations succeed and fail the one after.
+    pub fn fail_after(allocations: usize) {
+        assert!(allocations != usize::MAX);
+        REMAINING.store(allocations, Ordering::SeqCst);
+    }
+
+    /// Stop injecting failures.
+    pub fn disarm() {
+        REMAINING.store(usize::MAX, Ordering::SeqCst);
+    }
+
+    /// Number of pages allocated through the wrapper and not handed back yet.
+    ///
+    /// Counts what the caller gave back, whether or not `A` could actually reuse it.
+    pub fn outstanding_pages() -> usize {
+        OUTSTANDING_PAGES.load(Ordering::SeqCst)
+    }
+}
+
+impl<A: PageAllocator> PageAllocator for FaultingAllocator<A> {
+    fn alloc_pages(num: usize) -> Result<Page<Self>, &'static str> {
+        let armed = REMAINING.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| match left {
+            0 => None,
+            usize::MAX => Some(usize::MAX),
+            left => Some(left - 1),
+        });
+        if armed.is_err() {
+            // Keep failing until re-armed, so a retry does not slip through.
+            return Err("injected");
+        }
+
+        let (base, num) = A::alloc_pages(num)?.into_raw();
+        OUTSTANDING_PAGES.fetch_add(num, Ordering::SeqCst);
+        Ok(unsafe { Page::from_raw(base, num) })
+    }
+
+    unsafe fn free_pages(pages: &mut Page<Self>) -> Result<(), &'static str> {
+        let mut inner = Page::<A>::from_raw(pages.base(), pages.page_num());
+        let result = A::free_pages(&mut inner);
+        // Ownership stays with `pages`, the caller decides what happens to it on failure.
+        inner.into_raw();
+        OUTSTANDING_PAGES.fetch_sub(pages.page_num(), Ordering::SeqCst);
+        result
+    }
+}
@@ -0,0 +1,88 @@
+//! Reference counts of physical frames, for frames mapped more than once.
+
+use crate::{
+    bsp::config::MmuGranule,
+    memory::{memory_map::MemoryMap, AddrMapper, AddressRange, PhysFrame, Physical},
+};
+use core::sync::atomic::{AtomicU16, Ordering};
+
+/// The number of mappings of every frame of RAM, indexed by frame number from the start of RAM.
+///
+/// A frame that is shared, e.g. copy-on-write or a read-only segment mapped into several address
+/// spaces, must only be freed once its last mapping is gone.
+pub struct FrameRefCount {
+    ram: AddressRange<Physical>,
+    counts: &'static mut [AtomicU16],
+}
+
+impl FrameRefCount {
+    /// Take the counters from the first free range of `map` that fits them and reserve it. All
+    /// counts start at zero.
+    ///
+    /// # Safety
+    ///
+    /// - `MAPPER` must map the free RAM of `map` writable for as long as the instance lives.
+    /// - Nothing may use the free RAM of `map` yet.
+    pub unsafe fn from_memory_map<MAPPER: AddrMapper>(
+        map: &mut MemoryMap,
+    ) -> Result<Self, &'static str> {
+        let ram = map.ram();
+        let frames = ram.size() / MmuGranule::SIZE;
+        let size =
+            crate::common::align_up(frames * core::mem::size_of::<AtomicU16>(), MmuGranule::SIZE);
+        let table = map.reserve_free(size, MmuGranule::SIZE)?;
+
+        let vaddr = MAPPER::map_to_vaddr(table.addr()).into_usize();
+        core::ptr::write_bytes(vaddr as *mut u8, 0, size);
+        Ok(Self {
+            ram,
+            counts: core::slice::from_raw_parts_mut(vaddr as *mut AtomicU16, frames),
+        })
+    }
+
+    /// Number of frames counted.
+    pub fn frame_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    fn count(&self, frame: PhysFrame) -> &AtomicU16 {
+        let paddr = frame.start_address();
+        assert!(
+            self.ram.contains(paddr),
+            "Frame {} is not in RAM {}",
+            paddr,
+            self.ram
+        );
+        &self.counts[(paddr.into_usize() - self.ram.addr().into_usize()) / MmuGranule::SIZE]
+    }
+
+    /// Number of mappings of `frame`.
+    pub fn get(&self, frame: PhysFrame) -> u16 {
+        self.count(frame).load(Ordering::Acquire)
+    }
+
+    /// Count another mapping of `frame`.
+    pub fn inc(&self, frame: PhysFrame) {
+        if self
+            .count(frame)
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                count.checked_add(1)
+            })
+            .is_err()
+        {
+            panic!("Frame {} mapped too often", frame.start_address());
+        }
+    }
+
+    /// Drop a mapping of `frame`. Returns `true` if it was the last one, so the frame can be freed.
+    pub fn dec(&self, frame: PhysFrame) -> bool {
+        match self
+            .count(frame)
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                count.checked_sub(1)
+            }) {
+            Ok(previous) => previous == 1,
+            Err(_) => panic!("Frame {} is not mapped", frame.start_address()),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! The kernel's view of physical memory after boot.
+
+use crate::{
+    boot::BootInfo,
+    memory::{range_set::FixedRangeSet, Address, AddressRange, Physical},
+};
+
+/// Number of disjoint reserved ranges a `MemoryMap` can track.
+const RESERVED_CAPACITY: usize = 32;
+
+/// All RAM and the parts of it that are taken, everything else is free for the frame allocator.
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryMap {
+    ram: AddressRange<Physical>,
+    reserved: FixedRangeSet<RESERVED_CAPACITY>,
+}
+
+impl MemoryMap {
+    /// A map of `ram` with nothing reserved yet.
+    pub const fn new(ram: AddressRange<Physical>) -> Self {
+        Self {
+            ram,
+            reserved: FixedRangeSet::new(),
+        }
+    }
+
+    /// Build the map from what the bootloader handed over.
+    ///
+    /// Everything from the start of RAM up to the pages the bootloader allocated is reserved as
+    /// well: that is where QEMU places the device tree and the bootloader image.
+    pub fn from_boot_info(boot_info: &BootInfo) -> Result<Self, &'static str> {
+        let mut map = Self::new(boot_info.ram);
+        let used = boot_info.used_pages;
+        if used.addr() > map.ram.addr() {
+            map.reserve(AddressRange::new_range(map.ram.addr(), used.addr()))?;
+        }
+        map.reserve(used)?;
+        Ok(map)
+    }
+
+    /// Mark `range` as taken. Parts outside of RAM are ignored when counting free memory.
+    pub fn reserve(&mut self, range: AddressRange<Physical>) -> Result<(), &'static str> {
+        self.reserved.insert(range)
+    }
+
+    /// Reserve `size` bytes at the start of the first free range that fits them aligned to
+    /// `alignment`, and return them.
+    pub fn reserve_free(
+        &mut self,
+        size: usize,
+        alignment: usize,
+    ) -> Result<AddressRange<Physical>, &'static str> {
+        let range = self
+            .iter_free()
+            .filter_map(|free| free.align_inward(alignment))
+            .find(|free| free.size() >= size)
+            .map(|free| AddressRange::new(free.addr(), size))
+            .ok_or("No free range fits the reservation")?;
+        self.reserve(range)?;
+        Ok(range)
+    }
+
+    /// All RAM, reserved or not.
+    #[inline(always)]
+    pub fn ram(&self) -> AddressRange<Physical> {
+        self.ram
+    }
+
+    /// Size of all RAM in bytes.
+    #[inline(always)]
+    pub fn total_ram(&self) -> usize {
+        self.ram.size()
+    }
+
+    /// Size of the RAM not reserved in bytes.
+    pub fn free_ram(&self) -> usize {
+        self.iter_free().map(|range| range.size()).sum()
+    }
+
+    /// Check if `addr` lies in a reserved range.
+    pub fn is_reserved(&self, addr: Address<Physical>) -> bool {
+        self.reserved.contains_addr(addr)
+    }
+
+    /// The free parts of RAM in ascending order.
+    pub fn iter_free(&self) -> impl Iterator<Item = AddressRange<Physical>> + '_ {
+        self.reserved.free_gaps(self.ram)
+    }
+}
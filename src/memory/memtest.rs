@@ -0,0 +1,115 @@
+//! Destructive RAM test, run before the free memory is handed out.
+
+use crate::{
+    bsp::config::MmuGranule,
+    memory::{memory_map::MemoryMap, AddrMapper, Address, AddressRange, Physical},
+};
+
+/// Bytes tested at the start of each free range, to bound the time spent at boot.
+const SAMPLE_SIZE: usize = 256 * MmuGranule::SIZE;
+
+/// Number of mismatches printed before the rest are only counted.
+const MAX_REPORTS: usize = 8;
+
+/// Bits that read back differently from what was written.
+#[derive(Default)]
+struct Failures {
+    count: usize,
+    stuck_high: u64,
+    stuck_low: u64,
+}
+
+impl Failures {
+    fn check(&mut self, paddr: Address<Physical>, expected: u64, read: u64) {
+        if read == expected {
+            return;
+        }
+        if self.count < MAX_REPORTS {
+            println!(
+                "Memtest: {:#018x} reads {:#018x}, expected {:#018x}",
+                paddr, read, expected
+            );
+        }
+        self.count += 1;
+        self.stuck_high |= read & !expected;
+        self.stuck_low |= expected & !read;
+    }
+}
+
+/// Walk a single one and a single zero through the first word of `sample`, catching data lines
+/// stuck at either level.
+unsafe fn walking_bits<MAPPER: AddrMapper>(
+    sample: AddressRange<Physical>,
+    failures: &mut Failures,
+) {
+    let word = MAPPER::map_to_vaddr(sample.addr()).into_usize() as *mut u64;
+    for bit in 0..64 {
+        for &pattern in &[1 << bit, !(1 << bit)] {
+            word.write_volatile(pattern);
+            failures.check(sample.addr(), pattern, word.read_volatile());
+        }
+    }
+}
+
+/// Store every word's own address in it, then its complement, and read all of them back,
+/// catching address lines that alias two words.
+unsafe fn address_in_address<MAPPER: AddrMapper>(
+    sample: AddressRange<Physical>,
+    failures: &mut Failures,
+) {
+    let words = sample.size() / core::mem::size_of::<u64>();
+    let base = MAPPER::map_to_vaddr(sample.addr()).into_usize() as *mut u64;
+    for &invert in &[0, u64::MAX] {
+        for i in 0..words {
+            let paddr = sample.addr() + i * core::mem::size_of::<u64>();
+            base.add(i)
+                .write_volatile(paddr.into_usize() as u64 ^ invert);
+        }
+        for i in 0..words {
+            let paddr = sample.addr() + i * core::mem::size_of::<u64>();
+            failures.check(
+                paddr,
+                paddr.into_usize() as u64 ^ invert,
+                base.add(i).read_volatile(),
+            );
+        }
+    }
+}
+
+/// Test the start of every free range of `map`, reached through `MAPPER`, and report the bits that
+/// do not hold their value.
+///
+/// Reserved ranges, i.e. the kernel image, its stack and the translation tables, are left alone.
+/// The tested memory is overwritten.
+///
+/// # Safety
+///
+/// - `MAPPER` must map all free RAM of `map` writable.
+/// - Nothing may use the free RAM of `map` yet.
+pub unsafe fn selftest<MAPPER: AddrMapper>(map: &MemoryMap) -> Result<(), &'static str> {
+    let mut failures = Failures::default();
+    let mut tested = 0;
+    for free in map.iter_free() {
+        let sample = match free.align_inward(MmuGranule::SIZE) {
+            Some(aligned) => AddressRange::new(aligned.addr(), aligned.size().min(SAMPLE_SIZE)),
+            None => continue,
+        };
+        walking_bits::<MAPPER>(sample, &mut failures);
+        address_in_address::<MAPPER>(sample, &mut failures);
+        tested += sample.size();
+    }
+
+    println!(
+        "Memtest: {} KiB tested, {} mismatches",
+        tested >> 10,
+        failures.count
+    );
+    if failures.count != 0 {
+        println!(
+            "Memtest: bits stuck high {:#018x}, stuck low {:#018x}",
+            failures.stuck_high, failures.stuck_low
+        );
+        return Err("RAM test failed");
+    }
+    Ok(())
+}
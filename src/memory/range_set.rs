@@ -0,0 +1,128 @@
+//! A set of physical ranges that lives without a heap.
+
+use crate::memory::{Address, AddressRange, Physical};
+
+/// Up to `N` physical ranges, e.g. the regions reserved during boot.
+///
+/// Inserted ranges are merged with the ones they overlap or touch, so the set always holds
+/// disjoint ranges sorted by start address.
+#[derive(Copy, Clone, Debug)]
+pub struct FixedRangeSet<const N: usize> {
+    ranges: [AddressRange<Physical>; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedRangeSet<N> {
+    /// Create an empty set.
+    pub const fn new() -> Self {
+        Self {
+            ranges: [AddressRange::new_raw(0, 0); N],
+            len: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The ranges in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = &AddressRange<Physical>> {
+        self.ranges[..self.len].iter()
+    }
+
+    /// Add `range` to the set.
+    ///
+    /// Fails if `range` does not touch any range of the set and all `N` slots are taken.
+    pub fn insert(&mut self, range: AddressRange<Physical>) -> Result<(), &'static str> {
+        let (mut start, mut last) = match range.last() {
+            Some(last) => (range.addr().into_usize(), last.into_usize()),
+            None => return Ok(()),
+        };
+
+        // Absorb the ranges the new one overlaps or touches, keep the others in order. Ranges are
+        // compared by their last addresses, so ones reaching the top of the address space work.
+        let mut kept = 0;
+        for i in 0..self.len {
+            let current = self.ranges[i];
+            let current_start = current.addr().into_usize();
+            match current.last().map(Address::into_usize) {
+                Some(current_last)
+                    if current_start <= last.saturating_add(1)
+                        && start <= current_last.saturating_add(1) =>
+                {
+                    start = start.min(current_start);
+                    last = last.max(current_last);
+                }
+                _ => {
+                    self.ranges[kept] = current;
+                    kept += 1;
+                }
+            }
+        }
+        if kept == N {
+            return Err("Range set is full");
+        }
+
+        let pos = self.ranges[..kept]
+            .iter()
+            .position(|current| current.addr().into_usize() > start)
+            .unwrap_or(kept);
+        for i in (pos..kept).rev() {
+            self.ranges[i + 1] = self.ranges[i];
+        }
+        self.ranges[pos] = AddressRange::new_inclusive(Address::new(start), Address::new(last));
+        self.len = kept + 1;
+        Ok(())
+    }
+
+    /// Check if any range of the set contains `addr`.
+    pub fn contains_addr(&self, addr: Address<Physical>) -> bool {
+        self.iter().any(|range| range.contains(addr))
+    }
+
+    /// Check if `range` shares at least one address with any range of the set.
+    pub fn overlaps_any(&self, range: &AddressRange<Physical>) -> bool {
+        self.iter().any(|current| current.overlaps(range))
+    }
+
+    /// The parts of `within` not covered by the set, in ascending order.
+    pub fn free_gaps(
+        &self,
+        within: AddressRange<Physical>,
+    ) -> impl Iterator<Item = AddressRange<Physical>> + '_ {
+        let last = within.last().map(Address::into_usize);
+        // The first address not yet looked at, `None` past the top of the address space.
+        let mut cursor = Some(within.addr().into_usize());
+        let mut ranges = self.iter();
+        core::iter::from_fn(move || {
+            let last = last?;
+            while let Some(gap_start) = cursor.filter(|&cursor| cursor <= last) {
+                // The gap ends before the next range, which is never empty, and goes on past it.
+                let (gap_last, next_cursor) = match ranges.next() {
+                    Some(range) => {
+                        let before = range.addr().into_usize().checked_sub(1);
+                        let after = range
+                            .last()
+                            .and_then(|last| last.into_usize().checked_add(1));
+                        (before.map(|before| before.min(last)), after)
+                    }
+                    None => (Some(last), None),
+                };
+                cursor = next_cursor.map(|next| next.max(gap_start));
+                if let Some(gap_last) = gap_last.filter(|&gap_last| gap_start <= gap_last) {
+                    return Some(AddressRange::new_inclusive(
+                        Address::new(gap_start),
+                        Address::new(gap_last),
+                    ));
+                }
+            }
+            None
+        })
+    }
+}
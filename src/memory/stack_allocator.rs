@@ -0,0 +1,148 @@
+//! Bump allocator handing out pages from one fixed range, for the time before the real allocator
+//! is up: the bootloader, and the kernel from its first instruction on.
+
+use crate::{
+    bsp::config::MmuGranule,
+    cpu::smp::SingleCoreCell,
+    memory::{
+        memory_map::MemoryMap, range_set::FixedRangeSet, Address, AddressRange, Page,
+        PageAllocator, Physical,
+    },
+};
+use core::marker::PhantomData;
+
+/// Where a `StackPageAllocator` keeps its state.
+///
+/// `PageAllocator` has no receiver, so every allocator instance is a type of its own that finds its
+/// metadata through this trait, usually in a `static`.
+pub trait StackMetadataCell {
+    fn metadata() -> &'static SingleCoreCell<StackPageAllocatorMetadata>;
+}
+
+/// Number of disjoint ranges a `StackPageAllocatorMetadata` can keep reserved.
+const RESERVED_CAPACITY: usize = 8;
+
+/// The range a `StackPageAllocator` hands out and the start of what it did not hand out yet.
+pub struct StackPageAllocatorMetadata {
+    start: Address<Physical>,
+    end: Address<Physical>,
+    top: Address<Physical>,
+    /// Memory that is in use otherwise and must never be handed out.
+    reserved: FixedRangeSet<RESERVED_CAPACITY>,
+}
+
+impl StackPageAllocatorMetadata {
+    /// Metadata of an allocator that is not initialized yet, allocations panic.
+    pub const fn new() -> Self {
+        Self {
+            start: Address::new(0),
+            end: Address::new(0),
+            top: Address::new(0),
+            reserved: FixedRangeSet::new(),
+        }
+    }
+
+    /// Hand out the `num` pages from `start` on, forgetting what was allocated before.
+    pub fn init(&mut self, start: Address<Physical>, num: usize) {
+        assert!(start.is_aligned(MmuGranule::SIZE), "Page stack not aligned");
+        self.start = start;
+        self.top = start;
+        self.end = AddressRange::from_page_count(start, num)
+            .end()
+            .expect("Page stack reaches the top of the address space");
+    }
+
+    /// Make allocations panic if they overlap `range`, e.g. the image of the running code.
+    ///
+    /// The range is expected to lie outside of what the allocator hands out, this only catches
+    /// corrupted metadata.
+    pub fn reserve(&mut self, range: AddressRange<Physical>) -> Result<(), &'static str> {
+        self.reserved.insert(range)
+    }
+
+    /// The whole range the allocator hands out.
+    pub const fn range(&self) -> AddressRange<Physical> {
+        AddressRange::new_range(self.start, self.end)
+    }
+
+    /// The pages allocated so far.
+    pub const fn used(&self) -> AddressRange<Physical> {
+        AddressRange::new_range(self.start, self.top)
+    }
+}
+
+/// Hands out pages in ascending order from the range in the metadata of `CELL`, never taking any
+/// back.
+pub struct StackPageAllocator<CELL: StackMetadataCell> {
+    _cell: PhantomData<CELL>,
+}
+
+impl<CELL: StackMetadataCell> StackPageAllocator<CELL> {
+    /// Take `num` pages from the first free range of `map` that fits them, reserve them and hand
+    /// them out from now on.
+    pub fn init_from_memory_map(
+        map: &mut MemoryMap,
+        num: usize,
+    ) -> Result<AddressRange<Physical>, &'static str> {
+        let range = map.reserve_free(num * MmuGranule::SIZE, MmuGranule::SIZE)?;
+        CELL::metadata().with(|metadata| metadata.init(range.addr(), num));
+        Ok(range)
+    }
+
+    /// Like `alloc_pages`, with the first page aligned to `alignment`.
+    ///
+    /// The pages skipped to reach the alignment are not handed out anymore.
+    pub fn alloc_aligned_pages(num: usize, alignment: usize) -> Result<Page<Self>, &'static str> {
+        CELL::metadata().with(|metadata| metadata.top = metadata.top.align_up(alignment));
+        Self::alloc_pages(num)
+    }
+}
+
+impl<CELL: StackMetadataCell> PageAllocator for StackPageAllocator<CELL> {
+    fn alloc_pages(num: usize) -> Result<Page<Self>, &'static str> {
+        CELL::metadata().with(|metadata| {
+            assert_ne!(metadata.start.into_usize(), 0);
+            let pages = AddressRange::from_page_count(metadata.top, num);
+            let end = match pages.end() {
+                Some(end) if end <= metadata.end => end,
+                _ => return Err("Page stack overflow"),
+            };
+            // A misaligned top means the metadata got corrupted.
+            let page = unsafe { Page::try_from_raw(metadata.top, num) }
+                .map_err(|_| "Page stack top not aligned")?;
+            if let Some(reserved) = metadata
+                .reserved
+                .iter()
+                .find(|reserved| page.phys_range_overlaps(reserved))
+            {
+                panic!(
+                    "Page stack hands out {}, which overlaps reserved {}",
+                    page.range(),
+                    reserved
+                );
+            }
+            metadata.top = end;
+            Ok(page)
+        })
+    }
+
+    unsafe fn free_pages(_pages: &mut Page<Self>) -> Result<(), &'static str> {
+        Err("Page stack free not supported")
+    }
+}
+
+static EARLY_METADATA: SingleCoreCell<StackPageAllocatorMetadata> =
+    SingleCoreCell::new(StackPageAllocatorMetadata::new());
+
+/// The metadata of `EarlyPageAllocator`.
+pub struct EarlyPages;
+
+impl StackMetadataCell for EarlyPages {
+    fn metadata() -> &'static SingleCoreCell<StackPageAllocatorMetadata> {
+        &EARLY_METADATA
+    }
+}
+
+/// The kernel's allocator until the frame allocator is up, set up from the free RAM in `BootInfo`
+/// with `init_from_memory_map`.
+pub type EarlyPageAllocator = StackPageAllocator<EarlyPages>;
@@ -21,6 +21,9 @@ use core::{fmt, ops::RangeInclusive};
 //--------------------------------------------------------------------------------------------------
 pub use crate::arch::mmu;
 
+/// The layout describes its ranges with the same attributes the translation tables consume.
+pub use crate::memory::{AccessPermissions, AttributeFields, MemAttributes};
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -55,31 +58,6 @@ pub enum Translation {
     Offset(usize),
 }
 
-/// Architecture agnostic memory attributes.
-#[allow(missing_docs)]
-#[derive(Copy, Clone)]
-pub enum MemAttributes {
-    CacheableDRAM,
-    Device,
-}
-
-/// Architecture agnostic access permissions.
-#[allow(missing_docs)]
-#[derive(Copy, Clone)]
-pub enum AccessPermissions {
-    ReadOnly,
-    ReadWrite,
-}
-
-/// Collection of memory attributes.
-#[allow(missing_docs)]
-#[derive(Copy, Clone)]
-pub struct AttributeFields {
-    pub mem_attributes: MemAttributes,
-    pub acc_perms: AccessPermissions,
-    pub execute_never: bool,
-}
-
 /// Architecture agnostic descriptor for a memory range.
 #[allow(missing_docs)]
 pub struct TranslationDescriptor {
@@ -137,16 +115,6 @@ impl<const AS_SIZE: usize> AddressSpaceSize<AS_SIZE> {
     }
 }
 
-impl Default for AttributeFields {
-    fn default() -> AttributeFields {
-        AttributeFields {
-            mem_attributes: MemAttributes::CacheableDRAM,
-            acc_perms: AccessPermissions::ReadWrite,
-            execute_never: true,
-        }
-    }
-}
-
 /// Human-readable output of a TranslationDescriptor.
 impl fmt::Display for TranslationDescriptor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -172,6 +140,7 @@ impl fmt::Display for TranslationDescriptor {
 
         let attr = match self.attribute_fields.mem_attributes {
             MemAttributes::CacheableDRAM => "C",
+            MemAttributes::NonCacheable => "NC",
             MemAttributes::Device => "Dev",
         };
 
@@ -203,11 +172,16 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
         }
     }
 
+    /// The last (inclusive) address of the address space.
+    pub const fn max_virt_addr_inclusive(&self) -> usize {
+        self.max_virt_addr_inclusive
+    }
+
     /// For a virtual address, find and return the physical output address and corresponding
     /// attributes.
     ///
     /// If the address is not found in `inner`, return an identity mapped default with normal
-    /// cacheable DRAM attributes.
+    /// cacheable DRAM attributes. Fails if an offset translation leaves the address space.
     pub fn virt_addr_properties(
         &self,
         virt_addr: usize,
@@ -220,7 +194,9 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
             if (i.virtual_range)().contains(&virt_addr) {
                 let output_addr = match i.physical_range_translation {
                     Translation::Identity => virt_addr,
-                    Translation::Offset(a) => a + (virt_addr - (i.virtual_range)().start()),
+                    Translation::Offset(a) => a
+                        .checked_add(virt_addr - (i.virtual_range)().start())
+                        .ok_or("Offset translation overflow")?,
                 };
 
                 return Ok((output_addr, i.attribute_fields));
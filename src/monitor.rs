@@ -0,0 +1,104 @@
+//! Interactive command loop over the console, for poking at the running kernel.
+
+use core::str::SplitWhitespace;
+
+use crate::{
+    boot::BootInfo,
+    bsp::console,
+    cpu::{self, tlb},
+    debug::{self, HEXDUMP_ROW},
+    memory::{AddrMapper, Address, PhysOffsetMapper, Physical, Virtual},
+    status,
+};
+
+/// Longest command line accepted, the rest of the line is dropped.
+const LINE_SIZE: usize = 128;
+
+/// Most bytes a single `mem` command dumps.
+const MAX_DUMP: usize = 4096;
+
+const HELP: &str = "Commands:
+\tmem <paddr> <len>\tHexdump physical memory
+\tmap <vaddr>\t\tTranslate a virtual address
+\tregs\t\t\tPrint the system registers
+\treset\t\t\tReboot the machine
+\texit\t\t\tLeave the monitor";
+
+/// Parse a number, as hex if it starts with `0x`.
+fn parse_number(s: &str) -> Result<usize, &'static str> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| "Invalid number")
+}
+
+/// The next argument of the command, parsed as a number.
+fn next_number(args: &mut SplitWhitespace<'_>) -> Result<usize, &'static str> {
+    parse_number(args.next().ok_or("Missing argument")?)
+}
+
+/// Hexdump `len` bytes of RAM at `paddr`, read through the linear map.
+fn dump_memory(boot_info: &BootInfo, paddr: usize, len: usize) -> Result<(), &'static str> {
+    if len == 0 || len > MAX_DUMP {
+        return Err("Length must be between 1 and 4096 bytes");
+    }
+    let last = paddr.checked_add(len - 1).ok_or("Range outside of RAM")?;
+    if !boot_info.ram.contains(Address::new(paddr)) || !boot_info.ram.contains(Address::new(last)) {
+        return Err("Range outside of RAM");
+    }
+
+    let vaddr = PhysOffsetMapper::map_to_vaddr(Address::<Physical>::new(paddr)).into_usize();
+    let mut row = [0; HEXDUMP_ROW];
+    for offset in (0..len).step_by(HEXDUMP_ROW) {
+        let count = HEXDUMP_ROW.min(len - offset);
+        for (i, byte) in row[..count].iter_mut().enumerate() {
+            *byte = unsafe { ((vaddr + offset + i) as *const u8).read_volatile() };
+        }
+        debug::hexdump(&row[..count], paddr + offset);
+    }
+    Ok(())
+}
+
+/// Run one command line. Returns `Ok(false)` if the monitor should be left.
+fn execute(boot_info: &BootInfo, line: &str) -> Result<bool, &'static str> {
+    let mut args = line.split_whitespace();
+    match args.next() {
+        None => {}
+        Some("help") => println!("{}", HELP),
+        Some("mem") => {
+            let paddr = next_number(&mut args)?;
+            let len = next_number(&mut args)?;
+            dump_memory(boot_info, paddr, len)?;
+        }
+        Some("map") => {
+            let vaddr = Address::<Virtual>::new(next_number(&mut args)?);
+            match tlb::translate(vaddr) {
+                Some(paddr) => println!("{:#018x} -> {:#018x}", vaddr, paddr),
+                None => println!("{:#018x} is not mapped", vaddr),
+            }
+        }
+        Some("regs") => status::dump_registers(),
+        Some("reset") => {
+            console::flush();
+            cpu::reset()
+        }
+        Some("exit") => return Ok(false),
+        Some(_) => return Err("Unknown command, try `help`"),
+    }
+    Ok(true)
+}
+
+/// Read and run commands from the console until `exit` is entered.
+pub fn run(boot_info: &BootInfo) {
+    println!("Monitor ready, type `help` for the commands");
+    let mut buf = [0; LINE_SIZE];
+    loop {
+        print!("> ");
+        match execute(boot_info, console::read_line(&mut buf)) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => println!("Error: {}", err),
+        }
+    }
+}
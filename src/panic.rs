@@ -1,7 +1,23 @@
-use core::panic::PanicInfo;
+use core::{fmt::Write, panic::PanicInfo};
+use crate::{
+    bsp::{self, console},
+    cpu,
+};
 
 #[panic_handler]
 fn on_panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    loop {}
+    cpu::halt_others();
+    // The panic may have happened while printing, holding the output lock.
+    let _ = writeln!(
+        bsp::panic_console(),
+        "Kernel panic on core {:#x}: {}",
+        cpu::smp::core_id(),
+        info
+    );
+    // Whatever else is printed goes through the normal console again.
+    unsafe { console::break_output_lock() };
+    #[cfg(feature = "mmu_trace")]
+    crate::mmu::mmu::trace::dump();
+    console::flush();
+    cpu::end_panic()
 }
\ No newline at end of file
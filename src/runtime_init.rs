@@ -4,7 +4,13 @@
 
 //! Rust runtime initialization code.
 
-use crate::memory;
+use crate::{
+    boot::BootInfo,
+    bsp::{config, console},
+    cpu,
+    memory::{self, Address, AddressRange, Virtual},
+    mmu::{self, mmu::TranslationConfig},
+};
 
 // use crate::{bsp, memory};
 
@@ -22,21 +28,86 @@ unsafe fn zero_bss() {
     memory::zero_volatile(memory::bss_range_inclusive());
 }
 
+/// Check the state the bootloader handed over and halt if it is not what the kernel expects.
+///
+/// # Safety
+///
+/// - `boot_info` must be null or point to readable memory.
+unsafe fn check_handover(boot_info: *const BootInfo) {
+    let sp = Address::<Virtual>::new(cpu::stack_pointer());
+    let stack = AddressRange::<Virtual>::from_page_count(
+        Address::new(config::KERNEL_STACK_VBASE),
+        config::KERNEL_STACK_PAGES,
+    );
+    if !stack.contains(sp) {
+        println!(
+            "Stack pointer {} is outside of the expected stack {}",
+            sp, stack
+        );
+        cpu::wait_forever()
+    }
+
+    match boot_info.as_ref() {
+        None => {
+            println!("No boot info handed over");
+            cpu::wait_forever()
+        }
+        Some(boot_info) if !boot_info.is_valid() => {
+            println!(
+                "Boot info magic is {:#x} version {}, expected {:#x} version {}",
+                boot_info.magic,
+                boot_info.version,
+                crate::boot::BOOT_INFO_MAGIC,
+                crate::boot::BOOT_INFO_VERSION
+            );
+            cpu::wait_forever()
+        }
+        Some(boot_info) if boot_info.translation != TranslationConfig::expected() => {
+            println!(
+                "Bootloader set up {}, expected {}",
+                boot_info.translation,
+                TranslationConfig::expected()
+            );
+            cpu::wait_forever()
+        }
+        Some(_) => {}
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Equivalent to `crt0` or `c0` code in C/C++ world. Clears the `bss` section, then jumps to kernel
-/// init code.
+/// Equivalent to `crt0` or `c0` code in C/C++ world. Validates the handover from the bootloader,
+/// clears the `bss` section, then jumps to kernel init code.
+///
+/// From here on the kernel only relies on the TTBR1 half, so the bootloader's identity map is torn
+/// down.
 ///
 /// # Safety
 ///
 /// - Only a single core must be active and running this function.
-pub unsafe fn runtime_init() -> ! {
-    extern "Rust" {
-        fn kernel_init() -> !;
+#[no_mangle]
+pub unsafe extern "C" fn runtime_init(boot_info: *const BootInfo) -> ! {
+    extern "C" {
+        fn kernel_init(boot_info: &BootInfo) -> !;
     }
-    
+
+    check_handover(boot_info);
+    console::set_uart_base(config::UART0_VBASE);
+    mmu::mmu::identity_unmap_low_half();
     zero_bss();
-    kernel_init()
+    kernel_init(&*boot_info)
+}
+
+/// Continue a kernel started without the bootloader, entered from the direct boot stub in
+/// `cpu::boot` on the kernel stack, see `BootInfo::from_direct_boot` for the arguments.
+///
+/// # Safety
+///
+/// - Only a single core must be active and running this function.
+#[no_mangle]
+unsafe extern "C" fn direct_boot_init(handover: usize, used_end: usize) -> ! {
+    let boot_info = BootInfo::from_direct_boot(handover, used_end);
+    runtime_init(&boot_info)
 }
@@ -0,0 +1,139 @@
+//! Cooperative round-robin scheduler: a task runs until it calls `yield_now`.
+//!
+//! Preemption is left for later, on top of the timer interrupt.
+
+use crate::{
+    bsp::config::MmuGranule,
+    cpu::{
+        self,
+        context::{self, Context},
+        smp::SingleCoreCell,
+    },
+};
+
+/// Number of tasks, including the boot thread.
+const MAX_TASKS: usize = 4;
+
+/// Stack size of a spawned task.
+const STACK_SIZE: usize = 4 * MmuGranule::SIZE;
+
+#[derive(Copy, Clone)]
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+/// A thread of execution and the state it was switched out with.
+#[derive(Copy, Clone)]
+struct Task {
+    context: Context,
+}
+
+struct RunQueue {
+    /// Slot 0 is the boot thread running `kernel_init`, it keeps the stack it was started on.
+    tasks: [Option<Task>; MAX_TASKS],
+    current: usize,
+}
+
+static RUN_QUEUE: SingleCoreCell<RunQueue> = SingleCoreCell::new(RunQueue {
+    tasks: [
+        Some(Task {
+            context: Context::zeroed(),
+        }),
+        None,
+        None,
+        None,
+    ],
+    current: 0,
+});
+
+/// Stacks of the spawned tasks, the one of task `n` is at `n - 1`.
+static STACKS: SingleCoreCell<[Stack; MAX_TASKS - 1]> =
+    SingleCoreCell::new([Stack([0; STACK_SIZE]); MAX_TASKS - 1]);
+
+/// Add a task that starts running `entry` on its own stack at one of the next `yield_now` calls.
+///
+/// Returns the id of the task. Tasks never exit, so their slots are never reused.
+pub fn spawn(entry: extern "C" fn() -> !) -> Result<usize, &'static str> {
+    RUN_QUEUE.with(|queue| {
+        let (id, slot) = queue
+            .tasks
+            .iter_mut()
+            .enumerate()
+            .find(|(_, task)| task.is_none())
+            .ok_or("Run queue full")?;
+        let stack_end = STACKS.with(|stacks| stacks[id - 1].0.as_ptr_range().end as usize);
+        *slot = Some(Task {
+            context: Context::new(entry, stack_end),
+        });
+        Ok(id)
+    })
+}
+
+/// Id of the running task, 0 for the boot thread.
+pub fn current() -> usize {
+    RUN_QUEUE.with(|queue| queue.current)
+}
+
+/// Switch to the next task in the run queue. Returns immediately if there is no other task.
+pub fn yield_now() {
+    cpu::check_halt();
+    #[cfg(feature = "watchdog")]
+    crate::watchdog::kick();
+
+    let switch = RUN_QUEUE.with(|queue| {
+        let from = queue.current;
+        let to = (1..MAX_TASKS)
+            .map(|offset| (from + offset) % MAX_TASKS)
+            .find(|&id| queue.tasks[id].is_some())?;
+        queue.current = to;
+
+        let from_context: *mut Context = &mut queue.tasks[from].as_mut()?.context;
+        let to_context: *const Context = &queue.tasks[to].as_ref()?.context;
+        Some((from_context, to_context))
+    });
+
+    // The queue must not be borrowed while another task runs.
+    if let Some((from, to)) = switch {
+        unsafe { context::switch(from, to) }
+    }
+}
+
+/// Let two tasks take turns with the boot thread a few times and check the order they ran in.
+#[cfg(feature = "sched_selftest")]
+pub fn self_test() -> Result<(), &'static str> {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    const ROUNDS: usize = 3;
+
+    /// One entry per task run, the task id in the lowest bits of `TRACE` in run order.
+    static TRACE: AtomicUsize = AtomicUsize::new(0);
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    fn record() {
+        let run = RUNS.fetch_add(1, Ordering::SeqCst);
+        TRACE.fetch_or(current() << (run * 2), Ordering::SeqCst);
+    }
+
+    extern "C" fn ping_pong() -> ! {
+        loop {
+            if RUNS.load(Ordering::SeqCst) < 2 * ROUNDS {
+                println!("Task {} running", current());
+                record();
+            }
+            yield_now()
+        }
+    }
+
+    let ping = spawn(ping_pong)?;
+    let pong = spawn(ping_pong)?;
+    for _ in 0..ROUNDS {
+        yield_now();
+    }
+
+    let expected = (0..ROUNDS).fold(0, |trace, round| {
+        trace | (ping << (round * 4)) | (pong << (round * 4 + 2))
+    });
+    if RUNS.load(Ordering::SeqCst) != 2 * ROUNDS || TRACE.load(Ordering::SeqCst) != expected {
+        return Err("Tasks did not take turns");
+    }
+    Ok(())
+}
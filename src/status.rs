@@ -0,0 +1,47 @@
+//! One-shot snapshot of the kernel state over the console, for debugging.
+
+use crate::{
+    boot::BootInfo,
+    bsp::config::MmuGranule,
+    cpu,
+    memory::memory_map::MemoryMap,
+    mmu::mmu::{self, TranslationConfig},
+};
+use cortex_a::regs::*;
+
+/// Print the exception level, the MMU and cache enables, the translation table bases, the
+/// exception vector base and the physical memory handed over in `boot_info`.
+pub fn dump(boot_info: &BootInfo, memory_map: &MemoryMap) {
+    println!("Status:");
+    dump_registers();
+
+    println!("\tRAM: {}", boot_info.ram);
+    println!("\tUsed by the bootloader: {}", boot_info.used_pages);
+    for range in memory_map.iter_free() {
+        println!("\tFree: {}", range);
+    }
+    let total = memory_map.total_ram() / MmuGranule::SIZE;
+    let free = memory_map.free_ram() / MmuGranule::SIZE;
+    println!(
+        "\tFrames: {} free, {} used, {} total",
+        free,
+        total - free,
+        total
+    );
+}
+
+/// Print the system register part of `dump`.
+pub fn dump_registers() {
+    println!("\tEL{}", cpu::current_el());
+    println!(
+        "\tSCTLR_EL1: MMU {}, D-cache {}, I-cache {}",
+        SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable),
+        SCTLR_EL1.matches_all(SCTLR_EL1::C::Cacheable),
+        SCTLR_EL1.matches_all(SCTLR_EL1::I::Cacheable)
+    );
+    println!("\tTTBR0_EL1: {:#x}", TTBR0_EL1.get_baddr());
+    println!("\tTTBR1_EL1: {:#x}", TTBR1_EL1.get_baddr());
+    println!("\t{}", TranslationConfig::current());
+    println!("\tAccess flag: {:?}", mmu::access_flag_mode());
+    println!("\tVBAR_EL1: {:#x}", VBAR_EL1.get());
+}
@@ -0,0 +1,61 @@
+//! Software watchdog on the EL1 physical timer: unless `kick` is called within the timeout, the
+//! timer interrupt resets the machine.
+
+use crate::{
+    bsp::{config, console, gic},
+    cpu::{self, timer},
+};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// The timeout in counter ticks, 0 while disarmed.
+static TIMEOUT_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Start the watchdog. From now on `kick` must be called at least once per `timeout`.
+///
+/// # Safety
+///
+/// - The exception vectors must be installed, IRQs are unmasked on the executing core.
+pub unsafe fn arm(timeout: Duration) {
+    let ticks = timer::ticks(timeout).max(1);
+    TIMEOUT_TICKS.store(ticks, Ordering::SeqCst);
+    gic::init();
+    gic::enable(config::TIMER_IRQ);
+    timer::set_deadline(ticks);
+    cpu::enable_irq();
+}
+
+/// Push the reset one full timeout into the future.
+///
+/// Called by `sched::yield_now` and while `console::read_line` waits for input.
+pub fn kick() {
+    let ticks = TIMEOUT_TICKS.load(Ordering::SeqCst);
+    if ticks != 0 {
+        timer::set_deadline(ticks);
+    }
+}
+
+/// Stop the watchdog.
+pub fn disarm() {
+    TIMEOUT_TICKS.store(0, Ordering::SeqCst);
+    timer::disable();
+    gic::disable(config::TIMER_IRQ);
+}
+
+/// Handle interrupt `intid`. Returns `false` if it is not the watchdog's.
+pub fn handle_irq(intid: u32) -> bool {
+    if intid != config::TIMER_IRQ {
+        return false;
+    }
+    if TIMEOUT_TICKS.load(Ordering::SeqCst) == 0 {
+        // Raised just before `disarm`.
+        timer::disable();
+        return true;
+    }
+
+    println!("Watchdog expired, resetting");
+    console::flush();
+    cpu::reset()
+}
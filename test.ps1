@@ -0,0 +1,2 @@
+$hostTriple = (rustc -vV | Select-String "^host: (.*)").Matches[0].Groups[1].Value
+cargo test --lib --target $hostTriple @args
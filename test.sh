@@ -0,0 +1,5 @@
+#!/bin/sh
+# Run the unit tests on the host, against `arch::mock`. The default target in `.cargo/config.toml`
+# is the kernel's, so the host triple is passed explicitly.
+HOST=$(rustc -vV | sed -n 's/^host: //p')
+cargo test --lib --target "$HOST" "$@"